@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS users (
+    id SERIAL PRIMARY KEY,
+    name CHARACTER VARYING(100) NOT NULL,
+    email CHARACTER VARYING(100) NOT NULL,
+    api_key TEXT,
+    permission SMALLINT DEFAULT 0 NOT NULL,
+    password_hash TEXT DEFAULT '' NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS api_usage (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    api_key CHARACTER VARYING NOT NULL,
+    request_path CHARACTER VARYING NOT NULL,
+    request_method CHARACTER VARYING NOT NULL,
+    request_time TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    request_ip CHARACTER VARYING NOT NULL,
+    status_code INTEGER NOT NULL
+);
@@ -0,0 +1,11 @@
+-- API keys are stored as a keyed hash plus a short visible prefix used for lookup.
+-- Plaintext keys left in `users.api_key` are hashed and cleared at startup, since the
+-- hash key is only known to the application.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS api_key_prefix CHARACTER VARYING(8);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS api_key_hash TEXT;
+
+CREATE INDEX IF NOT EXISTS users_api_key_prefix_idx ON users (api_key_prefix);
+
+-- The usage log only keeps the prefix of the key that was used
+ALTER TABLE api_usage RENAME COLUMN api_key TO api_key_prefix;
+UPDATE api_usage SET api_key_prefix = left(api_key_prefix, 8);
@@ -0,0 +1,31 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name CHARACTER VARYING(64) NOT NULL,
+    key_prefix CHARACTER VARYING(8) NOT NULL,
+    key_hash TEXT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    last_used_at TIMESTAMP WITHOUT TIME ZONE,
+    revoked_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS api_keys_key_prefix_idx ON api_keys (key_prefix);
+CREATE INDEX IF NOT EXISTS api_keys_user_id_idx ON api_keys (user_id);
+-- A user cannot have two active keys with the same name
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_user_id_name_active_idx ON api_keys (user_id, name) WHERE revoked_at IS NULL;
+
+-- The single key of each user becomes its "default" key
+INSERT INTO api_keys (user_id, name, key_prefix, key_hash)
+SELECT id, 'default', api_key_prefix, api_key_hash FROM users WHERE api_key_hash IS NOT NULL;
+
+ALTER TABLE users DROP COLUMN IF EXISTS api_key_prefix;
+ALTER TABLE users DROP COLUMN IF EXISTS api_key_hash;
+
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS api_key_id INTEGER;
+
+UPDATE api_usage
+SET api_key_id = api_keys.id
+FROM api_keys
+WHERE api_keys.user_id = api_usage.user_id AND api_keys.key_prefix = api_usage.api_key_prefix;
+
+CREATE INDEX IF NOT EXISTS api_usage_user_id_api_key_id_idx ON api_usage (user_id, api_key_id);
@@ -0,0 +1,2 @@
+-- Existing keys keep access to every route
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{*}';
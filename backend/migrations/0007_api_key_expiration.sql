@@ -0,0 +1,11 @@
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP WITHOUT TIME ZONE;
+-- Set when a key is replaced by a rotation, the key then stays valid until `expires_at`
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS rotated_at TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS expiry_notified_at TIMESTAMP WITHOUT TIME ZONE;
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS disabled_reason CHARACTER VARYING(32);
+
+-- A key in its rotation grace period shares its name with the key that replaced it
+DROP INDEX IF EXISTS api_keys_user_id_name_active_idx;
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_user_id_name_current_idx ON api_keys (user_id, name) WHERE revoked_at IS NULL AND rotated_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS api_keys_expires_at_idx ON api_keys (expires_at) WHERE revoked_at IS NULL;
@@ -0,0 +1,4 @@
+-- Signed requests are accepted for the key
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS signing_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+-- Plain `x-api-key` requests are rejected for the key
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS require_signature BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,18 @@
+-- Client certificates allowed to call the API on behalf of a user, matched by fingerprint or subject
+CREATE TABLE IF NOT EXISTS client_certificates (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fingerprint CHARACTER VARYING(64) UNIQUE,
+    subject TEXT,
+    scopes TEXT[] NOT NULL DEFAULT '{*}',
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    last_used_at TIMESTAMP WITHOUT TIME ZONE,
+    revoked_at TIMESTAMP WITHOUT TIME ZONE,
+    CHECK (fingerprint IS NOT NULL OR subject IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS client_certificates_subject_idx ON client_certificates (subject);
+
+-- Requests authenticated with a certificate have no API key
+ALTER TABLE api_usage ALTER COLUMN api_key_prefix DROP NOT NULL;
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS client_certificate_id INTEGER;
@@ -0,0 +1,64 @@
+-- Roles replace the 0/1 `users.permission` flag, ranked from least to most privileged
+CREATE TABLE IF NOT EXISTS roles (
+    name CHARACTER VARYING(32) PRIMARY KEY,
+    rank SMALLINT NOT NULL UNIQUE,
+    description TEXT NOT NULL
+);
+
+INSERT INTO roles (name, rank, description) VALUES
+    ('viewer', 0, 'Read-only access to their own dashboard'),
+    ('developer', 1, 'Manages their own API keys'),
+    ('support', 2, 'Assists users: views accounts, revokes keys and unlocks logins'),
+    ('admin', 3, 'Manages users, keys and certificates'),
+    ('superadmin', 4, 'Full access, including granting the superadmin role')
+ON CONFLICT (name) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS permissions (
+    name CHARACTER VARYING(64) PRIMARY KEY,
+    description TEXT NOT NULL
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('admin:access', 'Open the admin panel'),
+    ('users:read', 'List and view users'),
+    ('users:create', 'Create users'),
+    ('users:delete', 'Delete users'),
+    ('users:manage_roles', 'Change the role of users'),
+    ('api_keys:manage_own', 'Create, rotate, restrict and revoke their own API keys'),
+    ('api_keys:create', 'Issue API keys for other users'),
+    ('api_keys:revoke', 'Revoke API keys of other users'),
+    ('logins:unlock', 'Clear login lockouts'),
+    ('certificates:manage', 'Register and revoke client certificates')
+ON CONFLICT (name) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS role_permissions (
+    role CHARACTER VARYING(32) NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+    permission CHARACTER VARYING(64) NOT NULL REFERENCES permissions(name) ON DELETE CASCADE,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('developer', 'api_keys:manage_own'),
+    ('support', 'api_keys:manage_own'),
+    ('support', 'admin:access'),
+    ('support', 'users:read'),
+    ('support', 'api_keys:revoke'),
+    ('support', 'logins:unlock')
+ON CONFLICT DO NOTHING;
+
+INSERT INTO role_permissions (role, permission)
+SELECT role, permission.name
+FROM (VALUES ('admin'), ('superadmin')) AS roles(role)
+CROSS JOIN permissions AS permission
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role CHARACTER VARYING(32) NOT NULL DEFAULT 'developer' REFERENCES roles(name);
+
+-- Existing administrators keep full access, the flag is already gone when the migration runs again
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'users' AND column_name = 'permission') THEN
+        UPDATE users SET role = 'superadmin' WHERE permission = 1;
+    END IF;
+END $$;
+ALTER TABLE users DROP COLUMN IF EXISTS permission;
@@ -0,0 +1,49 @@
+-- Organizations share API keys and a request quota between their members
+CREATE TABLE IF NOT EXISTS organizations (
+    id SERIAL PRIMARY KEY,
+    name CHARACTER VARYING(100) NOT NULL,
+    -- Requests per calendar month (UTC) across every key of the organization, unlimited when NULL
+    monthly_request_quota BIGINT,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS organization_members (
+    organization_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role CHARACTER VARYING(16) NOT NULL CHECK (role IN ('owner', 'admin', 'member')),
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    PRIMARY KEY (organization_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS organization_members_user_id_idx ON organization_members (user_id);
+
+CREATE TABLE IF NOT EXISTS organization_invitations (
+    id SERIAL PRIMARY KEY,
+    organization_id INTEGER NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+    email CHARACTER VARYING(100) NOT NULL,
+    role CHARACTER VARYING(16) NOT NULL CHECK (role IN ('admin', 'member')),
+    token_hash TEXT NOT NULL UNIQUE,
+    invited_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    accepted_at TIMESTAMP WITHOUT TIME ZONE,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
+
+-- Organization keys are still attached to the member who created them, names are unique per organization
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS organization_id INTEGER REFERENCES organizations(id) ON DELETE CASCADE;
+
+DROP INDEX IF EXISTS api_keys_user_id_name_current_idx;
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_user_id_name_current_idx ON api_keys (user_id, name) WHERE revoked_at IS NULL AND rotated_at IS NULL AND organization_id IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS api_keys_organization_id_name_current_idx ON api_keys (organization_id, name) WHERE revoked_at IS NULL AND rotated_at IS NULL AND organization_id IS NOT NULL;
+
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS organization_id INTEGER;
+CREATE INDEX IF NOT EXISTS api_usage_organization_id_request_time_idx ON api_usage (organization_id, request_time) WHERE organization_id IS NOT NULL;
+
+INSERT INTO permissions (name, description) VALUES
+    ('organizations:manage', 'View every organization and set their quotas')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'organizations:manage'),
+    ('superadmin', 'organizations:manage')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,39 @@
+-- Tenants isolate users, API routes, rate limits and usage of each product line
+CREATE TABLE IF NOT EXISTS tenants (
+    id SERIAL PRIMARY KEY,
+    slug CHARACTER VARYING(64) NOT NULL UNIQUE,
+    name CHARACTER VARYING(100) NOT NULL,
+    -- Requests for this host are resolved to the tenant, `/t/{slug}/...` paths work for every tenant
+    hostname CHARACTER VARYING(255) UNIQUE,
+    -- Override the default rate limit of the API when both are set
+    rate_limit_max_requests INTEGER,
+    rate_limit_window_secs INTEGER,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
+
+-- `<METHOD> <path glob>` patterns of the API routes a tenant can call, every route when a tenant has none
+CREATE TABLE IF NOT EXISTS tenant_routes (
+    tenant_id INTEGER NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    route_pattern TEXT NOT NULL,
+    PRIMARY KEY (tenant_id, route_pattern)
+);
+
+INSERT INTO tenants (slug, name) VALUES ('default', 'Default') ON CONFLICT (slug) DO NOTHING;
+
+-- Existing users and usage belong to the default tenant
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tenant_id INTEGER REFERENCES tenants(id);
+UPDATE users SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default') WHERE tenant_id IS NULL;
+ALTER TABLE users ALTER COLUMN tenant_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS users_tenant_id_email_idx ON users (tenant_id, email);
+
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS tenant_id INTEGER;
+UPDATE api_usage SET tenant_id = (SELECT id FROM tenants WHERE slug = 'default') WHERE tenant_id IS NULL;
+CREATE INDEX IF NOT EXISTS api_usage_tenant_id_request_time_idx ON api_usage (tenant_id, request_time);
+
+INSERT INTO permissions (name, description) VALUES
+    ('tenants:manage', 'Manage tenants and view data across every tenant')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('superadmin', 'tenants:manage')
+ON CONFLICT DO NOTHING;
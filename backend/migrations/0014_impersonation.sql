@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS impersonation_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    admin_user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    target_user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reason TEXT,
+    started_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    expires_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    ended_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS impersonation_sessions_admin_user_id_idx ON impersonation_sessions (admin_user_id);
+CREATE INDEX IF NOT EXISTS impersonation_sessions_target_user_id_idx ON impersonation_sessions (target_user_id);
+
+INSERT INTO permissions (name, description) VALUES
+    ('users:impersonate', 'Sign in to the dashboard as another user')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('support', 'users:impersonate'),
+    ('admin', 'users:impersonate'),
+    ('superadmin', 'users:impersonate')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,40 @@
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS before JSONB;
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS after JSONB;
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS tenant_id INTEGER;
+
+UPDATE audit_log SET tenant_id = COALESCE(
+    (SELECT tenant_id FROM users WHERE users.id = audit_log.target_user_id),
+    (SELECT tenant_id FROM users WHERE users.id = audit_log.actor_user_id)
+) WHERE tenant_id IS NULL;
+
+CREATE INDEX IF NOT EXISTS audit_log_actor_user_id_idx ON audit_log (actor_user_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_log_target_user_id_idx ON audit_log (target_user_id, created_at);
+CREATE INDEX IF NOT EXISTS audit_log_action_idx ON audit_log (action, created_at);
+CREATE INDEX IF NOT EXISTS audit_log_tenant_id_idx ON audit_log (tenant_id, created_at);
+
+-- Entries can only be added, never changed or removed
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_no_update_or_delete ON audit_log;
+CREATE TRIGGER audit_log_no_update_or_delete
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+DROP TRIGGER IF EXISTS audit_log_no_truncate ON audit_log;
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
+
+INSERT INTO permissions (name, description) VALUES
+    ('audit_log:read', 'Search the audit log')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('support', 'audit_log:read'),
+    ('admin', 'audit_log:read'),
+    ('superadmin', 'audit_log:read')
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS plans (
+    id SERIAL PRIMARY KEY,
+    name CHARACTER VARYING(64) NOT NULL UNIQUE,
+    rate_limit_max_requests INTEGER NOT NULL CHECK (rate_limit_max_requests >= 0),
+    rate_limit_window_secs INTEGER NOT NULL CHECK (rate_limit_window_secs > 0),
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL
+);
+
+-- Requests of an organization key are limited by the plan of the organization, other requests by the plan of the user
+ALTER TABLE users ADD COLUMN IF NOT EXISTS plan_id INTEGER REFERENCES plans (id) ON DELETE SET NULL;
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS plan_id INTEGER REFERENCES plans (id) ON DELETE SET NULL;
@@ -0,0 +1,3 @@
+-- Uses the algorithm configured with RATE_LIMIT_ALGORITHM when NULL
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS rate_limit_algorithm CHARACTER VARYING(32)
+    CHECK (rate_limit_algorithm IN ('fixed_window', 'sliding_window_log', 'sliding_window_counter', 'token_bucket'));
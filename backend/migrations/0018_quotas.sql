@@ -0,0 +1,25 @@
+-- Quotas over calendar days and months (UTC), counted per organization, or per key for personal keys
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS daily_request_quota BIGINT CHECK (daily_request_quota >= 0);
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS monthly_request_quota BIGINT CHECK (monthly_request_quota >= 0);
+-- What happens once a quota is exhausted: reject, accept and bill as overage, or apply the degraded rate limit
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS quota_action CHARACTER VARYING(16) NOT NULL DEFAULT 'block'
+    CHECK (quota_action IN ('block', 'overage', 'degrade'));
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS degraded_max_requests INTEGER CHECK (degraded_max_requests >= 0);
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS degraded_window_secs INTEGER CHECK (degraded_window_secs > 0);
+ALTER TABLE plans ADD CONSTRAINT plans_degraded_rate_limit_check
+    CHECK (quota_action <> 'degrade' OR (degraded_max_requests IS NOT NULL AND degraded_window_secs IS NOT NULL));
+
+-- Counters live in Redis and are checkpointed here, so they survive a restart of Redis
+CREATE TABLE IF NOT EXISTS quota_usage (
+    -- 'org:<id>', 'key:<id>' or 'user:<id>'
+    subject CHARACTER VARYING(32) NOT NULL,
+    -- '2026-01-31' for a day, '2026-01' for a month
+    period CHARACTER VARYING(16) NOT NULL,
+    used BIGINT NOT NULL DEFAULT 0,
+    overage BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    PRIMARY KEY (subject, period)
+);
+
+-- Requests accepted beyond the quota of a plan billed as overage
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS overage BOOLEAN NOT NULL DEFAULT false;
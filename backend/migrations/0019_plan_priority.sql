@@ -0,0 +1,2 @@
+-- Under load, requests of lower priority plans are shed first, requests without a plan have priority 0
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS priority SMALLINT NOT NULL DEFAULT 0 CHECK (priority BETWEEN 0 AND 10);
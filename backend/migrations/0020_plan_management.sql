@@ -0,0 +1,26 @@
+-- `<METHOD> <path glob>` patterns such as `GET /api/v1/*` the plan gives access to, every route when empty
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS allowed_routes TEXT[] NOT NULL DEFAULT '{}';
+-- `Cache-Control: private, max-age=<n>` on successful GET responses, `no-store` for 0, untouched when NULL
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS cache_max_age_secs INTEGER CHECK (cache_max_age_secs >= 0);
+-- Applies to users and organizations without a plan of their own
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS is_default BOOLEAN NOT NULL DEFAULT false;
+CREATE UNIQUE INDEX IF NOT EXISTS plans_is_default_idx ON plans (is_default) WHERE is_default;
+
+INSERT INTO plans (name, rate_limit_max_requests, rate_limit_window_secs, daily_request_quota, monthly_request_quota,
+    quota_action, priority, allowed_routes, cache_max_age_secs) VALUES
+    -- Existing callers fall under the default plan, so it keeps every route and leaves caching to the handlers
+    ('free', 5, 60, 1000, 10000, 'block', 0, '{}', NULL),
+    ('pro', 100, 60, 50000, 1000000, 'overage', 5, '{}', 60),
+    ('enterprise', 1000, 60, NULL, NULL, 'block', 10, '{}', NULL)
+ON CONFLICT (name) DO NOTHING;
+
+UPDATE plans SET is_default = true WHERE name = 'free' AND NOT EXISTS (SELECT 1 FROM plans WHERE is_default);
+
+INSERT INTO permissions (name, description) VALUES
+    ('plans:manage', 'Manage plans and assign them to users and organizations')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'plans:manage'),
+    ('superadmin', 'plans:manage')
+ON CONFLICT DO NOTHING;
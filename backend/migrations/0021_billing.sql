@@ -0,0 +1,65 @@
+-- Prices of a plan per calendar month (UTC), in cents of its currency
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS currency CHARACTER(3) NOT NULL DEFAULT 'USD';
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS base_price_cents BIGINT NOT NULL DEFAULT 0 CHECK (base_price_cents >= 0);
+-- Request units covered by the base price, units beyond are billed as overage.
+-- When NULL, only the units of requests accepted past a quota with the `overage` action are billed.
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS included_units BIGINT CHECK (included_units >= 0);
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS overage_price_per_1000_units_cents BIGINT NOT NULL DEFAULT 0
+    CHECK (overage_price_per_1000_units_cents >= 0);
+-- Request and response bodies, per 10^9 bytes
+ALTER TABLE plans ADD COLUMN IF NOT EXISTS price_per_gb_cents BIGINT NOT NULL DEFAULT 0 CHECK (price_per_gb_cents >= 0);
+
+UPDATE plans SET base_price_cents = 4900, included_units = 1000000, overage_price_per_1000_units_cents = 50, price_per_gb_cents = 10
+WHERE name = 'pro' AND base_price_cents = 0;
+UPDATE plans SET base_price_cents = 49900, included_units = 20000000, overage_price_per_1000_units_cents = 20, price_per_gb_cents = 5
+WHERE name = 'enterprise' AND base_price_cents = 0;
+
+-- Units a request is billed for, by `<METHOD> <path glob>` pattern, 1 for routes without a pattern
+CREATE TABLE IF NOT EXISTS route_costs (
+    route_pattern CHARACTER VARYING(255) PRIMARY KEY,
+    cost INTEGER NOT NULL CHECK (cost >= 0)
+);
+
+INSERT INTO route_costs (route_pattern, cost) VALUES
+    ('POST /api/graphql', 5)
+ON CONFLICT (route_pattern) DO NOTHING;
+
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS cost INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS request_bytes BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE api_usage ADD COLUMN IF NOT EXISTS response_bytes BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS api_usage_request_time_idx ON api_usage (request_time);
+
+-- One invoice per organization or user and month, with the plan and prices at the time it was generated
+CREATE TABLE IF NOT EXISTS invoices (
+    id SERIAL PRIMARY KEY,
+    -- 'org:<id>' or 'user:<id>'
+    subject CHARACTER VARYING(32) NOT NULL,
+    organization_id INTEGER REFERENCES organizations (id) ON DELETE SET NULL,
+    user_id INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    -- First day of the month, and of the next one
+    period_start DATE NOT NULL,
+    period_end DATE NOT NULL,
+    plan_id INTEGER REFERENCES plans (id) ON DELETE SET NULL,
+    plan_name CHARACTER VARYING(64) NOT NULL,
+    currency CHARACTER(3) NOT NULL,
+    requests BIGINT NOT NULL,
+    units BIGINT NOT NULL,
+    overage_units BIGINT NOT NULL,
+    request_bytes BIGINT NOT NULL,
+    response_bytes BIGINT NOT NULL,
+    base_cents BIGINT NOT NULL,
+    overage_cents BIGINT NOT NULL,
+    transfer_cents BIGINT NOT NULL,
+    total_cents BIGINT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE DEFAULT now() NOT NULL,
+    UNIQUE (subject, period_start)
+);
+
+-- Invoices cover every tenant
+INSERT INTO permissions (name, description) VALUES
+    ('billing:manage', 'Generate and export invoices, and set the cost of routes')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('superadmin', 'billing:manage')
+ON CONFLICT DO NOTHING;
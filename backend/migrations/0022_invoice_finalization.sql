@@ -0,0 +1,2 @@
+-- Finalized invoices are issued and never change, regenerating a period only replaces its drafts
+ALTER TABLE invoices ADD COLUMN IF NOT EXISTS finalized_at TIMESTAMP WITHOUT TIME ZONE;
@@ -0,0 +1,19 @@
+-- Organizations belong to the tenant of their creator, tenant admins only manage their own
+ALTER TABLE organizations ADD COLUMN IF NOT EXISTS tenant_id INTEGER REFERENCES tenants(id);
+
+UPDATE organizations
+SET tenant_id = COALESCE(
+    (
+        SELECT users.tenant_id
+        FROM organization_members
+        JOIN users ON users.id = organization_members.user_id
+        WHERE organization_members.organization_id = organizations.id AND organization_members.role = 'owner'
+        ORDER BY organization_members.created_at
+        LIMIT 1
+    ),
+    (SELECT id FROM tenants WHERE slug = 'default')
+)
+WHERE tenant_id IS NULL;
+
+ALTER TABLE organizations ALTER COLUMN tenant_id SET NOT NULL;
+CREATE INDEX IF NOT EXISTS organizations_tenant_id_idx ON organizations (tenant_id);
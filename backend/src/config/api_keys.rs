@@ -0,0 +1,23 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct ApiKeyConfig {
+    // How long a rotated key keeps working when the rotation does not specify it
+    pub rotation_grace_period_secs: u64,
+    // Longest grace period a rotation can ask for
+    pub max_rotation_grace_period_secs: u64,
+    pub expiry_check_interval_secs: u64,
+    // Owners are warned this long before one of their keys expires
+    pub expiry_warning_secs: u64,
+}
+
+impl ApiKeyConfig {
+    pub fn from_env() -> Self {
+        Self {
+            rotation_grace_period_secs: env_or("API_KEY_ROTATION_GRACE_PERIOD_SECS", 24 * 60 * 60),
+            max_rotation_grace_period_secs: env_or("API_KEY_MAX_ROTATION_GRACE_PERIOD_SECS", 7 * 24 * 60 * 60),
+            expiry_check_interval_secs: env_or("API_KEY_EXPIRY_CHECK_INTERVAL_SECS", 5 * 60),
+            expiry_warning_secs: env_or("API_KEY_EXPIRY_WARNING_SECS", 7 * 24 * 60 * 60),
+        }
+    }
+}
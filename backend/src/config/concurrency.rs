@@ -0,0 +1,26 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct ConcurrencyConfig {
+    // In-flight requests allowed per API key, or client certificate, 0 for unlimited
+    pub per_key: u32,
+    // In-flight requests allowed per upstream across every caller, 0 for unlimited
+    pub per_upstream: u32,
+    // Requests waiting for a slot per key and per upstream, 0 rejects requests as soon as the limit is reached
+    pub queue_size: u32,
+    pub queue_timeout_ms: u64,
+    // Slots held by an instance that died are freed after this delay, it must exceed the longest request
+    pub lease_secs: u64,
+}
+
+impl ConcurrencyConfig {
+    pub fn from_env() -> Self {
+        Self {
+            per_key: env_or("CONCURRENCY_PER_KEY", 10),
+            per_upstream: env_or("CONCURRENCY_PER_UPSTREAM", 0),
+            queue_size: env_or("CONCURRENCY_QUEUE_SIZE", 0),
+            queue_timeout_ms: env_or("CONCURRENCY_QUEUE_TIMEOUT_MS", 5000),
+            lease_secs: env_or("CONCURRENCY_LEASE_SECS", 60),
+        }
+    }
+}
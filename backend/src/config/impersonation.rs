@@ -0,0 +1,15 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct ImpersonationConfig {
+    // Lifetime of an impersonation token, it cannot be refreshed
+    pub ttl_secs: u64,
+}
+
+impl ImpersonationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            ttl_secs: env_or("IMPERSONATION_TTL_SECS", 15 * 60),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct LoadSheddingConfig {
+    pub enabled: bool,
+    // In-flight requests admitted per upstream and instance before any latency was measured
+    pub initial_limit: u32,
+    pub min_limit: u32,
+    pub max_limit: u32,
+    // Responses slower than this, or failing with a 5xx, shrink the limit
+    pub target_latency_ms: u64,
+    // The limit is multiplied by this on congestion, at most once per target latency
+    pub decrease_factor: f64,
+    // Share of the limit kept for the highest priority, requests of priority 0 are shed past the rest
+    pub reserved_fraction: f64,
+}
+
+impl LoadSheddingConfig {
+    pub fn from_env() -> Self {
+        Self {
+            enabled: env_or("LOAD_SHEDDING_ENABLED", true),
+            initial_limit: env_or("LOAD_SHEDDING_INITIAL_LIMIT", 100),
+            min_limit: env_or("LOAD_SHEDDING_MIN_LIMIT", 5),
+            max_limit: env_or("LOAD_SHEDDING_MAX_LIMIT", 1000),
+            target_latency_ms: env_or("LOAD_SHEDDING_TARGET_LATENCY_MS", 500),
+            decrease_factor: env_or("LOAD_SHEDDING_DECREASE_FACTOR", 0.9),
+            reserved_fraction: env_or("LOAD_SHEDDING_RESERVED_FRACTION", 0.5),
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct LoginGuardConfig {
+    // Failures are forgotten once this long has passed without a new one, each failure restarts the window
+    pub failure_window_secs: u64,
+    // Number of failures before each new attempt is delayed
+    pub delay_after_failures: u32,
+    pub base_delay_secs: u64,
+    pub max_delay_secs: u64,
+    // Number of failures before the account is temporarily locked
+    pub account_lockout_threshold: u32,
+    // Number of failures from a single IP, across all accounts, before the IP is locked
+    pub ip_lockout_threshold: u32,
+    pub lockout_duration_secs: u64,
+}
+
+impl LoginGuardConfig {
+    pub fn from_env() -> Self {
+        Self {
+            failure_window_secs: env_or("LOGIN_FAILURE_WINDOW_SECS", 15 * 60),
+            delay_after_failures: env_or("LOGIN_DELAY_AFTER_FAILURES", 3),
+            base_delay_secs: env_or("LOGIN_BASE_DELAY_SECS", 1),
+            max_delay_secs: env_or("LOGIN_MAX_DELAY_SECS", 60),
+            account_lockout_threshold: env_or("LOGIN_ACCOUNT_LOCKOUT_THRESHOLD", 10),
+            ip_lockout_threshold: env_or("LOGIN_IP_LOCKOUT_THRESHOLD", 50),
+            lockout_duration_secs: env_or("LOGIN_LOCKOUT_DURATION_SECS", 15 * 60),
+        }
+    }
+}
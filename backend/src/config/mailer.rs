@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::utils::mailer::{FileMailer, LogMailer, Mailer, SmtpMailer};
+
+pub fn create_mailer() -> Arc<dyn Mailer> {
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".to_string());
+
+    match transport.as_str() {
+        "smtp" => {
+            let host = std::env::var("SMTP_HOST").expect("SMTP_HOST must be set");
+            let port = std::env::var("SMTP_PORT")
+                .ok()
+                .and_then(|p| p.parse::<u16>().ok())
+                .unwrap_or(587);
+            let username = std::env::var("SMTP_USERNAME").unwrap_or_default();
+            let password = std::env::var("SMTP_PASSWORD").unwrap_or_default();
+            let from = std::env::var("MAIL_FROM").expect("MAIL_FROM must be set");
+
+            Arc::new(SmtpMailer::new(&host, port, username, password, &from).expect("Failed to create SMTP mailer"))
+        }
+        "file" => {
+            let path = std::env::var("MAIL_FILE_PATH").unwrap_or_else(|_| "mail.log".to_string());
+            Arc::new(FileMailer::new(PathBuf::from(path)))
+        }
+        "log" => Arc::new(LogMailer),
+        other => panic!("Unknown MAIL_TRANSPORT: {}", other),
+    }
+}
+
+// Base URL of the frontend, used to build the links sent by email
+pub fn app_base_url() -> String {
+    std::env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
@@ -1,2 +1,23 @@
 pub(crate) mod postgresql;
-pub(crate) mod redis;
\ No newline at end of file
+pub(crate) mod redis;
+pub(crate) mod mailer;
+pub(crate) mod login_guard;
+pub(crate) mod password_policy;
+pub(crate) mod api_keys;
+pub(crate) mod trusted_proxies;
+pub(crate) mod request_signing;
+pub(crate) mod tls;
+pub(crate) mod impersonation;
+pub(crate) mod rate_limit;
+pub(crate) mod quota;
+pub(crate) mod concurrency;
+pub(crate) mod load_shedding;
+pub(crate) mod request_cost;
+
+// Reads an optional setting from the environment, falling back to `default` when unset or invalid
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<T>().ok())
+        .unwrap_or(default)
+}
\ No newline at end of file
@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+use super::env_or;
+
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    // Rejects passwords containing the user's name or email
+    pub disallow_personal_info: bool,
+    // Directory of breached password hash files, one file per SHA-1 prefix
+    pub breached_passwords_dir: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 10),
+            max_length: env_or("PASSWORD_MAX_LENGTH", 128),
+            require_lowercase: env_or("PASSWORD_REQUIRE_LOWERCASE", true),
+            require_uppercase: env_or("PASSWORD_REQUIRE_UPPERCASE", true),
+            require_digit: env_or("PASSWORD_REQUIRE_DIGIT", true),
+            require_symbol: env_or("PASSWORD_REQUIRE_SYMBOL", false),
+            disallow_personal_info: env_or("PASSWORD_DISALLOW_PERSONAL_INFO", true),
+            breached_passwords_dir: std::env::var("BREACHED_PASSWORDS_DIR").ok().map(PathBuf::from),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use sqlx::postgres::PgPool;
 use sqlx::postgres::PgPoolOptions;
+use crate::utils::api_key::{hash_api_key, key_prefix};
 
 pub async fn create_db_pool(database_url: &str) -> PgPool {
     PgPoolOptions::new()
@@ -8,3 +9,50 @@ pub async fn create_db_pool(database_url: &str) -> PgPool {
         .await
         .expect("Failed to create pool")
 }
+
+pub async fn run_migrations(db_pool: &PgPool) {
+    sqlx::migrate!("./migrations")
+        .run(db_pool)
+        .await
+        .expect("Failed to run database migrations")
+}
+
+// Hashes the plaintext API keys stored before keys were hashed into default named keys, then clears them.
+// Their usage is linked to the new key here, the migrations ran before the key existed.
+pub async fn hash_legacy_api_keys(db_pool: &PgPool) {
+    let legacy_keys: Vec<(i32, String)> = sqlx::query_as("SELECT id, api_key FROM users WHERE api_key IS NOT NULL")
+        .fetch_all(db_pool)
+        .await
+        .expect("Failed to read legacy API keys");
+
+    for (id, api_key) in legacy_keys {
+        let mut tx = db_pool.begin().await.expect("Failed to start transaction");
+
+        let prefix = key_prefix(&api_key);
+        let (key_id,): (i32,) = sqlx::query_as(
+            "INSERT INTO api_keys (user_id, name, key_prefix, key_hash) VALUES ($1, 'default', $2, $3) RETURNING id",
+        )
+            .bind(id)
+            .bind(&prefix)
+            .bind(hash_api_key(&api_key))
+            .fetch_one(&mut *tx)
+            .await
+            .expect("Failed to hash legacy API key");
+
+        sqlx::query("UPDATE api_usage SET api_key_id = $1 WHERE user_id = $2 AND api_key_prefix = $3 AND api_key_id IS NULL")
+            .bind(key_id)
+            .bind(id)
+            .bind(&prefix)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to link legacy API key usage");
+
+        sqlx::query("UPDATE users SET api_key = NULL WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to clear legacy API key");
+
+        tx.commit().await.expect("Failed to hash legacy API key");
+    }
+}
@@ -0,0 +1,15 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct QuotaConfig {
+    // Counters changed in Redis are copied to Postgres this often, at most this much usage is lost with Redis
+    pub checkpoint_interval_secs: u64,
+}
+
+impl QuotaConfig {
+    pub fn from_env() -> Self {
+        Self {
+            checkpoint_interval_secs: env_or("QUOTA_CHECKPOINT_INTERVAL_SECS", 60),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::str::FromStr;
+use super::env_or;
+use crate::utils::rate_limit::Algorithm;
+use crate::utils::rate_limit_key::KeyStrategy;
+
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    // `api_key`, `user`, `org`, `ip`, `header:<name>`, or several joined with `+`
+    pub key_strategy: KeyStrategy,
+    // Used unless the plan of the caller picks another one
+    pub algorithm: Algorithm,
+    // What happens to rate limits, quotas and concurrency limits while Redis is unreachable
+    pub failure_mode: RedisFailureMode,
+    // Applies when neither the plan of the caller, its tenant nor a default plan sets a limit
+    pub max_requests: u32,
+    pub window_secs: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RedisFailureMode {
+    // Rate limits are counted in memory by each instance, quotas and concurrency limits are not enforced
+    Local,
+    // Requests are let through unchecked
+    Open,
+    // Requests are rejected with HTTP 503
+    Closed,
+}
+
+impl RedisFailureMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedisFailureMode::Local => "local",
+            RedisFailureMode::Open => "open",
+            RedisFailureMode::Closed => "closed",
+        }
+    }
+}
+
+impl FromStr for RedisFailureMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "local" => Ok(RedisFailureMode::Local),
+            "open" => Ok(RedisFailureMode::Open),
+            "closed" => Ok(RedisFailureMode::Closed),
+            value => Err(format!("Unknown Redis failure mode '{}'", value)),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            key_strategy: env_or("RATE_LIMIT_KEY", KeyStrategy::default()),
+            algorithm: env_or("RATE_LIMIT_ALGORITHM", Algorithm::SlidingWindowCounter),
+            failure_mode: env_or("RATE_LIMIT_FAILURE_MODE", RedisFailureMode::Local),
+            max_requests: env_or("RATE_LIMIT_MAX_REQUESTS", 5),
+            window_secs: env_or("RATE_LIMIT_WINDOW_SECS", 60),
+        }
+    }
+}
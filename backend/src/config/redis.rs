@@ -1,5 +1,149 @@
-use redis::Client;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::{AsyncConnectionConfig, Client, RedisFuture, RedisResult};
+use super::env_or;
 
-pub fn create_redis_client(redis_url: &str) -> Client {
-    Client::open(redis_url).expect("Failed to create Redis client")
+#[derive(Clone)]
+pub struct RedisConfig {
+    pub connect_timeout_ms: u64,
+    // Commands taking longer fail, so a stuck Redis does not hold requests
+    pub response_timeout_ms: u64,
+    // While Redis is unreachable, a new connection is attempted at most this often and other calls fail right away
+    pub reconnect_interval_ms: u64,
+}
+
+impl RedisConfig {
+    pub fn from_env() -> Self {
+        Self {
+            connect_timeout_ms: env_or("REDIS_CONNECT_TIMEOUT_MS", 1000),
+            response_timeout_ms: env_or("REDIS_RESPONSE_TIMEOUT_MS", 500),
+            reconnect_interval_ms: env_or("REDIS_RECONNECT_INTERVAL_MS", 1000),
+        }
+    }
+}
+
+pub fn create_redis_pool(redis_url: &str, config: RedisConfig) -> RedisPool {
+    let client = Client::open(redis_url).expect("Failed to create Redis client");
+    RedisPool {
+        inner: Arc::new(PoolInner { client, config, state: Mutex::new(PoolState::default()) }),
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    connection: Option<MultiplexedConnection>,
+    last_attempt: Option<Instant>,
+    // Set from the first failure until a connection succeeds again
+    unavailable_since: Option<Instant>,
+}
+
+// Only the start of an outage is logged, requests keep failing fast until it ends
+fn mark_unavailable(state: &mut PoolState, error: &redis::RedisError) {
+    if state.unavailable_since.is_none() {
+        eprintln!("Redis is unavailable: {}", error);
+        state.unavailable_since = Some(Instant::now());
+    }
+}
+
+struct PoolInner {
+    client: Client,
+    config: RedisConfig,
+    state: Mutex<PoolState>,
+}
+
+impl PoolInner {
+    // A broken connection is dropped, the next call connects again
+    fn observe<T>(&self, result: &RedisResult<T>) {
+        if let Err(e) = result {
+            if e.is_io_error() || e.is_unrecoverable_error() {
+                let mut state = self.state.lock().unwrap();
+                state.connection = None;
+                mark_unavailable(&mut state, e);
+            }
+        }
+    }
+}
+
+/// One multiplexed connection shared by every request, replaced when it breaks.
+#[derive(Clone)]
+pub struct RedisPool {
+    inner: Arc<PoolInner>,
+}
+
+impl RedisPool {
+    pub async fn connection(&self) -> RedisResult<RedisConnection> {
+        let config = &self.inner.config;
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if let Some(connection) = &state.connection {
+                return Ok(RedisConnection { connection: connection.clone(), pool: Arc::clone(&self.inner) });
+            }
+
+            let reconnect_interval = Duration::from_millis(config.reconnect_interval_ms);
+            if state.unavailable_since.is_some() && state.last_attempt.is_some_and(|at| at.elapsed() < reconnect_interval) {
+                return Err((redis::ErrorKind::IoError, "Redis is unavailable").into());
+            }
+            state.last_attempt = Some(Instant::now());
+        }
+
+        let connection_config = AsyncConnectionConfig::new()
+            .set_connection_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .set_response_timeout(Duration::from_millis(config.response_timeout_ms));
+        let result = self.inner.client.get_multiplexed_async_connection_with_config(&connection_config).await;
+
+        let mut state = self.inner.state.lock().unwrap();
+        match result {
+            Ok(connection) => {
+                if state.unavailable_since.take().is_some() {
+                    eprintln!("Redis is available again");
+                }
+                // Another request may have connected in the meantime, either connection works
+                let connection = state.connection.get_or_insert(connection).clone();
+                Ok(RedisConnection { connection, pool: Arc::clone(&self.inner) })
+            }
+            Err(e) => {
+                mark_unavailable(&mut state, &e);
+                Err(e)
+            }
+        }
+    }
+
+    /// How long Redis has been unreachable, `None` while it works.
+    pub fn unavailable_for(&self) -> Option<Duration> {
+        self.inner.state.lock().unwrap().unavailable_since.map(|since| since.elapsed())
+    }
+}
+
+pub struct RedisConnection {
+    connection: MultiplexedConnection,
+    pool: Arc<PoolInner>,
+}
+
+// Tells the pool about failed commands, so a dead connection is not handed out again
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, redis::Value> {
+        Box::pin(async move {
+            let result = self.connection.req_packed_command(cmd).await;
+            self.pool.observe(&result);
+            result
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<redis::Value>> {
+        Box::pin(async move {
+            let result = self.connection.req_packed_commands(cmd, offset, count).await;
+            self.pool.observe(&result);
+            result
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.connection.get_db()
+    }
 }
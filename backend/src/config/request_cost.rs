@@ -0,0 +1,15 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct RequestCostConfig {
+    // A GraphQL query costs one more unit per this many selected fields, 0 leaves only the cost of the route
+    pub graphql_fields_per_unit: u32,
+}
+
+impl RequestCostConfig {
+    pub fn from_env() -> Self {
+        Self {
+            graphql_fields_per_unit: env_or("GRAPHQL_FIELDS_PER_UNIT", 10),
+        }
+    }
+}
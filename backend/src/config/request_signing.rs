@@ -0,0 +1,15 @@
+use super::env_or;
+
+#[derive(Clone)]
+pub struct RequestSigningConfig {
+    // Maximum difference between the signed timestamp and the server clock
+    pub max_clock_skew_secs: u64,
+}
+
+impl RequestSigningConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_clock_skew_secs: env_or("SIGNATURE_MAX_CLOCK_SKEW_SECS", 5 * 60),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+// Plain HTTP is always served on this address
+pub const HTTP_BIND_ADDR: &str = "0.0.0.0:8080";
+
+/// Address HTTPS is served on, the plain HTTP one by default so clients and containers keep the same port.
+pub fn tls_bind_addr() -> String {
+    super::env_or("TLS_BIND_ADDR", HTTP_BIND_ADDR.to_string())
+}
+
+fn load_certificates(path: &str) -> Vec<CertificateDer<'static>> {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Failed to open certificate file {}: {}", path, e));
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| panic!("Failed to read certificates from {}: {}", path, e))
+}
+
+fn load_private_key(path: &str) -> PrivateKeyDer<'static> {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Failed to open private key file {}: {}", path, e));
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .unwrap_or_else(|e| panic!("Failed to read private key from {}: {}", path, e))
+        .unwrap_or_else(|| panic!("No private key found in {}", path))
+}
+
+/// Builds the TLS configuration from the environment, or returns `None` to serve plain HTTP.
+///
+/// When `TLS_CLIENT_CA_PATH` is set, client certificates signed by this CA bundle are verified and can
+/// be used to authenticate API calls. They are optional unless `TLS_CLIENT_AUTH_REQUIRED` is `true`.
+pub fn create_tls_config() -> Option<ServerConfig> {
+    let cert_path = std::env::var("TLS_CERT_PATH").ok()?;
+    let key_path = std::env::var("TLS_KEY_PATH").expect("TLS_KEY_PATH must be set when TLS_CERT_PATH is set");
+
+    let builder = ServerConfig::builder();
+    let builder = match std::env::var("TLS_CLIENT_CA_PATH") {
+        Ok(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(&ca_path) {
+                roots.add(certificate).expect("Invalid certificate in TLS_CLIENT_CA_PATH");
+            }
+
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if super::env_or("TLS_CLIENT_AUTH_REQUIRED", false) {
+                verifier.build()
+            } else {
+                verifier.allow_unauthenticated().build()
+            };
+
+            builder.with_client_cert_verifier(verifier.expect("Failed to create client certificate verifier"))
+        }
+        Err(_) => builder.with_no_client_auth(),
+    };
+
+    Some(
+        builder
+            .with_single_cert(load_certificates(&cert_path), load_private_key(&key_path))
+            .expect("Invalid TLS certificate or private key"),
+    )
+}
@@ -0,0 +1,24 @@
+use ipnet::IpNet;
+
+// Reverse proxies allowed to report the client address in `X-Forwarded-For`
+#[derive(Clone, Default)]
+pub struct TrustedProxies(pub Vec<IpNet>);
+
+impl TrustedProxies {
+    pub fn from_env() -> Self {
+        let proxies = std::env::var("TRUSTED_PROXIES").unwrap_or_default();
+
+        Self(
+            proxies
+                .split(',')
+                .map(|proxy| proxy.trim())
+                .filter(|proxy| !proxy.is_empty())
+                .map(|proxy| crate::utils::client_ip::parse_cidr(proxy).expect("Invalid CIDR in TRUSTED_PROXIES"))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, ip: &std::net::IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+use sqlx::PgPool;
+use crate::utils::billing::{self, BillingPeriod};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Generates the draft invoices of the previous month once it is over, unless an administrator already did.
+/// They are issued once an administrator finalizes them.
+pub async fn run(db_pool: PgPool) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let period = BillingPeriod::current().previous();
+        match billing::has_invoices(&db_pool, period).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("Error checking invoices: {}", e);
+                continue;
+            }
+        }
+
+        let generated = async {
+            let invoices = billing::compute_invoices(&db_pool, period).await?;
+            billing::save_invoices(&db_pool, period, &invoices).await
+        }.await;
+
+        match generated {
+            Ok(count) => eprintln!("Generated {} draft invoices for {}", count, period.label()),
+            Err(e) => eprintln!("Error generating invoices for {}: {}", period.label(), e),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::Arc;
+use std::time::Duration;
+use sqlx::PgPool;
+use crate::config::api_keys::ApiKeyConfig;
+use crate::utils::mailer::{Email, Mailer};
+
+/// Periodically disables expired API keys and warns owners of keys about to expire.
+pub async fn run(db_pool: PgPool, mailer: Arc<dyn Mailer>, config: ApiKeyConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.expiry_check_interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = disable_expired_keys(&db_pool, mailer.as_ref()).await {
+            eprintln!("Error disabling expired API keys: {}", e);
+        }
+        if let Err(e) = warn_expiring_keys(&db_pool, mailer.as_ref(), &config).await {
+            eprintln!("Error sending API key expiry warnings: {}", e);
+        }
+    }
+}
+
+async fn disable_expired_keys(db_pool: &PgPool, mailer: &dyn Mailer) -> Result<(), sqlx::Error> {
+    let expired = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET revoked_at = now(), disabled_reason = 'expired'
+        FROM users
+        WHERE users.id = api_keys.user_id
+            AND api_keys.revoked_at IS NULL
+            AND api_keys.expires_at <= now()
+        RETURNING api_keys.name, api_keys.key_prefix, api_keys.rotated_at, users.email
+        "#
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    for key in expired {
+        let reason = if key.rotated_at.is_some() {
+            "its rotation grace period is over"
+        } else {
+            "it reached its expiration date"
+        };
+
+        let sent = mailer.send(&Email {
+            to: key.email,
+            subject: format!("Your GateKeeper API key \"{}\" has been disabled", key.name),
+            body: format!(
+                "Your API key \"{}\" ({}…) has been disabled because {}.\n\nRequests made with it are now rejected. You can create a new key from your dashboard.",
+                key.name, key.key_prefix, reason
+            ),
+        }).await;
+
+        if let Err(e) = sent {
+            eprintln!("Error sending API key expiry notification: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn warn_expiring_keys(db_pool: &PgPool, mailer: &dyn Mailer, config: &ApiKeyConfig) -> Result<(), sqlx::Error> {
+    // Keys in their rotation grace period were replaced on purpose, their owner already knows
+    let expiring = sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET expiry_notified_at = now()
+        FROM users
+        WHERE users.id = api_keys.user_id
+            AND api_keys.revoked_at IS NULL
+            AND api_keys.rotated_at IS NULL
+            AND api_keys.expiry_notified_at IS NULL
+            AND api_keys.expires_at <= now() + make_interval(secs => $1)
+        RETURNING api_keys.name, api_keys.key_prefix, api_keys.expires_at AS "expires_at!", users.email
+        "#,
+        config.expiry_warning_secs as f64
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    for key in expiring {
+        let sent = mailer.send(&Email {
+            to: key.email,
+            subject: format!("Your GateKeeper API key \"{}\" expires soon", key.name),
+            body: format!(
+                "Your API key \"{}\" ({}…) expires on {} UTC.\n\nRotate it from your dashboard before then to avoid any interruption.",
+                key.name, key.key_prefix, key.expires_at
+            ),
+        }).await;
+
+        if let Err(e) = sent {
+            eprintln!("Error sending API key expiry warning: {}", e);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,3 @@
+pub(crate) mod key_expiry;
+pub(crate) mod quota_checkpoint;
+pub(crate) mod invoicing;
\ No newline at end of file
@@ -0,0 +1,39 @@
+use std::time::Duration;
+use sqlx::PgPool;
+use crate::config::redis::RedisPool;
+use crate::config::quota::QuotaConfig;
+use crate::utils::quota;
+
+// Counters saved per query
+const BATCH_SIZE: usize = 500;
+
+/// Periodically copies the quota counters changed in Redis to Postgres, so usage survives a restart of Redis.
+pub async fn run(db_pool: PgPool, redis_client: RedisPool, config: QuotaConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.checkpoint_interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        loop {
+            let checkpoints = match quota::take_dirty(&redis_client, BATCH_SIZE).await {
+                Ok(checkpoints) => checkpoints,
+                Err(e) => {
+                    eprintln!("Error reading quota counters: {}", e);
+                    break;
+                }
+            };
+            if checkpoints.is_empty() {
+                break;
+            }
+
+            if let Err(e) = quota::save_checkpoints(&db_pool, &checkpoints).await {
+                eprintln!("Error saving quota counters: {}", e);
+                // Retried on the next tick
+                if let Err(e) = quota::mark_dirty(&redis_client, &checkpoints).await {
+                    eprintln!("Error requeuing quota counters: {}", e);
+                }
+                break;
+            }
+        }
+    }
+}
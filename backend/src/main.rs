@@ -3,6 +3,7 @@ mod middlewares;
 mod models;
 mod utils;
 mod config;
+mod jobs;
 
 use actix_web::{web, App, HttpServer};
 use actix_cors::Cors;
@@ -10,40 +11,81 @@ use actix_cors::Cors;
 fn configure_routes(
     cfg: &mut web::ServiceConfig,
     db_pool: sqlx::postgres::PgPool,
-    redis_client: redis::Client,
+    redis_client: config::redis::RedisPool,
+    trusted_proxies: config::trusted_proxies::TrustedProxies,
+    signing_config: config::request_signing::RequestSigningConfig,
 ) {
     cfg
         .route("/login", web::post().to(routes::auth::login))
         .route("/register", web::post().to(routes::auth::register))
+        .route("/verify_email", web::post().to(routes::auth::verify_email))
+        .route("/resend_verification", web::post().to(routes::auth::resend_verification))
+        .route("/password_reset/request", web::post().to(routes::auth::request_password_reset))
+        .route("/password_reset/confirm", web::post().to(routes::auth::confirm_password_reset))
         .service(
             web::scope("/dashboard")
                 .wrap(middlewares::jwt_validator::JwtValidator)
                 .service(
                     web::scope("/admin")
                         .wrap(middlewares::admin_validator::AdminValidator::new(db_pool.clone()))
+                        // An impersonation token never carries the administrator's own privileges
+                        .wrap(middlewares::impersonation_guard::DenyImpersonation::all())
                         .configure(routes::user::configure_user_routes)
+                        .configure(routes::roles::configure_role_routes)
+                        .configure(routes::organizations::configure_admin_organization_routes)
+                        .configure(routes::tenants::configure_tenant_routes)
+                        .configure(routes::plans::configure_plan_routes)
+                        .configure(routes::billing::configure_billing_routes)
+                        .configure(routes::audit_log::configure_audit_log_routes)
                 )
-                .route("/users/refresh_api_key", web::post().to(routes::user::refresh_api_key))
+                .configure(routes::api_keys::configure_api_key_routes)
+                .configure(routes::organizations::configure_organization_routes)
+                .route("/users/refresh_api_key", web::post()
+                    .to(routes::user::refresh_api_key)
+                    .wrap(middlewares::permission_guard::RequirePermission::new(utils::permissions::Permission::ApiKeysManageOwn))
+                    .wrap(middlewares::impersonation_guard::DenyImpersonation::all()))
+                .route("/users/change_password", web::post()
+                    .to(routes::auth::change_password)
+                    .wrap(middlewares::impersonation_guard::DenyImpersonation::all()))
+                .route("/impersonation/stop", web::post().to(routes::impersonation::stop_impersonation))
+                .route("/quota", web::get().to(routes::quota::get_quota_status))
                 .route("/get_api_key_usage/{size}", web::get().to(routes::user::get_api_key_usage))
                 .route("/verify", web::get().to(routes::auth::verify))
         )
 
         .service(
             web::scope("/api")
-                .wrap(middlewares::api_key_validator::ApiKeyValidator::new(db_pool.clone()))
+                .wrap(middlewares::api_key_validator::ApiKeyValidator::new(db_pool.clone(), redis_client.clone(), trusted_proxies, signing_config))
                 .wrap(middlewares::api_usage_logger::ApiUsageLogger::new(db_pool.clone()))
                 .service(
                     web::scope("/v1")
-                        .wrap(middlewares::rate_limiter::RateLimiter::new(redis_client.clone(), 5, std::time::Duration::from_secs(60)))
-                        .route("/get_random_number", web::get().to(routes::api::v1::get_random_number::get_random_number)),
+                        // Sheds load from within the concurrency slot, measuring only the handler's latency
+                        .wrap(middlewares::load_shedder::LoadShedder::new("v1"))
+                        // Only requests within their rate limit take a concurrency slot
+                        .wrap(middlewares::concurrency_limiter::ConcurrencyLimiter::new(redis_client.clone(), "v1"))
+                        // Runs inside `ApiKeyValidator` so requests can be limited per key, user or organization
+                        .wrap(middlewares::rate_limiter::RateLimiter::new(redis_client.clone()))
+                        .route("/get_random_number", web::get()
+                            .to(routes::api::v1::get_random_number::get_random_number)
+                            // Quotas are charged last, for requests every other layer accepted
+                            .wrap(middlewares::quota_guard::QuotaGuard::new(db_pool.clone(), redis_client.clone()))
+                            .wrap(middlewares::scope_guard::RequireScope::new("random:read"))),
                 )
                 .service(
                     web::resource("/graphql")
-                        .route(web::post().to(routes::api::graphql::setup::graphql_handler))
+                        .wrap(middlewares::load_shedder::LoadShedder::new("graphql"))
+                        .wrap(middlewares::concurrency_limiter::ConcurrencyLimiter::new(redis_client.clone(), "graphql"))
+                        // Queries are limited by their cost, so expensive ones use up the limit faster
+                        .wrap(middlewares::rate_limiter::RateLimiter::new(redis_client.clone()))
+                        .route(web::post()
+                            .to(routes::api::graphql::setup::graphql_handler)
+                            .wrap(middlewares::quota_guard::QuotaGuard::new(db_pool.clone(), redis_client.clone()))
+                            .wrap(middlewares::scope_guard::RequireScope::new("graphql:query")))
                 )
         )
         .route("/playground", web::get().to(routes::api::graphql::setup::graphql_playground))
-        .route("/ping", web::get().to(routes::health_check::health_check));
+        .route("/ping", web::get().to(routes::health_check::health_check))
+        .route("/health", web::get().to(routes::health_check::health));
 }
 
 #[actix_web::main]
@@ -51,37 +93,118 @@ async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     std::env::set_var("RUST_LOG", "actix_web=debug");
 
+    // Secret used to hash API keys, changing it invalidates every existing key
+    let api_key_pepper = std::env::var("API_KEY_PEPPER").expect("API_KEY_PEPPER must be set");
+    utils::api_key::set_pepper(api_key_pepper);
+
     // Create PostgreSQL connection pool
     let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let db_pool = config::postgresql::create_db_pool(&database_url).await;
+    config::postgresql::run_migrations(&db_pool).await;
+    config::postgresql::hash_legacy_api_keys(&db_pool).await;
 
-    // Create Redis connection pool
+    // Create Redis connection pool, connected on first use and reconnected whenever Redis comes back
     let redis_url = std::env::var("REDIS_URL").expect("REDIS_URL must be set");
-    let redis_client = config::redis::create_redis_client(&redis_url);
+    let redis_client = config::redis::create_redis_pool(&redis_url, config::redis::RedisConfig::from_env());
+
+    // Load login brute-force protection settings
+    let login_guard_config = config::login_guard::LoginGuardConfig::from_env();
+
+    // Load password policy
+    let password_policy = config::password_policy::PasswordPolicy::from_env();
+
+    // Load the reverse proxies allowed to set X-Forwarded-For
+    let trusted_proxies = config::trusted_proxies::TrustedProxies::from_env();
+
+    // Load signed request settings
+    let signing_config = config::request_signing::RequestSigningConfig::from_env();
+
+    // Create mail transport
+    let mailer = config::mailer::create_mailer();
+
+    // Disable expired API keys in the background
+    let api_key_config = config::api_keys::ApiKeyConfig::from_env();
+    actix_web::rt::spawn(jobs::key_expiry::run(db_pool.clone(), mailer.clone(), api_key_config.clone()));
+
+    // Load impersonation settings
+    let impersonation_config = config::impersonation::ImpersonationConfig::from_env();
+
+    // Load TLS settings, plain HTTP is served when no certificate is configured
+    let tls_config = config::tls::create_tls_config();
+
+    // Tenants are resolved for every request from an in-memory copy of the tenants table
+    let tenant_registry = web::Data::new(utils::tenants::TenantRegistry::new(db_pool.clone()));
+
+    // Rate limits are counted per API key by default, with per-plan limits cached in memory
+    let rate_limit_config = config::rate_limit::RateLimitConfig::from_env();
+    let plan_registry = web::Data::new(utils::plans::PlanRegistry::new(db_pool.clone()));
+    // Units each route is billed for, cached in memory
+    let route_cost_registry = web::Data::new(utils::route_costs::RouteCostRegistry::new(db_pool.clone()));
+    // GraphQL queries also cost units for the fields they select
+    let request_cost_config = config::request_cost::RequestCostConfig::from_env();
+    // Counts rate limits while Redis is unreachable, shared by every worker
+    let local_rate_limiter = web::Data::new(utils::rate_limit::LocalRateLimiter::default());
+
+    // Limit in-flight requests per key and per upstream
+    let concurrency_config = config::concurrency::ConcurrencyConfig::from_env();
+    // Adaptive per-upstream limits, shared by every worker of this instance
+    let load_shedding = web::Data::new(utils::load_shedding::LoadShedding::new(config::load_shedding::LoadSheddingConfig::from_env()));
+
+    // Save daily and monthly quota counters to Postgres in the background
+    let quota_config = config::quota::QuotaConfig::from_env();
+    actix_web::rt::spawn(jobs::quota_checkpoint::run(db_pool.clone(), redis_client.clone(), quota_config));
+
+    // Invoice the previous month once it is over
+    actix_web::rt::spawn(jobs::invoicing::run(db_pool.clone()));
 
     // Create GraphQL schema
     let schema = routes::api::graphql::schema::create_schema();
 
     // Start HTTP server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:3000") // Allow requests from your frontend
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"]) // Allow specific HTTP methods
             .allowed_headers(vec!["Content-Type", "Authorization"]) // Allow specific headers
             .allow_any_header()
+            .expose_headers(vec![
+                "RateLimit-Limit", "RateLimit-Remaining", "RateLimit-Reset", "RateLimit-Policy",
+                "X-RateLimit-Limit", "X-RateLimit-Remaining", "X-RateLimit-Reset", "Retry-After", "X-Quota-Overage",
+            ])
             .max_age(3600);
 
         let db_pool_clone = db_pool.clone();
         let redis_client_clone = redis_client.clone();
+        let trusted_proxies_clone = trusted_proxies.clone();
+        let signing_config_clone = signing_config.clone();
 
         App::new()
+            .wrap(middlewares::tenant_resolver::TenantResolver::new(tenant_registry.clone(), trusted_proxies.clone()))
             .wrap(cors)
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(schema.clone()))
-            .configure(move |cfg| configure_routes(cfg, db_pool_clone.clone(), redis_client_clone.clone()))
+            .app_data(web::Data::from(mailer.clone()))
+            .app_data(web::Data::new(redis_client.clone()))
+            .app_data(web::Data::new(login_guard_config.clone()))
+            .app_data(web::Data::new(password_policy.clone()))
+            .app_data(web::Data::new(trusted_proxies.clone()))
+            .app_data(web::Data::new(api_key_config.clone()))
+            .app_data(web::Data::new(impersonation_config.clone()))
+            .app_data(tenant_registry.clone())
+            .app_data(web::Data::new(rate_limit_config.clone()))
+            .app_data(plan_registry.clone())
+            .app_data(route_cost_registry.clone())
+            .app_data(web::Data::new(request_cost_config.clone()))
+            .app_data(local_rate_limiter.clone())
+            .app_data(web::Data::new(concurrency_config.clone()))
+            .app_data(load_shedding.clone())
+            .configure(move |cfg| configure_routes(cfg, db_pool_clone.clone(), redis_client_clone.clone(), trusted_proxies_clone.clone(), signing_config_clone.clone()))
 
     })
-        .bind("0.0.0.0:8080")?
-        .run()
-        .await
+        .on_connect(utils::client_certificate::extract_client_certificate);
+
+    match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(config::tls::tls_bind_addr(), tls_config)?.run().await,
+        None => server.bind(config::tls::HTTP_BIND_ADDR)?.run().await,
+    }
 }
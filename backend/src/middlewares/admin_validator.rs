@@ -3,6 +3,7 @@ use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, Transform}, Erro
 use futures::future::{ok, Ready};
 use std::task::{Context, Poll};
 use sqlx::PgPool;
+use crate::utils::permissions::{has_permission, Permission};
 
 pub struct AdminValidator {
     pub db_pool: PgPool,
@@ -68,21 +69,10 @@ where
                 None => return Err(actix_web::error::ErrorUnauthorized("User ID not found")),
             };
 
-            let permission = sqlx::query!("SELECT permission FROM users WHERE id = $1", user_id)
-                .fetch_one(&db_pool)
-                .await;
-
-            match permission {
-                Ok(user) => {
-                    if user.permission == 1 {
-                        fut.await
-                    } else {
-                        Err(actix_web::error::ErrorUnauthorized(
-                            "You do not have permission to access this resource",
-                        ))
-                    }
-                }
-                Err(_) => Err(actix_web::error::ErrorUnauthorized(
+            // Each admin route then declares its own permission with `RequirePermission`
+            match has_permission(&db_pool, user_id, Permission::AdminAccess).await {
+                Ok(true) => fut.await,
+                _ => Err(actix_web::error::ErrorUnauthorized(
                     "You do not have permission to access this resource",
                 )),
             }
@@ -1,24 +1,48 @@
 use std::pin::Pin;
+use std::rc::Rc;
 use actix_web::{
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpResponse,
 };
 use futures::future::{ok, Ready};
 use std::task::{Context, Poll};
+use crate::config::redis::RedisPool;
+use crate::config::request_cost::RequestCostConfig;
+use crate::config::request_signing::RequestSigningConfig;
+use crate::config::trusted_proxies::TrustedProxies;
+use crate::models::api_key::ApiIdentity;
+use crate::utils::api_key::{key_prefix, signing_secret, verify_api_key};
+use crate::utils::client_certificate::ClientCertificate;
+use crate::utils::plans::PlanRegistry;
+use crate::utils::quota::{self, QuotaAction, QuotaCharge};
+use crate::utils::request_cost::{self, RequestCost};
+use crate::utils::route_costs::RouteCostRegistry;
+use crate::utils::tenants::Tenant;
+use crate::utils::client_ip::{parse_cidr, request_origin, resolve_client_ip, ClientIp};
+use crate::utils::request_signing::{self, SignatureHeaders};
+use crate::utils::wildcard;
 
 pub struct ApiKeyValidator {
     db_pool: sqlx::PgPool,
+    redis_client: RedisPool,
+    trusted_proxies: TrustedProxies,
+    signing_config: RequestSigningConfig,
 }
 
 impl ApiKeyValidator {
-    pub fn new(db_pool: sqlx::PgPool) -> Self {
-        Self { db_pool }
+    pub fn new(
+        db_pool: sqlx::PgPool,
+        redis_client: RedisPool,
+        trusted_proxies: TrustedProxies,
+        signing_config: RequestSigningConfig,
+    ) -> Self {
+        Self { db_pool, redis_client, trusted_proxies, signing_config }
     }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for ApiKeyValidator
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<B>;
@@ -29,20 +53,26 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(ApiKeyValidatorMiddleware {
-            service,
-            db_pool: self.db_pool.clone()
+            service: Rc::new(service),
+            db_pool: self.db_pool.clone(),
+            redis_client: self.redis_client.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            signing_config: self.signing_config.clone(),
         })
     }
 }
 
 pub struct ApiKeyValidatorMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     db_pool: sqlx::PgPool,
+    redis_client: RedisPool,
+    trusted_proxies: TrustedProxies,
+    signing_config: RequestSigningConfig,
 }
 
 impl<S, B> Service<ServiceRequest> for ApiKeyValidatorMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<B>;
@@ -56,33 +86,315 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let db_pool = self.db_pool.clone();
+        let redis_client = self.redis_client.clone();
+        let max_clock_skew_secs = self.signing_config.max_clock_skew_secs;
+        let service = Rc::clone(&self.service);
 
         let api_key = req
             .headers()
             .get("x-api-key")
             .and_then(|v| v.to_str().ok())
             .map(|v| v.to_string());
-
-        let fut = self.service.call(req);
+        let signature = request_signing::parse_headers(req.headers());
+        let client_ip = resolve_client_ip(req.request(), &self.trusted_proxies);
+        let origin = request_origin(&req);
+        let certificate = req.conn_data::<ClientCertificate>().cloned();
+        let tenant_id = req.extensions().get::<Tenant>().map(|tenant| tenant.id);
+        let plans = req.app_data::<web::Data<PlanRegistry>>().cloned();
+        let route_costs = req.app_data::<web::Data<RouteCostRegistry>>().cloned();
+        let cost_config = req
+            .app_data::<web::Data<RequestCostConfig>>()
+            .map(|config| config.get_ref().clone())
+            .unwrap_or_else(RequestCostConfig::from_env);
 
         Box::pin(async move {
-            if let Some(api_key) = api_key {
-                if validate_api_key(&db_pool, &api_key).await {
-                    return fut.await
-                } else {
-                    Err(actix_web::error::ErrorUnauthorized("Invalid or missing API key"))
+            let mut req = req;
+
+            let key = match (signature, api_key) {
+                (Err(e), _) => return Err(actix_web::error::ErrorUnauthorized(e)),
+                (Ok(Some(signature)), _) => {
+                    validate_signed_request(&db_pool, &redis_client, max_clock_skew_secs, &mut req, signature).await?
+                }
+                (Ok(None), Some(api_key)) => {
+                    let key = match validate_api_key(&db_pool, &api_key).await {
+                        Some(key) => key,
+                        None => return Err(actix_web::error::ErrorUnauthorized("Invalid or missing API key")),
+                    };
+                    if key.require_signature {
+                        return Err(actix_web::error::ErrorUnauthorized("This API key only accepts signed requests"));
+                    }
+                    key
+                }
+                // Without an API key, a verified client certificate mapped to a user is accepted
+                (Ok(None), None) => match certificate {
+                    Some(certificate) => validate_client_certificate(&db_pool, &certificate)
+                        .await?
+                        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Client certificate is not registered"))?,
+                    None => return Err(actix_web::error::ErrorUnauthorized("Invalid or missing API key")),
+                },
+            };
+
+            if Some(key.tenant_id) != tenant_id {
+                return Err(actix_web::error::ErrorUnauthorized("Invalid or missing API key"));
+            }
+
+            check_restrictions(&key, client_ip, origin.as_deref())?;
+
+            let plan = match plans {
+                Some(plans) => plans.resolve(key.identity.plan_id).await.map_err(|_| {
+                    actix_web::error::ErrorInternalServerError("Failed to load plan")
+                })?,
+                None => None,
+            };
+            if let Some(plan) = &plan {
+                if !plan.allows_route(req.method().as_str(), req.path()) {
+                    let message = format!("The {} plan does not include this route", plan.name);
+                    return Err(forbidden("route_not_in_plan", &message));
+                }
+            }
+            let cost = request_cost::resolve(&mut req, route_costs.as_ref().map(|registry| registry.get_ref()), &cost_config).await?;
+            let cache_control = plan.as_ref().and_then(|plan| plan.cache_control());
+            let limits = quota::limits(plan.as_ref(), key.monthly_request_quota);
+            // Charged by `QuotaGuard`, after the burst limits, so rejected requests do not use up the quota
+            let charge = (!limits.is_empty()).then(|| QuotaCharge {
+                subject: quota::subject(&key.identity),
+                limits,
+                action: plan.as_ref().map(|plan| plan.quota_action()).unwrap_or(QuotaAction::Block),
+            });
+
+            // The identity must be attached before the inner service starts handling the request
+            if let Some(client_ip) = client_ip {
+                req.extensions_mut().insert(ClientIp(client_ip));
+            }
+            req.extensions_mut().insert(key.identity);
+            req.extensions_mut().insert(RequestCost(cost));
+            if let Some(charge) = charge {
+                req.extensions_mut().insert(charge);
+            }
+
+            let mut res = service.call(req).await?;
+            // The cache policy of the plan only fills in for handlers that did not choose one
+            if let Some(cache_control) = cache_control {
+                let cacheable = res.request().method() == actix_web::http::Method::GET && res.status().is_success();
+                if cacheable && !res.headers().contains_key(actix_web::http::header::CACHE_CONTROL) {
+                    res.headers_mut().insert(actix_web::http::header::CACHE_CONTROL, cache_control);
                 }
-            } else {
-                Err(actix_web::error::ErrorUnauthorized("Invalid or missing API key"))
             }
+            Ok(res)
         })
     }
 }
 
-async fn validate_api_key(db_pool: &sqlx::PgPool, api_key: &str) -> bool {
-    let query = "SELECT EXISTS(SELECT 1 FROM users WHERE api_key = $1)";
-    sqlx::query_scalar(query)
-        .bind(api_key)
-        .fetch_one(db_pool)
-        .await.unwrap_or_else(|_| false)
-}
\ No newline at end of file
+struct ValidatedKey {
+    identity: ApiIdentity,
+    allowed_cidrs: Vec<String>,
+    allowed_origins: Vec<String>,
+    require_signature: bool,
+    // Quota set on the organization owning the key, replaces the monthly quota of its plan
+    monthly_request_quota: Option<i64>,
+    // Tenant of the key owner, keys only work on their own tenant
+    tenant_id: i32,
+}
+
+async fn validate_api_key(db_pool: &sqlx::PgPool, api_key: &str) -> Option<ValidatedKey> {
+    let prefix = key_prefix(api_key);
+
+    // Several keys may share a prefix, the hash tells them apart
+    let candidates = sqlx::query!(
+        r#"
+        SELECT api_keys.id, api_keys.user_id, api_keys.organization_id, api_keys.key_hash, api_keys.scopes,
+            api_keys.allowed_cidrs, api_keys.allowed_origins, api_keys.require_signature,
+            organizations.monthly_request_quota AS "monthly_request_quota?", users.tenant_id,
+            COALESCE(organizations.plan_id, users.plan_id) AS plan_id
+        FROM api_keys
+        JOIN users ON users.id = api_keys.user_id
+        LEFT JOIN organizations ON organizations.id = api_keys.organization_id
+        LEFT JOIN organization_members ON organization_members.organization_id = api_keys.organization_id
+            AND organization_members.user_id = api_keys.user_id
+        WHERE api_keys.key_prefix = $1 AND api_keys.revoked_at IS NULL
+            AND (api_keys.expires_at IS NULL OR api_keys.expires_at > now())
+            -- Organization keys stop working once their creator leaves the organization
+            AND (api_keys.organization_id IS NULL OR organization_members.user_id IS NOT NULL)
+        "#,
+        prefix
+    )
+        .fetch_all(db_pool)
+        .await
+        .unwrap_or_default();
+
+    candidates
+        .into_iter()
+        .find(|candidate| verify_api_key(api_key, &candidate.key_hash))
+        .map(|candidate| ValidatedKey {
+            identity: ApiIdentity {
+                user_id: candidate.user_id,
+                key_id: Some(candidate.id),
+                key_prefix: Some(prefix),
+                certificate_id: None,
+                organization_id: candidate.organization_id,
+                scopes: candidate.scopes,
+                plan_id: candidate.plan_id,
+            },
+            allowed_cidrs: candidate.allowed_cidrs,
+            allowed_origins: candidate.allowed_origins,
+            require_signature: candidate.require_signature,
+            monthly_request_quota: candidate.monthly_request_quota,
+            tenant_id: candidate.tenant_id,
+        })
+}
+
+async fn validate_signed_request(
+    db_pool: &sqlx::PgPool,
+    redis_client: &RedisPool,
+    max_clock_skew_secs: u64,
+    req: &mut ServiceRequest,
+    signature: SignatureHeaders,
+) -> Result<ValidatedKey, Error> {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    if now.abs_diff(signature.timestamp) > max_clock_skew_secs {
+        return Err(actix_web::error::ErrorUnauthorized("Request timestamp is outside the allowed clock skew"));
+    }
+
+    let key = sqlx::query!(
+        r#"
+        SELECT api_keys.id, api_keys.user_id, api_keys.organization_id, api_keys.key_prefix, api_keys.key_hash,
+            api_keys.scopes, api_keys.allowed_cidrs, api_keys.allowed_origins,
+            organizations.monthly_request_quota AS "monthly_request_quota?", users.tenant_id,
+            COALESCE(organizations.plan_id, users.plan_id) AS plan_id
+        FROM api_keys
+        JOIN users ON users.id = api_keys.user_id
+        LEFT JOIN organizations ON organizations.id = api_keys.organization_id
+        LEFT JOIN organization_members ON organization_members.organization_id = api_keys.organization_id
+            AND organization_members.user_id = api_keys.user_id
+        WHERE api_keys.id = $1 AND api_keys.signing_enabled AND api_keys.revoked_at IS NULL
+            AND (api_keys.expires_at IS NULL OR api_keys.expires_at > now())
+            -- Organization keys stop working once their creator leaves the organization
+            AND (api_keys.organization_id IS NULL OR organization_members.user_id IS NOT NULL)
+        "#,
+        signature.key_id
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to validate request signature"))?
+        .ok_or_else(|| actix_web::error::ErrorUnauthorized("Invalid request signature"))?;
+
+    // The body is consumed to be hashed, then put back for the handler
+    let body = req.extract::<web::Bytes>().await?;
+    let path_and_query = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/").to_string();
+    let canonical_request = request_signing::canonical_request(
+        req.method().as_str(),
+        &path_and_query,
+        signature.timestamp,
+        &signature.nonce,
+        &body,
+    );
+    req.set_payload(Payload::from(body));
+
+    if !request_signing::verify_signature(&signing_secret(&key.key_hash), &canonical_request, &signature.signature) {
+        return Err(actix_web::error::ErrorUnauthorized("Invalid request signature"));
+    }
+
+    // Only checked once the signature is valid, so unsigned garbage cannot fill Redis.
+    // A nonce only needs to be remembered as long as its timestamp is accepted.
+    let fresh = request_signing::register_nonce(redis_client, key.id, &signature.nonce, max_clock_skew_secs * 2)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to connect to Redis"))?;
+    if !fresh {
+        return Err(actix_web::error::ErrorUnauthorized("Request nonce has already been used"));
+    }
+
+    Ok(ValidatedKey {
+        identity: ApiIdentity {
+            user_id: key.user_id,
+            key_id: Some(key.id),
+            key_prefix: Some(key.key_prefix),
+            certificate_id: None,
+            organization_id: key.organization_id,
+            scopes: key.scopes,
+            plan_id: key.plan_id,
+        },
+        allowed_cidrs: key.allowed_cidrs,
+        allowed_origins: key.allowed_origins,
+        require_signature: true,
+        monthly_request_quota: key.monthly_request_quota,
+        tenant_id: key.tenant_id,
+    })
+}
+
+async fn validate_client_certificate(
+    db_pool: &sqlx::PgPool,
+    certificate: &ClientCertificate,
+) -> Result<Option<ValidatedKey>, Error> {
+    // A fingerprint pins one certificate, a subject follows it across renewals by the CA
+    let record = sqlx::query!(
+        r#"
+        SELECT client_certificates.id, client_certificates.user_id, client_certificates.scopes, users.tenant_id, users.plan_id
+        FROM client_certificates
+        JOIN users ON users.id = client_certificates.user_id
+        WHERE client_certificates.revoked_at IS NULL
+            AND (client_certificates.fingerprint = $1 OR (client_certificates.fingerprint IS NULL AND client_certificates.subject = $2))
+        ORDER BY client_certificates.fingerprint IS NULL
+        LIMIT 1
+        "#,
+        certificate.fingerprint,
+        certificate.subject
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to validate client certificate"))?;
+
+    Ok(record.map(|record| ValidatedKey {
+        identity: ApiIdentity {
+            user_id: record.user_id,
+            key_id: None,
+            key_prefix: None,
+            certificate_id: Some(record.id),
+            organization_id: None,
+            scopes: record.scopes,
+            plan_id: record.plan_id,
+        },
+        allowed_cidrs: Vec::new(),
+        allowed_origins: Vec::new(),
+        require_signature: false,
+        monthly_request_quota: None,
+        tenant_id: record.tenant_id,
+    }))
+}
+
+fn forbidden(error: &str, message: &str) -> Error {
+    let response = HttpResponse::Forbidden().json(serde_json::json!({
+        "error": error,
+        "message": message,
+    }));
+    actix_web::error::InternalError::from_response(message.to_string(), response).into()
+}
+
+// Empty lists leave the key unrestricted
+fn check_restrictions(key: &ValidatedKey, client_ip: Option<std::net::IpAddr>, origin: Option<&str>) -> Result<(), Error> {
+    if !key.allowed_cidrs.is_empty() {
+        let allowed = client_ip.is_some_and(|ip| {
+            key.allowed_cidrs
+                .iter()
+                .filter_map(|cidr| parse_cidr(cidr).ok())
+                .any(|net| net.contains(&ip))
+        });
+
+        if !allowed {
+            return Err(forbidden("ip_not_allowed", "This API key cannot be used from this IP address"));
+        }
+    }
+
+    if !key.allowed_origins.is_empty() {
+        let allowed = origin.is_some_and(|origin| {
+            key.allowed_origins
+                .iter()
+                .any(|pattern| wildcard::matches(&pattern.to_lowercase(), &origin.to_lowercase()))
+        });
+
+        if !allowed {
+            return Err(forbidden("origin_not_allowed", "This API key cannot be used from this origin"));
+        }
+    }
+
+    Ok(())
+}
@@ -1,11 +1,19 @@
 use std::pin::Pin;
 use actix_web::{
+    body::{BodySize, MessageBody},
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    http::header::CONTENT_LENGTH,
+    Error, HttpMessage,
 };
 use futures::future::{ok, Ready};
 use std::task::{Context, Poll};
 use time::PrimitiveDateTime;
+use crate::models::api_key::ApiIdentity;
+use crate::utils::client_ip::ClientIp;
+use crate::utils::quota::{QuotaAction, QuotaExceeded};
+use crate::utils::request_cost::RequestCost;
+use crate::utils::route_costs::DEFAULT_COST;
+use crate::utils::tenants::Tenant;
 
 pub struct ApiUsageLogger {
     db_pool: sqlx::PgPool,
@@ -21,6 +29,7 @@ impl<S, B> Transform<S, ServiceRequest> for ApiUsageLogger
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: MessageBody,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -45,6 +54,7 @@ impl<S, B> Service<ServiceRequest> for ApiUsageLoggerMiddleware<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
+    B: MessageBody,
 {
     type Response = ServiceResponse<B>;
 
@@ -62,40 +72,79 @@ where
         Box::pin(async move {
             let res = fut.await?;
             let request = res.request();
-            let api_key = request
-                .headers()
-                .get("x-api-key")
-                .and_then(|v| v.to_str().ok())
-                .unwrap();
+            let identity = request.extensions().get::<ApiIdentity>().cloned();
+            let identity = match identity {
+                Some(identity) => identity,
+                None => return Ok(res),
+            };
             let path = request.path();
             let method = request.method().as_str();
             let now = time::OffsetDateTime::now_utc();
             let primitive_now = PrimitiveDateTime::new(now.date(), now.time());
+            let client_ip = request.extensions().get::<ClientIp>().map(|ip| ip.0.to_string());
+            let tenant_id = request.extensions().get::<Tenant>().map(|tenant| tenant.id);
             let binding = request.connection_info().clone();
-            let peer_addr = binding.peer_addr().unwrap();
+            let peer_addr = client_ip.unwrap_or_else(|| binding.peer_addr().unwrap_or("unknown").to_string());
             let status_code = res.status().as_u16() as i32;
-            let user_id = sqlx::query!("SELECT id FROM users WHERE api_key = $1", api_key)
-                .fetch_one(&db_pool)
-                .await
-                .map(|r| r.id)
-                .unwrap();
-
+            let overage = request
+                .extensions()
+                .get::<QuotaExceeded>()
+                .is_some_and(|exceeded| exceeded.action == QuotaAction::Overage);
+            // The units the request was charged for by `ApiKeyValidator`, including GraphQL complexity
+            let cost = request.extensions().get::<RequestCost>().map(|cost| cost.0).unwrap_or(DEFAULT_COST);
+            // Bodies are metered from their declared size, streamed bodies of unknown size count as empty
+            let request_bytes = request
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok())
+                .and_then(|length| length.parse::<i64>().ok())
+                .unwrap_or(0);
+            let response_bytes = match res.response().body().size() {
+                BodySize::Sized(size) => size as i64,
+                _ => 0,
+            };
             let _ = sqlx::query!(
                 r#"
-                INSERT INTO api_usage (user_id, api_key, request_path, request_method, request_time, request_ip, status_code)
-                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                INSERT INTO api_usage (user_id, tenant_id, organization_id, api_key_id, api_key_prefix, client_certificate_id, request_path, request_method, request_time, request_ip, status_code, overage, cost, request_bytes, response_bytes)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
                 "#,
-                user_id,
-                api_key,
+                identity.user_id,
+                tenant_id,
+                identity.organization_id,
+                identity.key_id,
+                identity.key_prefix,
+                identity.certificate_id,
                 path,
                 method,
                 primitive_now,
                 peer_addr,
-                status_code
+                status_code,
+                overage,
+                cost,
+                request_bytes,
+                response_bytes
             )
             .execute(&db_pool)
             .await;
 
+            if let Some(key_id) = identity.key_id {
+                let _ = sqlx::query!("UPDATE api_keys SET last_used_at = $1 WHERE id = $2",
+                    primitive_now,
+                    key_id
+                )
+                .execute(&db_pool)
+                .await;
+            }
+
+            if let Some(certificate_id) = identity.certificate_id {
+                let _ = sqlx::query!("UPDATE client_certificates SET last_used_at = $1 WHERE id = $2",
+                    primitive_now,
+                    certificate_id
+                )
+                .execute(&db_pool)
+                .await;
+            }
+
             Ok(res)
         })
     }
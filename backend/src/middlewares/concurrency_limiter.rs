@@ -0,0 +1,195 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::RETRY_AFTER,
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use crate::config::redis::RedisPool;
+use crate::config::concurrency::ConcurrencyConfig;
+use crate::config::rate_limit::{RateLimitConfig, RedisFailureMode};
+use crate::models::api_key::ApiIdentity;
+use crate::utils::concurrency::{self, Acquisition, Semaphore};
+use crate::utils::rate_limit;
+
+// How often a queued request checks whether its turn has come
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+// Must run after `ApiKeyValidator`. Limits the requests handled at the same time per API key,
+// and across every caller for the upstream behind the wrapped routes.
+pub struct ConcurrencyLimiter {
+    redis_client: RedisPool,
+    upstream: &'static str,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(redis_client: RedisPool, upstream: &'static str) -> Self {
+        Self { redis_client, upstream }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ConcurrencyLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ConcurrencyLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConcurrencyLimiterMiddleware {
+            service: Rc::new(service),
+            redis_client: self.redis_client.clone(),
+            upstream: self.upstream,
+        })
+    }
+}
+
+pub struct ConcurrencyLimiterMiddleware<S> {
+    service: Rc<S>,
+    redis_client: RedisPool,
+    upstream: &'static str,
+}
+
+// Gives the slots back when the request completes, fails or is dropped because the client went away
+struct Permit {
+    redis_client: RedisPool,
+    names: Vec<String>,
+    request_id: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let redis_client = self.redis_client.clone();
+        let names = std::mem::take(&mut self.names);
+        let request_id = std::mem::take(&mut self.request_id);
+        actix_web::rt::spawn(async move {
+            if let Err(e) = concurrency::release(&redis_client, &names, &request_id).await {
+                eprintln!("Error releasing concurrency slots: {}", e);
+            }
+        });
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ConcurrencyLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let redis_client = self.redis_client.clone();
+        let service = Rc::clone(&self.service);
+        let upstream = self.upstream;
+        let config = req.app_data::<web::Data<ConcurrencyConfig>>().cloned();
+        let failure_mode = req
+            .app_data::<web::Data<RateLimitConfig>>()
+            .map(|config| config.failure_mode)
+            .unwrap_or(RedisFailureMode::Local);
+
+        let caller = req.extensions().get::<ApiIdentity>().map(|identity| {
+            match (identity.key_id, identity.certificate_id) {
+                (Some(key_id), _) => format!("key:{}", key_id),
+                (None, Some(certificate_id)) => format!("certificate:{}", certificate_id),
+                (None, None) => format!("user:{}", identity.user_id),
+            }
+        });
+
+        Box::pin(async move {
+            let config = match config {
+                Some(config) => config,
+                None => return service.call(req).await,
+            };
+
+            // A limit of 0 leaves its semaphore out
+            let semaphores = [
+                caller.map(|caller| Semaphore { name: caller, limit: config.per_key }),
+                Some(Semaphore { name: format!("upstream:{}", upstream), limit: config.per_upstream }),
+            ]
+                .into_iter()
+                .flatten()
+                .filter(|semaphore| semaphore.limit > 0)
+                .collect::<Vec<_>>();
+            if semaphores.is_empty() {
+                return service.call(req).await;
+            }
+
+            let permit = Permit {
+                redis_client: redis_client.clone(),
+                names: semaphores.iter().map(|semaphore| semaphore.name.clone()).collect(),
+                request_id: format!("{:016x}", rand::random::<u64>()),
+            };
+            let lease = Duration::from_secs(config.lease_secs);
+            let queue_timeout = Duration::from_millis(config.queue_timeout_ms);
+            let deadline = Instant::now() + queue_timeout;
+
+            loop {
+                let acquisition = concurrency::try_acquire(
+                    &redis_client,
+                    &semaphores,
+                    &permit.request_id,
+                    lease,
+                    config.queue_size,
+                    queue_timeout,
+                ).await;
+
+                // Slots are shared between instances, they are not enforced without Redis unless the failure mode is closed
+                let acquisition = match (acquisition, failure_mode) {
+                    (Ok(acquisition), _) => acquisition,
+                    (Err(_), RedisFailureMode::Closed) => return Err(rate_limit::redis_unavailable()),
+                    (Err(_), _) => break,
+                };
+
+                match acquisition {
+                    Acquisition::Acquired => break,
+                    Acquisition::Queued(_) if Instant::now() < deadline => {
+                        actix_web::rt::time::sleep(QUEUE_POLL_INTERVAL).await;
+                    }
+                    // Dropping the permit leaves the queues
+                    Acquisition::Queued(index) | Acquisition::Rejected(index) => {
+                        return Err(rejection(&semaphores[index], upstream));
+                    }
+                }
+            }
+
+            let res = service.call(req).await;
+            drop(permit);
+            res
+        })
+    }
+}
+
+// The caller is told to slow down when its own limit is reached, and to come back later when the upstream is busy
+fn rejection(semaphore: &Semaphore, upstream: &str) -> Error {
+    let response = if semaphore.name.starts_with("upstream:") {
+        HttpResponse::ServiceUnavailable()
+            .insert_header((RETRY_AFTER, 1))
+            .json(serde_json::json!({
+                "error": "upstream_busy",
+                "message": format!("Too many requests are in progress for {}", upstream),
+                "limit": semaphore.limit,
+            }))
+    } else {
+        HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, 1))
+            .json(serde_json::json!({
+                "error": "too_many_concurrent_requests",
+                "message": format!("At most {} requests can be in progress at once", semaphore.limit),
+                "limit": semaphore.limit,
+            }))
+    };
+    actix_web::error::InternalError::from_response("Concurrency limit exceeded", response).into()
+}
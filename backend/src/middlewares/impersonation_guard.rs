@@ -0,0 +1,85 @@
+use std::pin::Pin;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use crate::utils::impersonation::Impersonation;
+
+// Must run after `JwtValidator`, which marks requests made with an impersonation token
+#[derive(Clone, Copy)]
+pub struct DenyImpersonation {
+    // Only requests that change something are refused when set, reads stay available to support staff
+    writes_only: bool,
+}
+
+impl DenyImpersonation {
+    pub fn all() -> Self {
+        Self { writes_only: false }
+    }
+
+    pub fn writes() -> Self {
+        Self { writes_only: true }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for DenyImpersonation
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = DenyImpersonationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(DenyImpersonationMiddleware {
+            service,
+            writes_only: self.writes_only,
+        })
+    }
+}
+
+pub struct DenyImpersonationMiddleware<S> {
+    service: S,
+    writes_only: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for DenyImpersonationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+        let impersonated = req.extensions().get::<Impersonation>().is_some();
+
+        if impersonated && !(self.writes_only && is_read) {
+            let response = HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "impersonation_forbidden",
+                "message": "This action is not available while impersonating a user",
+            }));
+            return Box::pin(async move {
+                Err(actix_web::error::InternalError::from_response("Forbidden during impersonation", response).into())
+            });
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res)
+        })
+    }
+}
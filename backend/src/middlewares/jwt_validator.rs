@@ -1,10 +1,14 @@
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, web, Error, HttpMessage};
 use actix_web::dev::{Transform, Service};
 use futures::future::{ok, Ready};
 use futures::Future;
+use sqlx::PgPool;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
+use crate::utils::impersonation::{is_session_active, Impersonation};
 use crate::utils::jwt::validate_jwt;
+use crate::utils::tenants::Tenant;
 
 pub struct JwtValidator;
 
@@ -21,12 +25,12 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(JwtValidatorMiddleware { service })
+        ok(JwtValidatorMiddleware { service: Rc::new(service) })
     }
 }
 
 pub struct JwtValidatorMiddleware<S> {
-    service: S,
+    service: Rc<S>,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtValidatorMiddleware<S>
@@ -45,20 +49,43 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let token = req.headers().get("Authorization").and_then(|v| v.to_str().ok());
+        let tenant_id = req.extensions().get::<Tenant>().map(|tenant| tenant.id);
 
-        if let Some(token) = token {
-            if let Some(user_id) = validate_jwt(token) {
-                // Attach the user ID to the request extensions for downstream use
-                req.extensions_mut().insert(user_id);
+        // A token issued on one tenant cannot be used on another
+        let claims = match token.and_then(validate_jwt).filter(|claims| Some(claims.tid) == tenant_id) {
+            Some(claims) => claims,
+            None => return Box::pin(async { Err(actix_web::error::ErrorUnauthorized("Invalid JWT token")) }),
+        };
 
-                let fut = self.service.call(req);
-                return Box::pin(async move {
-                    let res = fut.await?;
-                    Ok(res)
-                });
+        let impersonation = match (claims.act, claims.sid) {
+            (Some(admin_user_id), Some(session_id)) => Some(Impersonation { admin_user_id, session_id }),
+            _ => None,
+        };
+        let db_pool = req.app_data::<web::Data<PgPool>>().cloned();
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            // Impersonation tokens stay valid only while their session has not been stopped
+            if let Some(impersonation) = impersonation {
+                let db_pool = match db_pool {
+                    Some(db_pool) => db_pool,
+                    None => return Err(actix_web::error::ErrorInternalServerError("Database pool not configured")),
+                };
+                let user_id = claims.sub.parse::<i32>().unwrap_or_default();
+
+                match is_session_active(&db_pool, impersonation.session_id, impersonation.admin_user_id, user_id).await {
+                    Ok(true) => {}
+                    Ok(false) => return Err(actix_web::error::ErrorUnauthorized("Impersonation session has ended")),
+                    Err(_) => return Err(actix_web::error::ErrorInternalServerError("Failed to check impersonation session")),
+                }
+
+                req.extensions_mut().insert(impersonation);
             }
-        }
 
-        Box::pin(async { Err(actix_web::error::ErrorUnauthorized("Invalid JWT token")) })
+            // Attach the user ID to the request extensions for downstream use
+            req.extensions_mut().insert(claims.sub);
+
+            service.call(req).await
+        })
     }
-}
\ No newline at end of file
+}
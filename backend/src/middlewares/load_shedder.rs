@@ -0,0 +1,129 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::RETRY_AFTER,
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use crate::models::api_key::ApiIdentity;
+use crate::utils::load_shedding::LoadShedding;
+use crate::utils::plans::PlanRegistry;
+
+// Must run after `ApiKeyValidator`, and inside the other limiters so only the upstream's own latency is measured.
+// Sheds requests once the adaptive limit of the upstream is reached, lower plan priorities first.
+pub struct LoadShedder {
+    upstream: &'static str,
+}
+
+impl LoadShedder {
+    pub fn new(upstream: &'static str) -> Self {
+        Self { upstream }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LoadShedder
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = LoadShedderMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LoadShedderMiddleware {
+            service: Rc::new(service),
+            upstream: self.upstream,
+        })
+    }
+}
+
+pub struct LoadShedderMiddleware<S> {
+    service: Rc<S>,
+    upstream: &'static str,
+}
+
+// Ends the request in the limit when it completes, fails or is dropped because the client went away
+struct InFlight {
+    shedding: web::Data<LoadShedding>,
+    upstream: &'static str,
+    started: Instant,
+    // Whether the request failed, unknown until it completes
+    failed: Option<bool>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let outcome = self.failed.map(|failed| (self.started.elapsed(), failed));
+        self.shedding.release(self.upstream, outcome);
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for LoadShedderMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let upstream = self.upstream;
+        let shedding = req.app_data::<web::Data<LoadShedding>>().cloned();
+        let plans = req.app_data::<web::Data<PlanRegistry>>().cloned();
+        let plan_id = req.extensions().get::<ApiIdentity>().and_then(|identity| identity.plan_id);
+
+        Box::pin(async move {
+            let shedding = match shedding {
+                Some(shedding) if shedding.enabled() => shedding,
+                _ => return service.call(req).await,
+            };
+
+            // Callers without a plan get the priority of the default plan, and everyone the lowest when plans cannot be loaded
+            let priority = match plans {
+                Some(plans) => plans
+                    .resolve(plan_id)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|plan| plan.priority)
+                    .unwrap_or(0),
+                None => 0,
+            };
+
+            if !shedding.try_acquire(upstream, priority) {
+                return Err(rejection(upstream));
+            }
+            let mut in_flight = InFlight { shedding, upstream, started: Instant::now(), failed: None };
+
+            let res = service.call(req).await;
+            in_flight.failed = Some(match &res {
+                Ok(res) => res.status().is_server_error(),
+                Err(e) => e.as_response_error().status_code().is_server_error(),
+            });
+            drop(in_flight);
+            res
+        })
+    }
+}
+
+fn rejection(upstream: &str) -> Error {
+    let response = HttpResponse::ServiceUnavailable()
+        .insert_header((RETRY_AFTER, 1))
+        .json(serde_json::json!({
+            "error": "overloaded",
+            "message": format!("{} is overloaded, try again later", upstream),
+        }));
+    actix_web::error::InternalError::from_response("Request shed", response).into()
+}
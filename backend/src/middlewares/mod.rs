@@ -3,3 +3,10 @@ pub(crate) mod jwt_validator;
 pub(crate) mod rate_limiter;
 pub(crate) mod api_key_validator;
 pub(crate) mod api_usage_logger;
+pub(crate) mod scope_guard;
+pub(crate) mod permission_guard;
+pub(crate) mod tenant_resolver;
+pub(crate) mod impersonation_guard;
+pub(crate) mod concurrency_limiter;
+pub(crate) mod load_shedder;
+pub(crate) mod quota_guard;
@@ -0,0 +1,95 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use sqlx::PgPool;
+use std::task::{Context, Poll};
+use crate::utils::permissions::{has_permission, Permission};
+
+// Must run after `JwtValidator`, which attaches the user id to the request
+pub struct RequirePermission {
+    permission: Permission,
+}
+
+impl RequirePermission {
+    pub fn new(permission: Permission) -> Self {
+        Self { permission }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequirePermissionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionMiddleware {
+            service: Rc::new(service),
+            permission: self.permission,
+        })
+    }
+}
+
+pub struct RequirePermissionMiddleware<S> {
+    service: Rc<S>,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let permission = self.permission;
+        let service = Rc::clone(&self.service);
+        let db_pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+        let user_id = req
+            .extensions()
+            .get::<String>()
+            .cloned()
+            .and_then(|id| id.parse::<i32>().ok());
+
+        Box::pin(async move {
+            let user_id = match user_id {
+                Some(id) => id,
+                None => return Err(actix_web::error::ErrorUnauthorized("User ID not found")),
+            };
+            let db_pool = match db_pool {
+                Some(db_pool) => db_pool,
+                None => return Err(actix_web::error::ErrorInternalServerError("Database pool not configured")),
+            };
+
+            match has_permission(&db_pool, user_id, permission).await {
+                Ok(true) => service.call(req).await,
+                Ok(false) => {
+                    let response = HttpResponse::Forbidden().json(serde_json::json!({
+                        "error": "insufficient_permission",
+                        "required_permission": permission.as_str(),
+                        "message": format!("Your role does not have the '{}' permission", permission.as_str()),
+                    }));
+                    Err(actix_web::error::InternalError::from_response("Insufficient permission", response).into())
+                }
+                Err(_) => Err(actix_web::error::ErrorInternalServerError("Failed to check permissions")),
+            }
+        })
+    }
+}
@@ -0,0 +1,152 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use crate::config::redis::RedisPool;
+use crate::config::rate_limit::{RateLimitConfig, RedisFailureMode};
+use crate::utils::quota::{self, QuotaAction, QuotaCharge, QuotaExceeded, QuotaLimit};
+use crate::utils::rate_limit;
+use crate::utils::request_cost::RequestCost;
+
+// Must run inside the rate and concurrency limiters and the scope check, so requests they reject
+// are neither counted against the quotas nor missing from `api_usage`
+pub struct QuotaGuard {
+    db_pool: sqlx::PgPool,
+    redis_client: RedisPool,
+}
+
+impl QuotaGuard {
+    pub fn new(db_pool: sqlx::PgPool, redis_client: RedisPool) -> Self {
+        Self { db_pool, redis_client }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for QuotaGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = QuotaGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(QuotaGuardMiddleware {
+            service: Rc::new(service),
+            db_pool: self.db_pool.clone(),
+            redis_client: self.redis_client.clone(),
+        })
+    }
+}
+
+pub struct QuotaGuardMiddleware<S> {
+    service: Rc<S>,
+    db_pool: sqlx::PgPool,
+    redis_client: RedisPool,
+}
+
+impl<S, B> Service<ServiceRequest> for QuotaGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let db_pool = self.db_pool.clone();
+        let redis_client = self.redis_client.clone();
+        let service = Rc::clone(&self.service);
+        // Attached by `ApiKeyValidator` when the plan of the caller has quotas
+        let charge = req.extensions().get::<QuotaCharge>().cloned();
+        let cost = req.extensions().get::<RequestCost>().map(|cost| cost.0).unwrap_or(1);
+        let failure_mode = req
+            .app_data::<web::Data<RateLimitConfig>>()
+            .map(|config| config.failure_mode)
+            .unwrap_or(RedisFailureMode::Local);
+
+        Box::pin(async move {
+            let charge = match charge {
+                Some(charge) => charge,
+                None => return service.call(req).await,
+            };
+
+            let exceeded = consume_quota(&db_pool, &redis_client, &charge, cost, failure_mode).await?;
+            if exceeded.is_some() {
+                req.extensions_mut().insert(QuotaExceeded { action: charge.action });
+            }
+
+            let mut res = service.call(req).await?;
+            if exceeded.is_some() && charge.action == QuotaAction::Overage {
+                res.headers_mut().insert(
+                    actix_web::http::header::HeaderName::from_static("x-quota-overage"),
+                    actix_web::http::header::HeaderValue::from_static("true"),
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Counts the units of the request against the quotas of its subject. Returns the quota exhausted by an accepted request,
+/// or the 429 to send when the action is to block.
+async fn consume_quota(
+    db_pool: &sqlx::PgPool,
+    redis_client: &RedisPool,
+    charge: &QuotaCharge,
+    cost: i32,
+    failure_mode: RedisFailureMode,
+) -> Result<Option<QuotaLimit>, Error> {
+    let QuotaCharge { subject, limits, action } = charge;
+    // Quotas cannot be counted in memory, they are left unchecked unless the failure mode is closed
+    let redis_error = |_: redis::RedisError| match failure_mode {
+        RedisFailureMode::Closed => Err(rate_limit::redis_unavailable()),
+        _ => Ok(None),
+    };
+
+    let decision = match quota::consume(redis_client, subject, limits, *action, cost as i64, None).await {
+        Err(e) => return redis_error(e),
+        Ok(Some(decision)) => decision,
+        // Counters missing from Redis start again from their last checkpoint
+        Ok(None) => {
+            let periods = limits.iter().map(|limit| limit.period.current()).collect::<Vec<_>>();
+            let checkpoint = quota::load_checkpoint(db_pool, subject, &periods)
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to load quota usage"))?;
+            match quota::consume(redis_client, subject, limits, *action, cost as i64, Some(&checkpoint)).await {
+                Err(e) => return redis_error(e),
+                Ok(decision) => decision
+                    .ok_or_else(|| actix_web::error::ErrorInternalServerError("Failed to count quota usage"))?,
+            }
+        }
+    };
+
+    match decision.exceeded {
+        Some(limit) if !decision.allowed => {
+            let resets_at = limit.period.resets_at();
+            let retry_after = (resets_at - time::OffsetDateTime::now_utc()).whole_seconds().max(1);
+            let response = HttpResponse::TooManyRequests()
+                .insert_header((actix_web::http::header::RETRY_AFTER, retry_after))
+                .json(serde_json::json!({
+                    "error": "quota_exceeded",
+                    "message": format!("The {} request quota of {} has been reached", limit.period.as_str(), limit.quota),
+                    "period": limit.period.as_str(),
+                    "quota": limit.quota,
+                    "resets_at": resets_at.unix_timestamp(),
+                }));
+            Err(actix_web::error::InternalError::from_response("Quota exceeded", response).into())
+        }
+        exceeded => Ok(exceeded),
+    }
+}
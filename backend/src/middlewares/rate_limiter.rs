@@ -1,32 +1,36 @@
 use std::pin::Pin;
+use std::rc::Rc;
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    web, Error, HttpMessage,
 };
-use redis::AsyncCommands;
 use futures::future::{ok, Ready};
 use std::task::{Context, Poll};
 use std::time::Duration;
+use crate::config::redis::RedisPool;
+use crate::config::rate_limit::{RateLimitConfig, RedisFailureMode};
+use crate::models::api_key::ApiIdentity;
+use crate::utils::plans::PlanRegistry;
+use crate::utils::quota::{self, QuotaAction, QuotaCharge};
+use crate::utils::rate_limit::{self, LocalRateLimiter};
+use crate::utils::request_cost::RequestCost;
+use crate::utils::tenants::Tenant;
 
+// Must run after `ApiKeyValidator`, requests are counted per API key, user, organization or IP
+// depending on the configured key strategy
 pub struct RateLimiter {
-    redis_client: redis::Client,
-    max_requests: u32,
-    window_size: Duration,
+    redis_client: RedisPool,
 }
 
 impl RateLimiter {
-    pub fn new(redis_client: redis::Client, max_requests: u32, window_size: Duration) -> Self {
-        Self {
-            redis_client,
-            max_requests,
-            window_size,
-        }
+    pub fn new(redis_client: RedisPool) -> Self {
+        Self { redis_client }
     }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for RateLimiter
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<B>;
@@ -37,24 +41,20 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(RateLimiterMiddleware {
-            service,
+            service: Rc::new(service),
             redis_client: self.redis_client.clone(),
-            max_requests: self.max_requests,
-            window_size: self.window_size
         })
     }
 }
 
 pub struct RateLimiterMiddleware<S> {
-    service: S,
-    redis_client: redis::Client,
-    max_requests: u32,
-    window_size: Duration,
+    service: Rc<S>,
+    redis_client: RedisPool,
 }
 
 impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
 {
     type Response = ServiceResponse<B>;
@@ -67,28 +67,76 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let redis_client = self.redis_client.clone();
-        let max_requests = self.max_requests;
-        let window_size = self.window_size;
+        let service = Rc::clone(&self.service);
+        let plans = req.app_data::<web::Data<PlanRegistry>>().cloned();
+        let db_pool = req.app_data::<web::Data<sqlx::PgPool>>().cloned();
+        let local_limiter = req.app_data::<web::Data<LocalRateLimiter>>().cloned();
+        let config = req
+            .app_data::<web::Data<RateLimitConfig>>()
+            .map(|config| config.get_ref().clone())
+            .unwrap_or_else(RateLimitConfig::from_env);
+        let default_limit = (config.max_requests, Duration::from_secs(config.window_secs.max(1)));
 
-        let connection_info = req.connection_info().clone();
-        let fut = self.service.call(req);
+        // Tenants can override the default limit, and are counted separately
+        let tenant = req.extensions().get::<Tenant>().cloned();
+        let tenant_limit = tenant.as_ref().and_then(|tenant| tenant.rate_limit());
+        let tenant_slug = tenant.map(|tenant| tenant.slug).unwrap_or_default();
+        let plan_id = req.extensions().get::<ApiIdentity>().and_then(|identity| identity.plan_id);
+        let charge = req.extensions().get::<QuotaCharge>().cloned();
+        // Requests count for the units set by `ApiKeyValidator`, one when it did not run
+        let cost = req.extensions().get::<RequestCost>().map(|cost| cost.0.max(0) as u32).unwrap_or(1);
+        let bucket = config.key_strategy.resolve(&req);
+        let failure_mode = config.failure_mode;
 
         Box::pin(async move {
-            let mut redis_conn = redis_client.get_multiplexed_async_connection().await.map_err(|_| {
-                actix_web::error::ErrorInternalServerError("Failed to connect to Redis")
-            })?;
-            let key = format!("rate_limiter:{}", connection_info.realip_remote_addr().unwrap());
-            let count: u32 = redis_conn.get(&key).await.unwrap_or(0);
-            let ip: String = connection_info.realip_remote_addr().unwrap().to_string();
+            let plan = match plans {
+                Some(plans) => plans.resolve(plan_id).await.map_err(|_| {
+                    actix_web::error::ErrorInternalServerError("Failed to load rate limit plan")
+                })?,
+                None => None,
+            };
+            // Once a quota of the plan is exhausted, the degraded limit replaces the regular one.
+            // The request is only charged by `QuotaGuard`, after this limit, so the counters are read here.
+            let degraded = match (&charge, &db_pool) {
+                (Some(charge), Some(db_pool)) if charge.action == QuotaAction::Degrade => {
+                    let exceeded = quota::peek_exceeded(&redis_client, db_pool, &charge.subject, &charge.limits, cost as i64).await;
+                    matches!(exceeded, Ok(Some(_)))
+                }
+                _ => false,
+            };
+            let plan_limit = plan.as_ref().map(|plan| match plan.degraded_rate_limit() {
+                Some(degraded_limit) if degraded => degraded_limit,
+                _ => plan.rate_limit(),
+            });
+            // The plan of the caller takes precedence over the tenant limit, which takes precedence over the default plan
+            let limit = if plan_id.is_some() || degraded {
+                plan_limit.or(tenant_limit)
+            } else {
+                tenant_limit.or(plan_limit)
+            };
+            let (max_requests, window_size) = limit.unwrap_or(default_limit);
+            let algorithm = plan.and_then(|plan| plan.algorithm()).unwrap_or(config.algorithm);
 
-            if count >= max_requests {
-                return Err(actix_web::error::ErrorTooManyRequests(format!("Rate limit exceeded for IP: {} - {} requests in {} seconds", ip, max_requests, window_size.as_secs())));
-            }
+            let bucket = format!("{}:{}", tenant_slug, bucket);
+            let decision = match rate_limit::check(&redis_client, algorithm, &bucket, max_requests, window_size, cost).await {
+                Ok(decision) => decision,
+                Err(_) => match (failure_mode, local_limiter) {
+                    (RedisFailureMode::Local, Some(local_limiter)) => {
+                        local_limiter.check(&bucket, max_requests, window_size, cost)
+                    }
+                    (RedisFailureMode::Closed, _) => return Err(rate_limit::redis_unavailable()),
+                    _ => return service.call(req).await,
+                },
+            };
 
-            let _: () = redis_conn.incr(&key, 1).await.unwrap();
-            let _: () = redis_conn.expire(&key, window_size.as_secs() as usize as i64).await.unwrap();
+            if !decision.allowed {
+                let response = decision.rejection(window_size);
+                return Err(actix_web::error::InternalError::from_response("Rate limit exceeded", response).into());
+            }
 
-            let res = fut.await?;
+            // Clients can pace themselves before hitting the limit
+            let mut res = service.call(req).await?;
+            decision.write_headers(res.headers_mut(), window_size);
             Ok(res)
         })
     }
@@ -0,0 +1,83 @@
+use std::pin::Pin;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use crate::models::api_key::ApiIdentity;
+use crate::utils::scopes::grants;
+
+// Must run after `ApiKeyValidator`, which attaches the scopes of the key to the request
+pub struct RequireScope {
+    scope: &'static str,
+}
+
+impl RequireScope {
+    pub fn new(scope: &'static str) -> Self {
+        Self { scope }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireScopeMiddleware {
+            service,
+            scope: self.scope,
+        })
+    }
+}
+
+pub struct RequireScopeMiddleware<S> {
+    service: S,
+    scope: &'static str,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let scope = self.scope;
+        let allowed = req
+            .extensions()
+            .get::<ApiIdentity>()
+            .is_some_and(|identity| grants(&identity.scopes, scope, req.method().as_str(), req.path()));
+
+        if !allowed {
+            let response = HttpResponse::Forbidden().json(serde_json::json!({
+                "error": "insufficient_scope",
+                "required_scope": scope,
+                "message": format!("This API key does not have the '{}' scope", scope),
+            }));
+            return Box::pin(async move {
+                Err(actix_web::error::InternalError::from_response("Insufficient scope", response).into())
+            });
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            Ok(res)
+        })
+    }
+}
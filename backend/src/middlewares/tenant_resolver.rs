@@ -0,0 +1,105 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::Uri,
+    web, Error, HttpMessage,
+};
+use futures::future::{ok, Ready};
+use std::task::{Context, Poll};
+use crate::config::trusted_proxies::TrustedProxies;
+use crate::utils::client_ip::request_host;
+use crate::utils::tenants::TenantRegistry;
+
+// Wraps the whole app: resolves the tenant before routing, so `/t/{slug}` prefixes can be stripped
+pub struct TenantResolver {
+    registry: web::Data<TenantRegistry>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl TenantResolver {
+    pub fn new(registry: web::Data<TenantRegistry>, trusted_proxies: TrustedProxies) -> Self {
+        Self { registry, trusted_proxies }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TenantResolver
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TenantResolverMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TenantResolverMiddleware {
+            service: Rc::new(service),
+            registry: self.registry.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        })
+    }
+}
+
+pub struct TenantResolverMiddleware<S> {
+    service: Rc<S>,
+    registry: web::Data<TenantRegistry>,
+    trusted_proxies: TrustedProxies,
+}
+
+impl<S, B> Service<ServiceRequest> for TenantResolverMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let registry = self.registry.clone();
+        let service = Rc::clone(&self.service);
+        let host = request_host(&req, &self.trusted_proxies);
+
+        Box::pin(async move {
+            let mut req = req;
+
+            let resolved = registry
+                .resolve(&host, req.path())
+                .await
+                .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to resolve tenant"))?;
+
+            let (tenant, path) = match resolved {
+                Some(resolved) => resolved,
+                None => return Err(actix_web::error::ErrorNotFound("Unknown tenant")),
+            };
+
+            if let Some(path) = path {
+                let path_and_query = match req.uri().query() {
+                    Some(query) => format!("{}?{}", path, query),
+                    None => path,
+                };
+                let uri = path_and_query
+                    .parse::<Uri>()
+                    .map_err(|_| actix_web::error::ErrorBadRequest("Invalid path"))?;
+
+                req.match_info_mut().get_mut().update(&uri);
+                req.head_mut().uri = uri;
+            }
+
+            // Tenants restricted to some API routes get a 404 for the others, as if they did not exist
+            if req.path().starts_with("/api/") && !tenant.allows_route(req.method().as_str(), req.path()) {
+                return Err(actix_web::error::ErrorNotFound("Not found"));
+            }
+
+            req.extensions_mut().insert(tenant);
+            service.call(req).await
+        })
+    }
+}
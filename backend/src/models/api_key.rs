@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+// Attached to the request extensions by `ApiKeyValidator` once the caller is authenticated.
+// Either the key or the client certificate fields are set, depending on how the caller authenticated.
+#[derive(Clone)]
+pub struct ApiIdentity {
+    pub user_id: i32,
+    pub key_id: Option<i32>,
+    pub key_prefix: Option<String>,
+    pub certificate_id: Option<i32>,
+    // Set when the key is owned by an organization
+    pub organization_id: Option<i32>,
+    pub scopes: Vec<String>,
+    // Plan of the organization owning the key, or of the user, used for rate limiting
+    pub plan_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub organization_id: Option<i32>,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub allowed_cidrs: Vec<String>,
+    pub allowed_origins: Vec<String>,
+    pub signing_enabled: bool,
+    pub require_signature: bool,
+    pub created_at: PrimitiveDateTime,
+    pub last_used_at: Option<PrimitiveDateTime>,
+    pub expires_at: Option<PrimitiveDateTime>,
+    pub rotated_at: Option<PrimitiveDateTime>,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    // Member who created the key, for organization keys
+    pub created_by: i32,
+    pub organization_id: Option<i32>,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub allowed_cidrs: Vec<String>,
+    pub allowed_origins: Vec<String>,
+    pub signing_enabled: bool,
+    pub require_signature: bool,
+    pub created_at: String, // String for formatted response
+    pub last_used_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub rotated_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            id: key.id,
+            created_by: key.user_id,
+            organization_id: key.organization_id,
+            name: key.name,
+            key_prefix: key.key_prefix,
+            scopes: key.scopes,
+            allowed_cidrs: key.allowed_cidrs,
+            allowed_origins: key.allowed_origins,
+            signing_enabled: key.signing_enabled,
+            require_signature: key.require_signature,
+            created_at: key.created_at.to_string(),
+            last_used_at: key.last_used_at.map(|t| t.to_string()),
+            expires_at: key.expires_at.map(|t| t.to_string()),
+            rotated_at: key.rotated_at.map(|t| t.to_string()),
+            revoked_at: key.revoked_at.map(|t| t.to_string()),
+        }
+    }
+}
+
+// Returned only once, when a key is created
+#[derive(Serialize)]
+pub struct CreatedApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub key_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<String>,
+    pub api_key: String,
+}
@@ -5,22 +5,29 @@ use time::PrimitiveDateTime;
 pub struct ApiUsage {
     pub id: i32,
     pub user_id: i32,
-    pub api_key: String,
+    pub api_key_id: Option<i32>,
+    pub api_key_prefix: Option<String>,
+    pub client_certificate_id: Option<i32>,
     pub request_path: String,
     pub request_method: String,
     pub request_time: PrimitiveDateTime,
     pub request_ip: String,
     pub status_code: i32,
+    pub cost: i32,
 }
 
 #[derive(Serialize)]
 pub struct ApiUsageResponse {
     pub id: i32,
     pub user_id: i32,
-    pub api_key: String,
+    pub api_key_id: Option<i32>,
+    pub api_key_prefix: Option<String>,
+    pub client_certificate_id: Option<i32>,
     pub request_path: String,
     pub request_method: String,
     pub request_time: String, // String for formatted response
     pub request_ip: String,
     pub status_code: i32,
+    // Units deducted from rate limits and quotas
+    pub cost: i32,
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use serde::Serialize;
+use time::PrimitiveDateTime;
+
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub tenant_id: Option<i32>,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub action: String,
+    pub ip: Option<String>,
+    pub details: Option<serde_json::Value>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogEntryResponse {
+    pub id: i64,
+    pub tenant_id: Option<i32>,
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub action: String,
+    pub ip: Option<String>,
+    pub details: Option<serde_json::Value>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub created_at: String,
+}
+
+impl From<AuditLogEntry> for AuditLogEntryResponse {
+    fn from(entry: AuditLogEntry) -> Self {
+        Self {
+            id: entry.id,
+            tenant_id: entry.tenant_id,
+            actor_user_id: entry.actor_user_id,
+            target_user_id: entry.target_user_id,
+            action: entry.action,
+            ip: entry.ip,
+            details: entry.details,
+            before: entry.before,
+            after: entry.after,
+            created_at: entry.created_at.to_string(),
+        }
+    }
+}
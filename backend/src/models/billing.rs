@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Invoice {
+    // `None` for invoices previewed before being generated
+    pub id: Option<i32>,
+    // `org:<id>` or `user:<id>`
+    pub subject: String,
+    pub organization_id: Option<i32>,
+    pub user_id: Option<i32>,
+    // Calendar month, e.g. `2026-01`
+    pub period: String,
+    pub plan_id: Option<i32>,
+    pub plan: String,
+    pub currency: String,
+    pub requests: i64,
+    // Requests weighted by the cost of their route
+    pub units: i64,
+    // Units billed beyond what the plan includes
+    pub overage_units: i64,
+    pub request_bytes: i64,
+    pub response_bytes: i64,
+    pub base_cents: i64,
+    pub overage_cents: i64,
+    pub transfer_cents: i64,
+    pub total_cents: i64,
+    // Set once the invoice is issued, it never changes afterwards
+    pub finalized_at: Option<String>,
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientCertificate {
+    pub id: i32,
+    pub user_id: i32,
+    pub fingerprint: Option<String>,
+    pub subject: Option<String>,
+    pub scopes: Vec<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_used_at: Option<PrimitiveDateTime>,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Serialize)]
+pub struct ClientCertificateResponse {
+    pub id: i32,
+    pub user_id: i32,
+    pub fingerprint: Option<String>,
+    pub subject: Option<String>,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+impl From<ClientCertificate> for ClientCertificateResponse {
+    fn from(certificate: ClientCertificate) -> Self {
+        Self {
+            id: certificate.id,
+            user_id: certificate.user_id,
+            fingerprint: certificate.fingerprint,
+            subject: certificate.subject,
+            scopes: certificate.scopes,
+            created_at: certificate.created_at.to_string(),
+            last_used_at: certificate.last_used_at.map(|t| t.to_string()),
+            revoked_at: certificate.revoked_at.map(|t| t.to_string()),
+        }
+    }
+}
@@ -1 +1,7 @@
-pub(crate) mod api_usage;
\ No newline at end of file
+pub(crate) mod api_usage;
+pub(crate) mod api_key;
+pub(crate) mod client_certificate;
+pub(crate) mod organization;
+pub(crate) mod audit_log;
+pub(crate) mod quota;
+pub(crate) mod billing;
\ No newline at end of file
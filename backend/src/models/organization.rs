@@ -0,0 +1,67 @@
+use serde::Serialize;
+
+// Role of a user inside an organization, ordered from least to most privileged
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrganizationRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl OrganizationRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrganizationRole::Member => "member",
+            OrganizationRole::Admin => "admin",
+            OrganizationRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "member" => Some(OrganizationRole::Member),
+            "admin" => Some(OrganizationRole::Admin),
+            "owner" => Some(OrganizationRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrganizationResponse {
+    pub id: i32,
+    pub name: String,
+    // Role of the current user, absent in the administrator listing
+    pub role: Option<String>,
+    pub monthly_request_quota: Option<i64>,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationMemberResponse {
+    pub user_id: i32,
+    pub name: String,
+    pub email: String,
+    pub role: String,
+    pub joined_at: String,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationInvitationResponse {
+    pub id: i32,
+    pub email: String,
+    pub role: String,
+    pub expires_at: String,
+    pub accepted_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationUsageResponse {
+    pub day: String,
+    pub api_key_id: Option<i32>,
+    pub requests: i64,
+    // Requests weighted by their cost
+    pub units: i64,
+    pub errors: i64,
+}
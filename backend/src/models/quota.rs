@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct QuotaPeriodResponse {
+    // `daily` or `monthly`
+    pub period: &'static str,
+    // The day or month being counted, e.g. `2026-01-31` or `2026-01`
+    pub current: String,
+    pub quota: i64,
+    pub used: i64,
+    pub remaining: i64,
+    // Requests accepted beyond the quota, with the `overage` action
+    pub overage: i64,
+    // Unix timestamp in seconds
+    pub resets_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct QuotaStatusResponse {
+    // `org:<id>`, `key:<id>` or `user:<id>` for client certificates
+    pub subject: String,
+    pub name: String,
+    pub plan: Option<String>,
+    pub action: &'static str,
+    pub exhausted: bool,
+    // Empty when the subject has no quota
+    pub periods: Vec<QuotaPeriodResponse>,
+}
@@ -0,0 +1,568 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
+use actix_web::web::ServiceConfig;
+use serde::Deserialize;
+use sqlx::{PgConnection, PgPool};
+use crate::config::api_keys::ApiKeyConfig;
+use crate::models::api_key::{ApiKey, ApiKeyResponse, CreatedApiKeyResponse};
+use crate::utils::api_key::{generate_api_key, signing_secret};
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::client_ip::parse_cidr;
+use crate::utils::scopes::{validate_scopes, ALL_SCOPES, KNOWN_SCOPES};
+use crate::utils::permissions::Permission;
+use crate::middlewares::impersonation_guard::DenyImpersonation;
+use crate::middlewares::permission_guard::RequirePermission;
+
+pub fn configure_api_key_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("api_keys")
+            .wrap(DenyImpersonation::writes())
+            .route("/scopes", web::get().to(list_scopes))
+            .route("/{id}/rotate", web::post().to(rotate_api_key).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("/{id}/restrictions", web::put().to(update_restrictions).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("/{id}/signing", web::post().to(enable_signing).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("/{id}/signing", web::delete().to(disable_signing).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("/{id}", web::delete().to(revoke_api_key).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("", web::post().to(create_api_key).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("", web::get().to(list_api_keys))
+    );
+}
+
+pub const DEFAULT_KEY_NAME: &str = "default";
+
+pub fn default_scopes() -> Vec<String> {
+    vec![ALL_SCOPES.to_string()]
+}
+
+/// Parses a comma separated list of scopes, as accepted in query strings.
+pub fn parse_scopes(scopes: &str) -> Vec<String> {
+    scopes
+        .split(',')
+        .map(|scope| scope.trim().to_string())
+        .filter(|scope| !scope.is_empty())
+        .collect()
+}
+
+/// Inserts a new active key for the user, owned by the organization if any, and returns it with its full value.
+/// The key never expires when `expires_in_secs` is `None`.
+pub async fn insert_api_key(
+    conn: &mut PgConnection,
+    user_id: i32,
+    organization_id: Option<i32>,
+    name: &str,
+    scopes: &[String],
+    expires_in_secs: Option<f64>,
+) -> Result<CreatedApiKeyResponse, sqlx::Error> {
+    let api_key = generate_api_key();
+
+    let record = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (user_id, organization_id, name, key_prefix, key_hash, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+        RETURNING id, expires_at
+        "#,
+        user_id,
+        organization_id,
+        name,
+        api_key.prefix,
+        api_key.hash,
+        scopes,
+        expires_in_secs
+    )
+        .fetch_one(&mut *conn)
+        .await?;
+
+    Ok(CreatedApiKeyResponse {
+        id: record.id,
+        name: name.to_string(),
+        key_prefix: api_key.prefix,
+        scopes: scopes.to_vec(),
+        expires_at: record.expires_at.map(|t| t.to_string()),
+        api_key: api_key.key,
+    })
+}
+
+/// Carries the IP and origin restrictions and the signing settings of a replaced key over to the key issued
+/// in its place, so rotating or refreshing a restricted key never leaves it unrestricted.
+pub async fn copy_key_restrictions(conn: &mut PgConnection, old_key_id: i32, new_key_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE api_keys
+        SET allowed_cidrs = old_key.allowed_cidrs, allowed_origins = old_key.allowed_origins,
+            signing_enabled = old_key.signing_enabled, require_signature = old_key.require_signature
+        FROM api_keys AS old_key
+        WHERE api_keys.id = $2 AND old_key.id = $1
+        "#,
+        old_key_id,
+        new_key_id
+    )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// What the audit log keeps of a newly issued key, never the key itself.
+pub fn key_snapshot(created: &CreatedApiKeyResponse) -> serde_json::Value {
+    serde_json::json!({
+        "id": created.id,
+        "name": created.name,
+        "key_prefix": created.key_prefix,
+        "scopes": created.scopes,
+        "expires_at": created.expires_at,
+    })
+}
+
+/// Revokes the active keys with the given name, if any, and issues a new one in its place.
+/// The new key keeps the scopes of the replaced one unless `scopes` is given, and always its restrictions and lifetime.
+/// Returns the prefixes of the revoked keys along with the new key.
+pub async fn replace_api_key(
+    db_pool: &PgPool,
+    user_id: i32,
+    name: &str,
+    scopes: Option<Vec<String>>,
+) -> Result<(Vec<String>, CreatedApiKeyResponse), sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    // Also revokes a previous key of the same name still in its rotation grace period
+    let replaced = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = now()
+        WHERE user_id = $1 AND name = $2 AND organization_id IS NULL AND revoked_at IS NULL
+        RETURNING id, key_prefix, scopes, rotated_at, EXTRACT(EPOCH FROM expires_at - created_at)::FLOAT8 AS lifetime_secs
+        "#,
+        user_id,
+        name
+    )
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let replaced_prefixes = replaced.iter().map(|key| key.key_prefix.clone()).collect();
+    let current = replaced.iter().find(|key| key.rotated_at.is_none());
+    let scopes = scopes
+        .or_else(|| current.map(|key| key.scopes.clone()))
+        .unwrap_or_else(default_scopes);
+
+    let lifetime_secs = current.and_then(|key| key.lifetime_secs);
+
+    let created = insert_api_key(&mut tx, user_id, None, name, &scopes, lifetime_secs).await?;
+    if let Some(current) = current {
+        copy_key_restrictions(&mut tx, current.id, created.id).await?;
+    }
+    tx.commit().await?;
+
+    Ok((replaced_prefixes, created))
+}
+
+pub async fn list_api_keys(db_pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let result = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id, user_id, organization_id, name, key_prefix, scopes, allowed_cidrs, allowed_origins, signing_enabled, require_signature, created_at, last_used_at, expires_at, rotated_at, revoked_at
+        FROM api_keys
+        WHERE user_id = $1 AND organization_id IS NULL
+        ORDER BY revoked_at IS NOT NULL, created_at DESC
+        "#,
+        user_id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(keys) => HttpResponse::Ok().json(keys.into_iter().map(ApiKeyResponse::from).collect::<Vec<_>>()),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_scopes() -> impl Responder {
+    let scopes: Vec<_> = KNOWN_SCOPES
+        .iter()
+        .map(|(name, description)| serde_json::json!({ "name": name, "description": description }))
+        .collect();
+
+    HttpResponse::Ok().json(scopes)
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Option<Vec<String>>,
+    // The key never expires when omitted
+    pub expires_in_days: Option<u32>,
+}
+
+impl NewApiKey {
+    /// Returns the trimmed name, the scopes and the lifetime in seconds of the key to create.
+    pub fn validate(&self) -> Result<(String, Vec<String>, Option<f64>), String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err("Key name must be between 1 and 64 characters.".to_string());
+        }
+
+        let scopes = self.scopes.clone().unwrap_or_else(default_scopes);
+        validate_scopes(&scopes)?;
+
+        let expires_in_secs = self.expires_in_days.map(|days| days as f64 * 24.0 * 60.0 * 60.0);
+        Ok((name.to_string(), scopes, expires_in_secs))
+    }
+}
+
+pub async fn create_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    new_key: web::Json<NewApiKey>,
+) -> impl Responder {
+    let user_id = match req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let (name, scopes, expires_in_secs) = match new_key.validate() {
+        Ok(validated) => validated,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let mut conn = match db_pool.acquire().await {
+        Ok(conn) => conn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    match insert_api_key(&mut conn, user_id, None, &name, &scopes, expires_in_secs).await {
+        Ok(created) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: Some(user_id),
+                action: "api_key.created",
+                ip: actor_ip,
+                details: None,
+                before: None,
+                after: Some(key_snapshot(&created)),
+            }).await;
+
+            HttpResponse::Created().json(created)
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("An active key with this name already exists.")
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn revoke_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let result = sqlx::query!(
+        "UPDATE api_keys SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND organization_id IS NULL AND revoked_at IS NULL RETURNING name, key_prefix",
+        id,
+        user_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(key)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: actor_user_id,
+                action: "api_key.revoked",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "key_id": id })),
+                before: Some(serde_json::json!({ "name": key.name, "key_prefix": key.key_prefix })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct RotateQuery {
+    pub grace_period_secs: Option<u64>,
+}
+
+pub async fn rotate_api_key(
+    db_pool: web::Data<PgPool>,
+    config: web::Data<ApiKeyConfig>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<RotateQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = match req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+    let grace_period_secs = query
+        .grace_period_secs
+        .unwrap_or(config.rotation_grace_period_secs)
+        .min(config.max_rotation_grace_period_secs) as f64;
+
+    let rotated = async {
+        let mut tx = db_pool.begin().await?;
+
+        // The old key keeps working until the end of the grace period, or its own expiration if sooner
+        let old_key = sqlx::query!(
+            r#"
+            WITH old_key AS (
+                SELECT id, expires_at - created_at AS lifetime
+                FROM api_keys
+                WHERE id = $1 AND user_id = $2 AND organization_id IS NULL AND revoked_at IS NULL AND rotated_at IS NULL
+                FOR UPDATE
+            )
+            UPDATE api_keys
+            SET rotated_at = now(), expires_at = LEAST(api_keys.expires_at, now() + make_interval(secs => $3))
+            FROM old_key
+            WHERE api_keys.id = old_key.id
+            RETURNING api_keys.name, api_keys.key_prefix, api_keys.scopes, api_keys.expires_at,
+                EXTRACT(EPOCH FROM old_key.lifetime)::FLOAT8 AS lifetime_secs
+            "#,
+            id,
+            user_id,
+            grace_period_secs
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let old_key = match old_key {
+            Some(old_key) => old_key,
+            None => return Ok(None),
+        };
+
+        // A key created with an expiration is replaced by a key with the same lifetime
+        let created = insert_api_key(&mut tx, user_id, None, &old_key.name, &old_key.scopes, old_key.lifetime_secs).await?;
+        copy_key_restrictions(&mut tx, id, created.id).await?;
+        tx.commit().await?;
+
+        let before = serde_json::json!({
+            "id": id,
+            "key_prefix": old_key.key_prefix,
+            "expires_at": old_key.expires_at.map(|t| t.to_string()),
+        });
+        Ok::<_, sqlx::Error>(Some((before, created)))
+    }.await;
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    match rotated {
+        Ok(Some((before, created))) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: Some(user_id),
+                action: "api_key.rotated",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "grace_period_secs": grace_period_secs })),
+                before: Some(before),
+                after: Some(key_snapshot(&created)),
+            }).await;
+
+            HttpResponse::Ok().json(created)
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct KeyRestrictions {
+    // Source addresses or CIDR ranges the key can be used from
+    pub allowed_cidrs: Vec<String>,
+    // Origin patterns, e.g. `https://*.example.com`, matched against `Origin` or `Referer`
+    pub allowed_origins: Vec<String>,
+}
+
+pub async fn update_restrictions(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    restrictions: web::Json<KeyRestrictions>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let allowed_cidrs = match restrictions
+        .allowed_cidrs
+        .iter()
+        .map(|cidr| parse_cidr(cidr.trim()).map(|net| net.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(cidrs) => cidrs,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let allowed_origins: Vec<String> = restrictions
+        .allowed_origins
+        .iter()
+        .map(|origin| origin.trim().trim_end_matches('/').to_lowercase())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+
+    if let Some(origin) = allowed_origins.iter().find(|origin| !origin.contains("://")) {
+        return HttpResponse::BadRequest().body(format!("Invalid origin pattern '{}', expected 'scheme://host'", origin));
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_key AS (
+            SELECT id, allowed_cidrs, allowed_origins
+            FROM api_keys
+            WHERE id = $3 AND user_id = $4 AND organization_id IS NULL AND revoked_at IS NULL
+            FOR UPDATE
+        )
+        UPDATE api_keys SET allowed_cidrs = $1, allowed_origins = $2
+        FROM old_key
+        WHERE api_keys.id = old_key.id
+        RETURNING old_key.allowed_cidrs AS "old_cidrs!", old_key.allowed_origins AS "old_origins!"
+        "#,
+        &allowed_cidrs[..],
+        &allowed_origins[..],
+        id,
+        user_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(old_key)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: actor_user_id,
+                action: "api_key.restrictions_changed",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "key_id": id })),
+                before: Some(serde_json::json!({ "allowed_cidrs": old_key.old_cidrs, "allowed_origins": old_key.old_origins })),
+                after: Some(serde_json::json!({ "allowed_cidrs": allowed_cidrs, "allowed_origins": allowed_origins })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EnableSigning {
+    // Rejects plain `x-api-key` requests once enabled
+    #[serde(default)]
+    pub require_signature: bool,
+}
+
+pub async fn enable_signing(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<EnableSigning>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys SET signing_enabled = TRUE, require_signature = $1
+        WHERE id = $2 AND user_id = $3 AND organization_id IS NULL AND revoked_at IS NULL
+        RETURNING key_hash
+        "#,
+        body.require_signature,
+        id,
+        user_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    // The secret is derived from the key, it changes when the key is rotated or refreshed
+    match result {
+        Ok(Some(key)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: actor_user_id,
+                action: "api_key.signing_enabled",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "key_id": id })),
+                before: None,
+                after: Some(serde_json::json!({ "signing_enabled": true, "require_signature": body.require_signature })),
+            }).await;
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "key_id": id,
+                "signing_secret": signing_secret(&key.key_hash),
+                "require_signature": body.require_signature,
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn disable_signing(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let result = sqlx::query!(
+        "UPDATE api_keys SET signing_enabled = FALSE, require_signature = FALSE WHERE id = $1 AND user_id = $2 AND organization_id IS NULL AND revoked_at IS NULL",
+        id,
+        user_id
+    )
+        .execute(&**db_pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: actor_user_id,
+                action: "api_key.signing_disabled",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "key_id": id })),
+                before: None,
+                after: Some(serde_json::json!({ "signing_enabled": false, "require_signature": false })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,115 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::web::ServiceConfig;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::models::audit_log::{AuditLogEntry, AuditLogEntryResponse};
+use crate::utils::permissions::Permission;
+use crate::utils::tenants::admin_tenant_scope;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+pub fn configure_audit_log_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/audit_log", web::get().to(list_audit_log).wrap(RequirePermission::new(Permission::AuditLogRead)));
+}
+
+#[derive(Deserialize)]
+pub struct AuditLogQuery {
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    // An exact action such as `user.deleted`, or every action of a kind with `user.*`
+    pub action: Option<String>,
+    // Unix timestamps in seconds
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    // Slug of another tenant, or `all`, for superadmins
+    pub tenant: Option<String>,
+    // Starts at 1
+    pub page: Option<u32>,
+    pub per_page: Option<u32>,
+}
+
+pub async fn list_audit_log(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<AuditLogQuery>,
+) -> impl Responder {
+    let tenant_id = match admin_tenant_scope(&db_pool, &req, query.tenant.as_deref()).await {
+        Ok(tenant_id) => tenant_id,
+        Err(response) => return response,
+    };
+
+    let (action, action_prefix) = match query.action.as_deref().map(str::trim) {
+        Some(action) if action.ends_with('*') => (None, Some(action.trim_end_matches('*').to_string())),
+        Some(action) if !action.is_empty() => (Some(action.to_string()), None),
+        _ => (None, None),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = (page as i64 - 1) * per_page as i64;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM audit_log
+        WHERE ($1::INTEGER IS NULL OR tenant_id = $1)
+            AND ($2::INTEGER IS NULL OR actor_user_id = $2)
+            AND ($3::INTEGER IS NULL OR target_user_id = $3)
+            AND ($4::TEXT IS NULL OR action = $4)
+            AND ($5::TEXT IS NULL OR starts_with(action, $5))
+            AND ($6::BIGINT IS NULL OR created_at >= to_timestamp($6)::TIMESTAMP)
+            AND ($7::BIGINT IS NULL OR created_at < to_timestamp($7)::TIMESTAMP)
+        "#,
+        tenant_id,
+        query.actor_user_id,
+        query.target_user_id,
+        action,
+        action_prefix,
+        query.since,
+        query.until
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    let entries = sqlx::query_as!(
+        AuditLogEntry,
+        r#"
+        SELECT id, tenant_id, actor_user_id, target_user_id, action, ip, details, before, after, created_at
+        FROM audit_log
+        WHERE ($1::INTEGER IS NULL OR tenant_id = $1)
+            AND ($2::INTEGER IS NULL OR actor_user_id = $2)
+            AND ($3::INTEGER IS NULL OR target_user_id = $3)
+            AND ($4::TEXT IS NULL OR action = $4)
+            AND ($5::TEXT IS NULL OR starts_with(action, $5))
+            AND ($6::BIGINT IS NULL OR created_at >= to_timestamp($6)::TIMESTAMP)
+            AND ($7::BIGINT IS NULL OR created_at < to_timestamp($7)::TIMESTAMP)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $8 OFFSET $9
+        "#,
+        tenant_id,
+        query.actor_user_id,
+        query.target_user_id,
+        action,
+        action_prefix,
+        query.since,
+        query.until,
+        per_page as i64,
+        offset
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match (total, entries) {
+        (Ok(total), Ok(entries)) => HttpResponse::Ok().json(serde_json::json!({
+            "entries": entries.into_iter().map(AuditLogEntryResponse::from).collect::<Vec<_>>(),
+            "page": page,
+            "per_page": per_page,
+            "total": total,
+        })),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Error fetching audit log: {:?}", e);
+            HttpResponse::InternalServerError().body("Error fetching audit log")
+        }
+    }
+}
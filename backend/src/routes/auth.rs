@@ -1,4 +1,4 @@
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
 use serde::{Deserialize};
 use argon2::{
@@ -9,9 +9,22 @@ use argon2::{
     Argon2
 };
 
-use super::user::generate_api_key;
-use super::user::User;
+use crate::config::redis::RedisPool;
+use crate::config::login_guard::LoginGuardConfig;
+use crate::config::mailer::app_base_url;
+use crate::config::password_policy::PasswordPolicy;
+use super::api_keys::{default_scopes, insert_api_key, DEFAULT_KEY_NAME};
+use crate::models::api_key::CreatedApiKeyResponse;
+use crate::utils::audit::{self, AuditEntry};
+use crate::utils::client_ip::request_client_ip;
+use crate::utils::impersonation::request_impersonation;
 use crate::utils::jwt::create_jwt;
+use crate::utils::login_guard::{self, FailureOutcome, LoginBlock};
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::password_policy::{validate_password, violations_response};
+use crate::utils::permissions::user_permissions;
+use crate::utils::tenants::request_tenant_id;
+use crate::utils::tokens::{consume_token, issue_token, TokenPurpose};
 
 pub async fn hash_password(password: &str) -> String {
     let argon2 = Argon2::default();
@@ -38,18 +51,34 @@ pub struct RegisterRequest {
     pub password: String,
 }
 
-pub async fn register(db_pool: web::Data<PgPool>, req: web::Json<RegisterRequest>) -> impl Responder {
-    let hashed_password = hash_password(&req.password).await;
-    let api_key = generate_api_key().await;
+pub async fn register(
+    db_pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    password_policy: web::Data<PasswordPolicy>,
+    http_req: HttpRequest,
+    req: web::Json<RegisterRequest>,
+) -> impl Responder {
+    let tenant_id = match request_tenant_id(&http_req) {
+        Some(tenant_id) => tenant_id,
+        None => return HttpResponse::NotFound().body("Unknown tenant"),
+    };
 
     let regex = regex::Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$").unwrap();
     if !regex.is_match(&req.email) {
         return HttpResponse::BadRequest().body("Invalid email address.");
     }
 
+    let violations = validate_password(&password_policy, &req.password, &req.name, &req.email).await;
+    if !violations.is_empty() {
+        return violations_response(violations);
+    }
+
+    let hashed_password = hash_password(&req.password).await;
+
     let user = sqlx::query!(
-        "SELECT id FROM users WHERE email = $1",
-        req.email
+        "SELECT id FROM users WHERE email = $1 AND tenant_id = $2",
+        req.email,
+        tenant_id
     )
         .fetch_optional(db_pool.get_ref())
         .await;
@@ -58,20 +87,28 @@ pub async fn register(db_pool: web::Data<PgPool>, req: web::Json<RegisterRequest
         return HttpResponse::Conflict().body("User with this email already exists.");
     }
 
-    let result = sqlx::query!(
-        "INSERT INTO users (name, email, password_hash, api_key, permission)
-         VALUES ($1, $2, $3, $4, $5)",
+    // The account gets its API key once the email address is verified, see `activate_account`
+    let registered = sqlx::query!(
+        "INSERT INTO users (name, email, password_hash, email_verified, tenant_id)
+         VALUES ($1, $2, $3, FALSE, $4)
+         RETURNING id",
         req.name,
         req.email,
         hashed_password,
-        api_key,
-        0 // Default permission level
+        tenant_id
     )
-        .execute(db_pool.get_ref())
+        .fetch_one(db_pool.get_ref())
         .await;
 
-    match result {
-        Ok(_) => HttpResponse::Ok().body("User registered successfully."),
+    match registered {
+        Ok(record) => {
+            if let Err(e) = send_verification_email(db_pool.get_ref(), mailer.get_ref(), record.id, &req.email).await {
+                eprintln!("Error sending verification email: {}", e);
+            }
+            HttpResponse::Ok().json(serde_json::json!({
+                "message": "User registered successfully. Please check your email to verify your account.",
+            }))
+        }
         Err(e) => {
             eprintln!("Error: {}", e);
             HttpResponse::InternalServerError().body("Registration failed.")
@@ -79,35 +116,371 @@ pub async fn register(db_pool: web::Data<PgPool>, req: web::Json<RegisterRequest
     }
 }
 
+async fn send_verification_email(db_pool: &PgPool, mailer: &dyn Mailer, user_id: i32, email: &str) -> Result<(), String> {
+    let token = issue_token(db_pool, user_id, TokenPurpose::EmailVerification)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    mailer.send(&Email {
+        to: email.to_string(),
+        subject: "Verify your GateKeeper account".to_string(),
+        body: format!(
+            "Welcome to GateKeeper!\n\nPlease verify your email address by opening the following link:\n{}/verify_email?token={}\n\nThis link expires in 24 hours.",
+            app_base_url(),
+            token
+        ),
+    }).await
+}
+
 #[derive(Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
-pub async fn login(db_pool: web::Data<PgPool>, req: web::Json<LoginRequest>) -> impl Responder {
-    let user = sqlx::query_as!(
-        User,
-        "SELECT id, name, email, api_key, permission, password_hash FROM users WHERE email = $1",
-        req.email
+pub async fn login(
+    db_pool: web::Data<PgPool>,
+    redis_client: web::Data<RedisPool>,
+    guard_config: web::Data<LoginGuardConfig>,
+    http_req: HttpRequest,
+    req: web::Json<LoginRequest>,
+) -> impl Responder {
+    // Forwarding headers are only trusted from known proxies, so rotating them cannot escape the IP lockout
+    let ip = request_client_ip(&http_req)
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let tenant_id = request_tenant_id(&http_req);
+
+    // Redis being unavailable must not prevent users from logging in
+    match login_guard::check(redis_client.get_ref(), tenant_id, &req.email, &ip).await {
+        Ok(Some(LoginBlock::Locked { retry_after })) => {
+            return HttpResponse::Locked()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many failed login attempts. Try again later.");
+        }
+        Ok(Some(LoginBlock::Delayed { retry_after })) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body("Too many failed login attempts. Try again later.");
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Error checking login attempts: {}", e),
+    }
+
+    let user = sqlx::query!(
+        "SELECT id, tenant_id, password_hash, email_verified FROM users WHERE email = $1 AND tenant_id = $2",
+        req.email,
+        tenant_id
     )
         .fetch_optional(db_pool.get_ref())
         .await;
 
+    let user = match user {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let authenticated = match &user {
+        Some(user) => verify_password(&req.password, &user.password_hash).await,
+        None => false,
+    };
+
     match user {
-        Ok(Some(user)) => {
-            if verify_password(&req.password, &user.password_hash).await {
-                let token = create_jwt(&user.id.to_string());
-                HttpResponse::Ok().json(token)
-            } else {
-                HttpResponse::Unauthorized().finish()
+        Some(user) if authenticated => {
+            if let Err(e) = login_guard::clear_account(redis_client.get_ref(), tenant_id, &req.email).await {
+                eprintln!("Error clearing login attempts: {}", e);
+            }
+            if !user.email_verified {
+                return HttpResponse::Forbidden().body("Email address not verified.");
             }
+            let token = create_jwt(&user.id.to_string(), user.tenant_id);
+            HttpResponse::Ok().json(token)
+        }
+        user => {
+            // Unknown emails are counted too, so lockouts do not reveal which accounts exist
+            match login_guard::record_failure(redis_client.get_ref(), guard_config.get_ref(), tenant_id, &req.email, &ip).await {
+                Ok(FailureOutcome::AccountLocked) => {
+                    audit::record(db_pool.get_ref(), AuditEntry {
+                        actor_user_id: None,
+                        target_user_id: user.map(|u| u.id),
+                        action: "login.account_locked",
+                        ip: Some(ip),
+                        details: Some(serde_json::json!({ "email": req.email })),
+                        before: None,
+                        after: None,
+                    }).await;
+                }
+                Ok(FailureOutcome::IpLocked) => {
+                    audit::record(db_pool.get_ref(), AuditEntry {
+                        actor_user_id: None,
+                        target_user_id: None,
+                        action: "login.ip_locked",
+                        ip: Some(ip),
+                        details: None,
+                        before: None,
+                        after: None,
+                    }).await;
+                }
+                Ok(FailureOutcome::Counted) => {}
+                Err(e) => eprintln!("Error recording failed login attempt: {}", e),
+            }
+            HttpResponse::Unauthorized().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub token: String,
+}
+
+// Marks the email address as verified and issues the default API key of accounts that have none yet,
+// so accounts cannot call the API before proving they own their address
+async fn activate_account(conn: &mut PgConnection, user_id: i32) -> Result<Option<CreatedApiKeyResponse>, sqlx::Error> {
+    let activated = sqlx::query!(
+        r#"
+        UPDATE users SET email_verified = TRUE
+        WHERE id = $1 AND NOT email_verified
+        RETURNING NOT EXISTS (SELECT 1 FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL) AS "needs_key!"
+        "#,
+        user_id
+    )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+    match activated {
+        Some(activated) if activated.needs_key => {
+            Ok(Some(insert_api_key(conn, user_id, None, DEFAULT_KEY_NAME, &default_scopes(), None).await?))
         }
-        Ok(None) => HttpResponse::Unauthorized().finish(),
+        _ => Ok(None),
+    }
+}
+
+pub async fn verify_email(db_pool: web::Data<PgPool>, req: web::Json<TokenRequest>) -> impl Responder {
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let user_id = match consume_token(&mut *tx, &req.token, TokenPurpose::EmailVerification).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired token."),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let created = match activate_account(&mut tx, user_id).await {
+        Ok(created) => created,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    match tx.commit().await {
+        // The full key is only returned here, it cannot be retrieved afterwards
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
+            "message": "Email address verified.",
+            "api_key": created.map(|created| created.api_key),
+        })),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
+#[derive(Deserialize)]
+pub struct EmailRequest {
+    pub email: String,
+}
+
+// Always answers with the same response so it cannot be used to find registered emails
+pub async fn resend_verification(
+    db_pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    http_req: HttpRequest,
+    req: web::Json<EmailRequest>,
+) -> impl Responder {
+    let user = sqlx::query!(
+        "SELECT id, email FROM users WHERE email = $1 AND tenant_id = $2 AND email_verified = FALSE",
+        req.email,
+        request_tenant_id(&http_req)
+    )
+        .fetch_optional(db_pool.get_ref())
+        .await;
+
+    if let Ok(Some(user)) = user {
+        if let Err(e) = send_verification_email(db_pool.get_ref(), mailer.get_ref(), user.id, &user.email).await {
+            eprintln!("Error sending verification email: {}", e);
+        }
+    }
+
+    HttpResponse::Ok().body("If this account exists and is not verified, a new verification email has been sent.")
+}
+
+// Always answers with the same response so it cannot be used to find registered emails
+pub async fn request_password_reset(
+    db_pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    http_req: HttpRequest,
+    req: web::Json<EmailRequest>,
+) -> impl Responder {
+    let user = sqlx::query!(
+        "SELECT id, email FROM users WHERE email = $1 AND tenant_id = $2",
+        req.email,
+        request_tenant_id(&http_req)
+    )
+        .fetch_optional(db_pool.get_ref())
+        .await;
+
+    if let Ok(Some(user)) = user {
+        let sent = match issue_token(db_pool.get_ref(), user.id, TokenPurpose::PasswordReset).await {
+            Ok(token) => mailer.send(&Email {
+                to: user.email,
+                subject: "Reset your GateKeeper password".to_string(),
+                body: format!(
+                    "A password reset was requested for your account.\n\nOpen the following link to choose a new password:\n{}/reset_password?token={}\n\nThis link expires in 30 minutes. If you did not request it, you can ignore this email.",
+                    app_base_url(),
+                    token
+                ),
+            }).await,
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = sent {
+            eprintln!("Error sending password reset email: {}", e);
+        }
+    }
+
+    HttpResponse::Ok().body("If this account exists, a password reset email has been sent.")
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmPasswordResetRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+pub async fn confirm_password_reset(
+    db_pool: web::Data<PgPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    req: web::Json<ConfirmPasswordResetRequest>,
+) -> impl Responder {
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let user_id = match consume_token(&mut *tx, &req.token, TokenPurpose::PasswordReset).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return HttpResponse::BadRequest().body("Invalid or expired token."),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let user = match sqlx::query!("SELECT name, email FROM users WHERE id = $1", user_id)
+        .fetch_one(&mut *tx)
+        .await
+    {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // Returning before the commit rolls back the token consumption, so the user can retry with the same link
+    let violations = validate_password(&password_policy, &req.new_password, &user.name, &user.email).await;
+    if !violations.is_empty() {
+        return violations_response(violations);
+    }
+
+    let hashed_password = hash_password(&req.new_password).await;
+
+    let result = sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE id = $2",
+        hashed_password,
+        user_id
+    )
+        .execute(&mut *tx)
+        .await;
+
+    if result.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // Receiving the reset email also proves ownership of the address
+    if activate_account(&mut tx, user_id).await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().body("Password has been reset."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+pub async fn change_password(
+    db_pool: web::Data<PgPool>,
+    password_policy: web::Data<PasswordPolicy>,
+    http_req: HttpRequest,
+    req: web::Json<ChangePasswordRequest>,
+) -> impl Responder {
+    let user_id = match http_req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let user = sqlx::query!(
+        "SELECT name, email, password_hash FROM users WHERE id = $1",
+        user_id
+    )
+        .fetch_optional(db_pool.get_ref())
+        .await;
+
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if user.password_hash.is_empty() || !verify_password(&req.current_password, &user.password_hash).await {
+        return HttpResponse::Unauthorized().body("Current password is incorrect.");
+    }
+
+    let violations = validate_password(&password_policy, &req.new_password, &user.name, &user.email).await;
+    if !violations.is_empty() {
+        return violations_response(violations);
+    }
+
+    let hashed_password = hash_password(&req.new_password).await;
+
+    let result = sqlx::query!("UPDATE users SET password_hash = $1 WHERE id = $2",
+        hashed_password,
+        user_id
+    )
+        .execute(db_pool.get_ref())
+        .await;
+
+    if result.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let ip = request_client_ip(&http_req).map(|ip| ip.to_string());
+    audit::record(db_pool.get_ref(), AuditEntry {
+        actor_user_id: Some(user_id),
+        target_user_id: Some(user_id),
+        action: "user.password_changed",
+        ip,
+        details: None,
+        before: None,
+        after: None,
+    }).await;
+
+    HttpResponse::NoContent().finish()
+}
+
 pub async fn verify(db_pool: web::Data<PgPool>, req: HttpRequest, ) -> impl Responder {
     let user_id = req
         .extensions()
@@ -116,7 +489,12 @@ pub async fn verify(db_pool: web::Data<PgPool>, req: HttpRequest, ) -> impl Resp
         .and_then(|id| id.parse::<i32>().ok());
 
     let user = sqlx::query!(
-        "SELECT id, name, email, api_key, permission, password_hash FROM users WHERE id = $1",
+        r#"
+        SELECT id, name, email, role,
+            (SELECT key_prefix FROM api_keys WHERE api_keys.user_id = users.id AND revoked_at IS NULL ORDER BY created_at DESC LIMIT 1) AS api_key_prefix
+        FROM users
+        WHERE id = $1
+        "#,
         user_id
     )
         .fetch_optional(db_pool.get_ref())
@@ -124,11 +502,32 @@ pub async fn verify(db_pool: web::Data<PgPool>, req: HttpRequest, ) -> impl Resp
 
     match user {
         Ok(Some(record)) => {
+            let permissions = match user_permissions(db_pool.get_ref(), record.id).await {
+                Ok(permissions) => permissions,
+                Err(_) => return HttpResponse::InternalServerError().finish(),
+            };
+
+            // Lets the dashboard show who is really behind an impersonated session
+            let impersonated_by = match request_impersonation(&req) {
+                Some(impersonation) => {
+                    let admin = sqlx::query!("SELECT id, name FROM users WHERE id = $1", impersonation.admin_user_id)
+                        .fetch_optional(db_pool.get_ref())
+                        .await;
+                    match admin {
+                        Ok(admin) => admin.map(|admin| serde_json::json!({ "id": admin.id, "name": admin.name })),
+                        Err(_) => return HttpResponse::InternalServerError().finish(),
+                    }
+                }
+                None => None,
+            };
+
             let response = serde_json::json!({
                 "name": record.name,
                 "email": record.email,
-                "api_key": record.api_key,
-                "permission": record.permission,
+                "api_key_prefix": record.api_key_prefix,
+                "role": record.role,
+                "permissions": permissions,
+                "impersonated_by": impersonated_by,
             });
             HttpResponse::Ok().json(response)
         }
@@ -0,0 +1,272 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::ServiceConfig;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::billing::{self, BillingPeriod};
+use crate::utils::permissions::Permission;
+use crate::utils::route_costs::RouteCostRegistry;
+use super::tenants::is_valid_route_pattern;
+
+pub fn configure_billing_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("billing")
+            .route("/usage", web::get().to(get_billable_usage).wrap(RequirePermission::new(Permission::BillingManage)))
+            .route("/invoices", web::get().to(export_invoices).wrap(RequirePermission::new(Permission::BillingManage)))
+            .route("/invoices", web::post().to(generate_invoices).wrap(RequirePermission::new(Permission::BillingManage)))
+            .route("/invoices/finalize", web::post().to(finalize_invoices).wrap(RequirePermission::new(Permission::BillingManage)))
+            .route("/route_costs", web::get().to(list_route_costs).wrap(RequirePermission::new(Permission::BillingManage)))
+            .route("/route_costs", web::put().to(set_route_costs).wrap(RequirePermission::new(Permission::BillingManage)))
+    );
+}
+
+fn parse_period(period: Option<&str>, default: BillingPeriod) -> Result<BillingPeriod, String> {
+    match period {
+        Some(period) => period.parse(),
+        None => Ok(default),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BillableUsageQuery {
+    // `YYYY-MM`, the current month by default
+    pub period: Option<String>,
+}
+
+// Preview of the invoices of a period, priced with the current plans, without saving them
+pub async fn get_billable_usage(db_pool: web::Data<PgPool>, query: web::Query<BillableUsageQuery>) -> impl Responder {
+    let period = match parse_period(query.period.as_deref(), BillingPeriod::current()) {
+        Ok(period) => period,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    match billing::compute_invoices(&db_pool, period).await {
+        Ok(invoices) => HttpResponse::Ok().json(invoices),
+        Err(e) => {
+            eprintln!("Error computing billable usage: {:?}", e);
+            HttpResponse::InternalServerError().body("Error computing billable usage")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InvoicePeriod {
+    // `YYYY-MM`, the previous month by default
+    pub period: Option<String>,
+}
+
+// Invoices are only generated and issued for months that are over
+fn parse_ended_period(period: Option<&str>) -> Result<BillingPeriod, String> {
+    let period = parse_period(period, BillingPeriod::current().previous())?;
+    if !period.has_ended() {
+        return Err(format!("The period {} has not ended yet.", period.label()));
+    }
+    Ok(period)
+}
+
+pub async fn generate_invoices(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<InvoicePeriod>,
+) -> impl Responder {
+    let period = match parse_ended_period(body.period.as_deref()) {
+        Ok(period) => period,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let generated = async {
+        let invoices = billing::compute_invoices(&db_pool, period).await?;
+        billing::save_invoices(&db_pool, period, &invoices).await
+    }.await;
+
+    // Subjects already invoiced with a finalized invoice keep it, only the others get a draft
+    match generated {
+        Ok(drafts) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "billing.invoices_generated",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "period": period.label(), "drafts": drafts })),
+                before: None,
+                after: None,
+            }).await;
+
+            HttpResponse::Created().json(serde_json::json!({ "period": period.label(), "drafts": drafts }))
+        }
+        Err(e) => {
+            eprintln!("Error generating invoices: {:?}", e);
+            HttpResponse::InternalServerError().body("Error generating invoices")
+        }
+    }
+}
+
+pub async fn finalize_invoices(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<InvoicePeriod>,
+) -> impl Responder {
+    let period = match parse_ended_period(body.period.as_deref()) {
+        Ok(period) => period,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    match billing::finalize_invoices(&db_pool, period).await {
+        Ok(finalized) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "billing.invoices_finalized",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "period": period.label(), "invoices": finalized })),
+                before: None,
+                after: None,
+            }).await;
+
+            HttpResponse::Ok().json(serde_json::json!({ "period": period.label(), "finalized": finalized }))
+        }
+        Err(e) => {
+            eprintln!("Error finalizing invoices: {:?}", e);
+            HttpResponse::InternalServerError().body("Error finalizing invoices")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InvoiceQuery {
+    // `YYYY-MM`, every period when not set
+    pub period: Option<String>,
+    pub organization_id: Option<i32>,
+    pub user_id: Option<i32>,
+    // `json` (default) or `csv`
+    pub format: Option<String>,
+}
+
+pub async fn export_invoices(db_pool: web::Data<PgPool>, query: web::Query<InvoiceQuery>) -> impl Responder {
+    let period = match query.period.as_deref().map(str::parse::<BillingPeriod>).transpose() {
+        Ok(period) => period,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let invoices = match billing::list_invoices(&db_pool, period, query.organization_id, query.user_id).await {
+        Ok(invoices) => invoices,
+        Err(e) => {
+            eprintln!("Error fetching invoices: {:?}", e);
+            return HttpResponse::InternalServerError().body("Error fetching invoices");
+        }
+    };
+
+    match query.format.as_deref().unwrap_or("json") {
+        "json" => HttpResponse::Ok().json(invoices),
+        "csv" => {
+            let filename = format!("invoices-{}.csv", period.map(|period| period.label()).unwrap_or_else(|| "all".to_string()));
+            HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(filename)],
+                })
+                .body(billing::to_csv(&invoices))
+        }
+        format => HttpResponse::BadRequest().body(format!("Unknown format '{}', expected 'json' or 'csv'", format)),
+    }
+}
+
+pub async fn list_route_costs(db_pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query!("SELECT route_pattern, cost FROM route_costs ORDER BY route_pattern")
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(costs) => HttpResponse::Ok().json(
+            costs
+                .into_iter()
+                .map(|cost| serde_json::json!({ "route": cost.route_pattern, "cost": cost.cost }))
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RouteCostEntry {
+    // `<METHOD> <path glob>`, such as `POST /api/graphql`
+    pub route: String,
+    pub cost: i32,
+}
+
+#[derive(Deserialize)]
+pub struct RouteCosts {
+    // Replaces every cost, routes left out cost 1 unit
+    pub costs: Vec<RouteCostEntry>,
+}
+
+pub async fn set_route_costs(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<RouteCostRegistry>,
+    req: HttpRequest,
+    body: web::Json<RouteCosts>,
+) -> impl Responder {
+    let routes: Vec<String> = body.costs.iter().map(|entry| entry.route.trim().to_string()).collect();
+    let costs: Vec<i32> = body.costs.iter().map(|entry| entry.cost).collect();
+    if let Some(route) = routes.iter().find(|route| !is_valid_route_pattern(route)) {
+        return HttpResponse::BadRequest().body(format!("Invalid route pattern '{}', expected '<METHOD> <path>'", route));
+    }
+    if costs.iter().any(|cost| *cost < 0) {
+        return HttpResponse::BadRequest().body("Costs cannot be negative.");
+    }
+
+    let updated = async {
+        let mut tx = db_pool.begin().await?;
+
+        let old_costs = sqlx::query!("DELETE FROM route_costs RETURNING route_pattern, cost")
+            .fetch_all(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO route_costs (route_pattern, cost)
+            SELECT * FROM UNNEST($1::TEXT[], $2::INTEGER[])
+            ON CONFLICT (route_pattern) DO UPDATE SET cost = EXCLUDED.cost
+            "#,
+            &routes[..],
+            &costs[..]
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(old_costs)
+    }.await;
+
+    match updated {
+        Ok(old_costs) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "billing.route_costs_changed",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!(old_costs
+                    .into_iter()
+                    .map(|cost| serde_json::json!({ "route": cost.route_pattern, "cost": cost.cost }))
+                    .collect::<Vec<_>>())),
+                after: Some(serde_json::json!(routes
+                    .iter()
+                    .zip(&costs)
+                    .map(|(route, cost)| serde_json::json!({ "route": route, "cost": cost }))
+                    .collect::<Vec<_>>())),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,172 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::models::client_certificate::{ClientCertificate, ClientCertificateResponse};
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::scopes::validate_scopes;
+use crate::utils::tenants::ensure_user_in_scope;
+use super::api_keys::default_scopes;
+
+#[derive(Deserialize)]
+pub struct NewClientCertificate {
+    pub fingerprint: Option<String>,
+    pub subject: Option<String>,
+    pub scopes: Option<Vec<String>>,
+}
+
+// Accepts the usual `AA:BB:...` notation as well as plain hex
+fn normalize_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let fingerprint: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase();
+
+    if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Fingerprint must be a SHA-256 hash of the certificate.".to_string());
+    }
+    Ok(fingerprint)
+}
+
+pub async fn add_client_certificate(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    new_certificate: web::Json<NewClientCertificate>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, user_id).await {
+        return response;
+    }
+    let new_certificate = new_certificate.into_inner();
+
+    let fingerprint = match new_certificate.fingerprint.as_deref().map(normalize_fingerprint).transpose() {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let subject = new_certificate.subject.map(|subject| subject.trim().to_string()).filter(|subject| !subject.is_empty());
+    if fingerprint.is_none() && subject.is_none() {
+        return HttpResponse::BadRequest().body("A fingerprint or a subject is required.");
+    }
+
+    let scopes = new_certificate.scopes.unwrap_or_else(default_scopes);
+    if let Err(e) = validate_scopes(&scopes) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let result = sqlx::query_as!(
+        ClientCertificate,
+        r#"
+        INSERT INTO client_certificates (user_id, fingerprint, subject, scopes)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, user_id, fingerprint, subject, scopes, created_at, last_used_at, revoked_at
+        "#,
+        user_id,
+        fingerprint,
+        subject,
+        &scopes[..]
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    match result {
+        Ok(certificate) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(user_id),
+                action: "client_certificate.added",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "certificate_id": certificate.id })),
+                before: None,
+                after: Some(serde_json::json!({
+                    "fingerprint": certificate.fingerprint,
+                    "subject": certificate.subject,
+                    "scopes": certificate.scopes,
+                })),
+            }).await;
+
+            HttpResponse::Created().json(ClientCertificateResponse::from(certificate))
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("This certificate is already registered.")
+        }
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_client_certificates(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, user_id).await {
+        return response;
+    }
+
+    let result = sqlx::query_as!(
+        ClientCertificate,
+        r#"
+        SELECT id, user_id, fingerprint, subject, scopes, created_at, last_used_at, revoked_at
+        FROM client_certificates
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(certificates) => HttpResponse::Ok().json(
+            certificates
+                .into_iter()
+                .map(ClientCertificateResponse::from)
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            eprintln!("Error fetching client certificates: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn revoke_client_certificate(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> impl Responder {
+    let (user_id, certificate_id) = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, user_id).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        "UPDATE client_certificates SET revoked_at = now() WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        certificate_id,
+        user_id
+    )
+        .execute(&**db_pool)
+        .await;
+
+    match result {
+        Ok(result) if result.rows_affected() == 0 => HttpResponse::NotFound().finish(),
+        Ok(_) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(user_id),
+                action: "client_certificate.revoked",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "certificate_id": certificate_id })),
+                before: None,
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -1,5 +1,43 @@
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web, HttpResponse, Responder};
+use crate::config::rate_limit::{RateLimitConfig, RedisFailureMode};
+use crate::config::redis::RedisPool;
+use crate::utils::load_shedding::LoadShedding;
 
 pub async fn health_check() -> impl Responder {
     HttpResponse::Ok().body("I'm alive!")
-}
\ No newline at end of file
+}
+
+/// Reports whether the dependencies of request limiting work. Without Redis the gateway is degraded:
+/// it keeps serving unless the failure mode is closed, in which case it answers 503 like the API does.
+pub async fn health(
+    redis_client: web::Data<RedisPool>,
+    config: web::Data<RateLimitConfig>,
+    load_shedding: web::Data<LoadShedding>,
+) -> impl Responder {
+    let redis_available = match redis_client.connection().await {
+        Ok(mut redis_conn) => redis::cmd("PING").query_async::<String>(&mut redis_conn).await.is_ok(),
+        Err(_) => false,
+    };
+
+    let body = serde_json::json!({
+        "status": if redis_available { "ok" } else { "degraded" },
+        "redis": {
+            "available": redis_available,
+            "unavailable_for_secs": redis_client.unavailable_for().map(|duration| duration.as_secs()),
+        },
+        // How rate limits are enforced at the moment
+        "rate_limiting": if redis_available { "redis" } else { config.failure_mode.as_str() },
+        // Current adaptive limit of each upstream on this instance
+        "load_shedding": load_shedding
+            .snapshot()
+            .into_iter()
+            .map(|load| (load.upstream.to_string(), serde_json::json!({ "limit": load.limit, "in_flight": load.in_flight })))
+            .collect::<serde_json::Map<_, _>>(),
+    });
+
+    if !redis_available && config.failure_mode == RedisFailureMode::Closed {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
@@ -0,0 +1,145 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::config::impersonation::ImpersonationConfig;
+use crate::utils::audit::{self, actor_ip, AuditEntry};
+use crate::utils::impersonation::request_impersonation;
+use crate::utils::jwt::create_impersonation_jwt;
+use crate::utils::permissions::ensure_outranks;
+use crate::utils::tenants::ensure_user_in_scope;
+
+#[derive(Deserialize)]
+pub struct ImpersonationRequest {
+    // Why the dashboard is being viewed as this user, kept in the audit log
+    pub reason: String,
+}
+
+pub async fn start_impersonation(
+    db_pool: web::Data<PgPool>,
+    config: web::Data<ImpersonationConfig>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<ImpersonationRequest>,
+) -> impl Responder {
+    let user_id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, user_id).await {
+        return response;
+    }
+    let admin_user_id = match req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(admin_user_id) => admin_user_id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    if admin_user_id == user_id {
+        return HttpResponse::BadRequest().body("You cannot impersonate yourself.");
+    }
+    let reason = body.reason.trim();
+    if reason.is_empty() || reason.chars().count() > 500 {
+        return HttpResponse::BadRequest().body("A reason of at most 500 characters is required.");
+    }
+
+    if let Err(response) = ensure_outranks(&db_pool, &req, user_id).await {
+        return response;
+    }
+
+    let target = sqlx::query!("SELECT tenant_id FROM users WHERE id = $1",
+        user_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    let target = match target {
+        Ok(Some(target)) => target,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let ttl_secs = config.ttl_secs as i64;
+    let session = sqlx::query!(
+        r#"
+        INSERT INTO impersonation_sessions (admin_user_id, target_user_id, reason, expires_at)
+        VALUES ($1, $2, $3, now() + make_interval(secs => $4))
+        RETURNING id
+        "#,
+        admin_user_id,
+        user_id,
+        reason,
+        ttl_secs as f64
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    let session_id = match session {
+        Ok(session) => session.id,
+        Err(e) => {
+            eprintln!("Error starting impersonation session: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let expires_at = (Utc::now() + Duration::seconds(ttl_secs)).timestamp();
+    let token = create_impersonation_jwt(user_id, target.tenant_id, admin_user_id, session_id, expires_at);
+
+    let actor_ip = actor_ip(&req);
+    audit::record(&db_pool, AuditEntry {
+        actor_user_id: Some(admin_user_id),
+        target_user_id: Some(user_id),
+        action: "impersonation.started",
+        ip: actor_ip,
+        details: Some(serde_json::json!({
+            "session_id": session_id,
+            "reason": reason,
+            "expires_in_secs": ttl_secs,
+        })),
+        before: None,
+        after: None,
+    }).await;
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "token": token,
+        "session_id": session_id,
+        "expires_in_secs": ttl_secs,
+    }))
+}
+
+// Called with the impersonation token itself, which stops working immediately
+pub async fn stop_impersonation(db_pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let impersonation = match request_impersonation(&req) {
+        Some(impersonation) => impersonation,
+        None => return HttpResponse::BadRequest().body("This session is not an impersonation."),
+    };
+    let user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+
+    let result = sqlx::query!(
+        "UPDATE impersonation_sessions SET ended_at = now() WHERE id = $1 AND ended_at IS NULL",
+        impersonation.session_id
+    )
+        .execute(&**db_pool)
+        .await;
+
+    match result {
+        Ok(_) => {
+            let actor_ip = actor_ip(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(impersonation.admin_user_id),
+                target_user_id: user_id,
+                action: "impersonation.stopped",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "session_id": impersonation.session_id })),
+                before: None,
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -1,4 +1,14 @@
 pub(crate) mod user;
 pub(crate) mod api;
 pub(crate) mod auth;
-pub(crate) mod health_check;
\ No newline at end of file
+pub(crate) mod health_check;
+pub(crate) mod api_keys;
+pub(crate) mod client_certificates;
+pub(crate) mod roles;
+pub(crate) mod organizations;
+pub(crate) mod tenants;
+pub(crate) mod impersonation;
+pub(crate) mod audit_log;
+pub(crate) mod quota;
+pub(crate) mod plans;
+pub(crate) mod billing;
\ No newline at end of file
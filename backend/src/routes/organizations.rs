@@ -0,0 +1,1015 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
+use actix_web::web::ServiceConfig;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::config::redis::RedisPool;
+use crate::config::mailer::app_base_url;
+use crate::middlewares::impersonation_guard::DenyImpersonation;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::models::api_key::{ApiKey, ApiKeyResponse};
+use crate::models::organization::{
+    OrganizationInvitationResponse, OrganizationMemberResponse, OrganizationResponse, OrganizationRole,
+    OrganizationUsageResponse,
+};
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::mailer::{Email, Mailer};
+use crate::utils::quota::{self, QuotaPeriod};
+use crate::utils::permissions::Permission;
+use crate::utils::tenants::{admin_tenant_scope, ensure_organization_in_scope, request_tenant_id};
+use crate::utils::tokens::{generate_token, hash_token};
+use super::api_keys::{insert_api_key, key_snapshot, NewApiKey};
+use super::user::TenantQuery;
+
+// Lifetime of an invitation in seconds
+const INVITATION_TTL_SECS: f64 = 60.0 * 60.0 * 24.0 * 7.0;
+
+pub fn configure_organization_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("organizations")
+            .wrap(DenyImpersonation::writes())
+            .route("/invitations/accept", web::post().to(accept_invitation))
+            .route("/{id}/members/{user_id}", web::put().to(change_member_role))
+            .route("/{id}/members/{user_id}", web::delete().to(remove_member))
+            .route("/{id}/invitations/{invitation_id}", web::delete().to(cancel_invitation))
+            .route("/{id}/invitations", web::get().to(list_invitations))
+            .route("/{id}/invitations", web::post().to(invite_member))
+            .route("/{id}/api_keys/{key_id}", web::delete().to(revoke_organization_api_key))
+            .route("/{id}/api_keys", web::get().to(list_organization_api_keys))
+            .route("/{id}/api_keys", web::post().to(create_organization_api_key).wrap(RequirePermission::new(Permission::ApiKeysManageOwn)))
+            .route("/{id}/usage", web::get().to(get_organization_usage))
+            .route("/{id}", web::get().to(get_organization))
+            .route("/{id}", web::delete().to(delete_organization))
+            .route("", web::post().to(create_organization))
+            .route("", web::get().to(list_organizations))
+    );
+}
+
+// Registered under `/dashboard/admin`, for GateKeeper administrators rather than organization members
+pub fn configure_admin_organization_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("organizations")
+            .route("/{id}/quota", web::put().to(set_organization_quota).wrap(RequirePermission::new(Permission::OrganizationsManage)))
+            .route("/{id}/plan", web::put().to(super::plans::set_organization_plan).wrap(RequirePermission::new(Permission::PlansManage)))
+            .route("", web::get().to(list_all_organizations).wrap(RequirePermission::new(Permission::OrganizationsManage)))
+    );
+}
+
+fn current_user_id(req: &HttpRequest) -> Option<i32> {
+    req.extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+}
+
+/// Returns the role of the user in the organization, or the response to send when it is below `minimum`.
+/// Non-members get a 404 so they cannot probe which organizations exist.
+async fn require_role(
+    db_pool: &PgPool,
+    organization_id: i32,
+    user_id: Option<i32>,
+    minimum: OrganizationRole,
+) -> Result<OrganizationRole, HttpResponse> {
+    let user_id = user_id.ok_or_else(|| HttpResponse::BadRequest().body("Invalid user ID"))?;
+
+    let member = sqlx::query!(
+        "SELECT role FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+        organization_id,
+        user_id
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    let role = member
+        .and_then(|member| OrganizationRole::parse(&member.role))
+        .ok_or_else(|| HttpResponse::NotFound().finish())?;
+
+    if role < minimum {
+        return Err(HttpResponse::Forbidden().body(format!(
+            "This action requires the '{}' role in the organization.",
+            minimum.as_str()
+        )));
+    }
+    Ok(role)
+}
+
+#[derive(Deserialize)]
+pub struct NewOrganization {
+    pub name: String,
+}
+
+pub async fn create_organization(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    new_organization: web::Json<NewOrganization>,
+) -> impl Responder {
+    let user_id = match current_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let name = new_organization.name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        return HttpResponse::BadRequest().body("Organization name must be between 1 and 100 characters.");
+    }
+    let tenant_id = match request_tenant_id(&req) {
+        Some(tenant_id) => tenant_id,
+        None => return HttpResponse::NotFound().body("Unknown tenant"),
+    };
+
+    // The creator becomes the first owner
+    let created = async {
+        let mut tx = db_pool.begin().await?;
+
+        let organization = sqlx::query!(
+            "INSERT INTO organizations (name, tenant_id) VALUES ($1, $2) RETURNING id, monthly_request_quota, created_at",
+            name,
+            tenant_id
+        )
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO organization_members (organization_id, user_id, role) VALUES ($1, $2, $3)",
+            organization.id,
+            user_id,
+            OrganizationRole::Owner.as_str()
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(organization)
+    }.await;
+
+    match created {
+        Ok(organization) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: None,
+                action: "organization.created",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": organization.id })),
+                before: None,
+                after: Some(serde_json::json!({ "name": name })),
+            }).await;
+
+            HttpResponse::Created().json(OrganizationResponse {
+                id: organization.id,
+                name: name.to_string(),
+                role: Some(OrganizationRole::Owner.as_str().to_string()),
+                monthly_request_quota: organization.monthly_request_quota,
+                created_at: organization.created_at.to_string(),
+            })
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_organizations(db_pool: web::Data<PgPool>, req: HttpRequest) -> impl Responder {
+    let user_id = current_user_id(&req);
+
+    let result = sqlx::query!(
+        r#"
+        SELECT organizations.id, organizations.name, organizations.monthly_request_quota, organizations.created_at,
+            organization_members.role
+        FROM organizations
+        JOIN organization_members ON organization_members.organization_id = organizations.id
+        WHERE organization_members.user_id = $1
+        ORDER BY organizations.name
+        "#,
+        user_id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(organizations) => HttpResponse::Ok().json(
+            organizations
+                .into_iter()
+                .map(|organization| OrganizationResponse {
+                    id: organization.id,
+                    name: organization.name,
+                    role: Some(organization.role),
+                    monthly_request_quota: organization.monthly_request_quota,
+                    created_at: organization.created_at.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn get_organization(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let role = match require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Member).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    let organization = sqlx::query!(
+        "SELECT id, name, monthly_request_quota, created_at FROM organizations WHERE id = $1",
+        id
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    let members = sqlx::query!(
+        r#"
+        SELECT users.id, users.name, users.email, organization_members.role, organization_members.created_at
+        FROM organization_members
+        JOIN users ON users.id = organization_members.user_id
+        WHERE organization_members.organization_id = $1
+        ORDER BY organization_members.created_at
+        "#,
+        id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match (organization, members) {
+        (Ok(organization), Ok(members)) => HttpResponse::Ok().json(serde_json::json!({
+            "organization": OrganizationResponse {
+                id: organization.id,
+                name: organization.name,
+                role: Some(role.as_str().to_string()),
+                monthly_request_quota: organization.monthly_request_quota,
+                created_at: organization.created_at.to_string(),
+            },
+            "members": members
+                .into_iter()
+                .map(|member| OrganizationMemberResponse {
+                    user_id: member.id,
+                    name: member.name,
+                    email: member.email,
+                    role: member.role,
+                    joined_at: member.created_at.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        })),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn delete_organization(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Owner).await {
+        return response;
+    }
+
+    // The keys of the organization are deleted with it
+    let result = sqlx::query!("DELETE FROM organizations WHERE id = $1 RETURNING name, monthly_request_quota", id)
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(organization)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "organization.deleted",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id })),
+                before: Some(serde_json::json!({
+                    "name": organization.name,
+                    "monthly_request_quota": organization.monthly_request_quota,
+                })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MemberRoleChange {
+    pub role: String,
+}
+
+// Fails when the organization would be left without an owner
+async fn ensure_other_owner(db_pool: &PgPool, organization_id: i32, user_id: i32) -> Result<(), HttpResponse> {
+    let owners = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM organization_members
+        WHERE organization_id = $1 AND role = 'owner' AND user_id <> $2
+        "#,
+        organization_id,
+        user_id
+    )
+        .fetch_one(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    if owners.count == 0 {
+        return Err(HttpResponse::Conflict().body("An organization must keep at least one owner."));
+    }
+    Ok(())
+}
+
+pub async fn change_member_role(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+    role_change: web::Json<MemberRoleChange>,
+) -> impl Responder {
+    let (id, member_id) = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Owner).await {
+        return response;
+    }
+
+    let role = match OrganizationRole::parse(&role_change.role) {
+        Some(role) => role,
+        None => return HttpResponse::BadRequest().body("Role must be one of 'owner', 'admin' or 'member'."),
+    };
+
+    if role != OrganizationRole::Owner {
+        if let Err(response) = ensure_other_owner(&db_pool, id, member_id).await {
+            return response;
+        }
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_member AS (
+            SELECT organization_id, user_id, role
+            FROM organization_members
+            WHERE organization_id = $2 AND user_id = $3
+            FOR UPDATE
+        )
+        UPDATE organization_members SET role = $1
+        FROM old_member
+        WHERE organization_members.organization_id = old_member.organization_id
+            AND organization_members.user_id = old_member.user_id
+        RETURNING old_member.role AS "old_role!"
+        "#,
+        role.as_str(),
+        id,
+        member_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(member)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(member_id),
+                action: "organization.member_role_changed",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id })),
+                before: Some(serde_json::json!({ "role": member.old_role })),
+                after: Some(serde_json::json!({ "role": role.as_str() })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn remove_member(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> impl Responder {
+    let (id, member_id) = path.into_inner();
+    let user_id = current_user_id(&req);
+
+    // Members can leave on their own, admins can remove members and owners can remove anyone
+    let minimum = if user_id == Some(member_id) { OrganizationRole::Member } else { OrganizationRole::Admin };
+    let role = match require_role(&db_pool, id, user_id, minimum).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    let member = sqlx::query!(
+        "SELECT role FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+        id,
+        member_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    let member_role = match member {
+        Ok(Some(member)) => OrganizationRole::parse(&member.role).unwrap_or(OrganizationRole::Member),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if user_id != Some(member_id) && member_role >= role && role != OrganizationRole::Owner {
+        return HttpResponse::Forbidden().body("You cannot remove a member with the same or a higher role.");
+    }
+    if member_role == OrganizationRole::Owner {
+        if let Err(response) = ensure_other_owner(&db_pool, id, member_id).await {
+            return response;
+        }
+    }
+
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let removed = sqlx::query!(
+        "DELETE FROM organization_members WHERE organization_id = $1 AND user_id = $2",
+        id,
+        member_id
+    )
+        .execute(&mut *tx)
+        .await;
+    if removed.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // Keys the member created for the organization would otherwise keep using its quota
+    let revoked = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = now()
+        WHERE organization_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        RETURNING key_prefix
+        "#,
+        id,
+        member_id
+    )
+        .fetch_all(&mut *tx)
+        .await;
+    let revoked = match revoked {
+        Ok(revoked) => revoked,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    let (actor_user_id, actor_ip) = actor(&req);
+    audit::record(&db_pool, AuditEntry {
+        actor_user_id,
+        target_user_id: Some(member_id),
+        action: "organization.member_removed",
+        ip: actor_ip,
+        details: Some(serde_json::json!({
+            "organization_id": id,
+            "revoked_key_prefixes": revoked.into_iter().map(|key| key.key_prefix).collect::<Vec<_>>(),
+        })),
+        before: Some(serde_json::json!({ "role": member_role.as_str() })),
+        after: None,
+    }).await;
+
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Deserialize)]
+pub struct NewInvitation {
+    pub email: String,
+    // `member` when omitted
+    pub role: Option<String>,
+}
+
+pub async fn invite_member(
+    db_pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    invitation: web::Json<NewInvitation>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = current_user_id(&req);
+    if let Err(response) = require_role(&db_pool, id, user_id, OrganizationRole::Admin).await {
+        return response;
+    }
+
+    let email = invitation.email.trim().to_string();
+    if email.is_empty() || !email.contains('@') {
+        return HttpResponse::BadRequest().body("Invalid email address.");
+    }
+
+    // Ownership is transferred by changing the role of an existing member
+    let role = match invitation.role.as_deref().map(OrganizationRole::parse).unwrap_or(Some(OrganizationRole::Member)) {
+        Some(role) if role != OrganizationRole::Owner => role,
+        _ => return HttpResponse::BadRequest().body("Role must be 'admin' or 'member'."),
+    };
+
+    let token = generate_token();
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO organization_invitations (organization_id, email, role, token_hash, invited_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5, now() + make_interval(secs => $6))
+        RETURNING id, expires_at, created_at, (SELECT name FROM organizations WHERE id = $1) AS "organization_name!"
+        "#,
+        id,
+        email,
+        role.as_str(),
+        hash_token(&token),
+        user_id,
+        INVITATION_TTL_SECS
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    let created = match created {
+        Ok(created) => created,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let sent = mailer.send(&Email {
+        to: email.clone(),
+        subject: format!("You have been invited to {} on GateKeeper", created.organization_name),
+        body: format!(
+            "You have been invited to join the organization {} on GateKeeper.\n\nSign in or create an account with this email address, then open the following link to accept:\n{}/organizations/accept?token={}\n\nThis invitation expires in 7 days.",
+            created.organization_name,
+            app_base_url(),
+            token
+        ),
+    }).await;
+
+    if let Err(e) = sent {
+        eprintln!("Failed to send invitation email: {}", e);
+    }
+
+    let (_, actor_ip) = actor(&req);
+    audit::record(&db_pool, AuditEntry {
+        actor_user_id: user_id,
+        target_user_id: None,
+        action: "organization.member_invited",
+        ip: actor_ip,
+        details: Some(serde_json::json!({ "organization_id": id, "invitation_id": created.id })),
+        before: None,
+        after: Some(serde_json::json!({ "email": email, "role": role.as_str() })),
+    }).await;
+
+    HttpResponse::Created().json(OrganizationInvitationResponse {
+        id: created.id,
+        email,
+        role: role.as_str().to_string(),
+        expires_at: created.expires_at.to_string(),
+        accepted_at: None,
+        created_at: created.created_at.to_string(),
+    })
+}
+
+pub async fn list_invitations(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Admin).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        r#"
+        SELECT id, email, role, expires_at, accepted_at, created_at
+        FROM organization_invitations
+        WHERE organization_id = $1
+        ORDER BY created_at DESC
+        "#,
+        id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(invitations) => HttpResponse::Ok().json(
+            invitations
+                .into_iter()
+                .map(|invitation| OrganizationInvitationResponse {
+                    id: invitation.id,
+                    email: invitation.email,
+                    role: invitation.role,
+                    expires_at: invitation.expires_at.to_string(),
+                    accepted_at: invitation.accepted_at.map(|t| t.to_string()),
+                    created_at: invitation.created_at.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn cancel_invitation(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> impl Responder {
+    let (id, invitation_id) = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Admin).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM organization_invitations WHERE id = $1 AND organization_id = $2 AND accepted_at IS NULL RETURNING email, role",
+        invitation_id,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(invitation)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "organization.invitation_cancelled",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id, "invitation_id": invitation_id })),
+                before: Some(serde_json::json!({ "email": invitation.email, "role": invitation.role })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInvitation {
+    pub token: String,
+}
+
+pub async fn accept_invitation(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    body: web::Json<AcceptInvitation>,
+) -> impl Responder {
+    let user_id = match current_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    let accepted = async {
+        let mut tx = db_pool.begin().await?;
+
+        // An invitation can only be accepted once, by the account it was sent to on the tenant of the organization
+        let invitation = sqlx::query!(
+            r#"
+            UPDATE organization_invitations
+            SET accepted_at = now()
+            FROM organizations, users
+            WHERE organization_invitations.token_hash = $1 AND organization_invitations.accepted_at IS NULL
+                AND organization_invitations.expires_at > now()
+                AND organizations.id = organization_invitations.organization_id
+                AND users.id = $2 AND users.tenant_id = organizations.tenant_id
+                AND LOWER(organization_invitations.email) = LOWER(users.email)
+            RETURNING organization_invitations.organization_id, organization_invitations.role
+            "#,
+            hash_token(&body.token),
+            user_id
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let invitation = match invitation {
+            Some(invitation) => invitation,
+            None => return Ok(None),
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO organization_members (organization_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, user_id) DO NOTHING
+            "#,
+            invitation.organization_id,
+            user_id,
+            invitation.role
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some((invitation.organization_id, invitation.role)))
+    }.await;
+
+    match accepted {
+        Ok(Some((organization_id, role))) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: Some(user_id),
+                action: "organization.invitation_accepted",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": organization_id })),
+                before: None,
+                after: Some(serde_json::json!({ "role": role })),
+            }).await;
+
+            HttpResponse::Ok().json(serde_json::json!({ "organization_id": organization_id }))
+        }
+        Ok(None) => HttpResponse::BadRequest().body("Invalid or expired invitation."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_organization_api_keys(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Member).await {
+        return response;
+    }
+
+    let result = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id, user_id, organization_id, name, key_prefix, scopes, allowed_cidrs, allowed_origins, signing_enabled, require_signature, created_at, last_used_at, expires_at, rotated_at, revoked_at
+        FROM api_keys
+        WHERE organization_id = $1
+        ORDER BY revoked_at IS NOT NULL, created_at DESC
+        "#,
+        id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(keys) => HttpResponse::Ok().json(keys.into_iter().map(ApiKeyResponse::from).collect::<Vec<_>>()),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn create_organization_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    new_key: web::Json<NewApiKey>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let user_id = match current_user_id(&req) {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+    if let Err(response) = require_role(&db_pool, id, Some(user_id), OrganizationRole::Admin).await {
+        return response;
+    }
+
+    let (name, scopes, expires_in_secs) = match new_key.validate() {
+        Ok(validated) => validated,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let mut conn = match db_pool.acquire().await {
+        Ok(conn) => conn,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    match insert_api_key(&mut conn, user_id, Some(id), &name, &scopes, expires_in_secs).await {
+        Ok(created) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: None,
+                action: "organization.api_key_created",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id })),
+                before: None,
+                after: Some(key_snapshot(&created)),
+            }).await;
+
+            HttpResponse::Created().json(created)
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("An active key with this name already exists in this organization.")
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn revoke_organization_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<(i32, i32)>,
+) -> impl Responder {
+    let (id, key_id) = path.into_inner();
+    let user_id = current_user_id(&req);
+    let role = match require_role(&db_pool, id, user_id, OrganizationRole::Member).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    // Members can only revoke the keys they created
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = now()
+        WHERE id = $1 AND organization_id = $2 AND revoked_at IS NULL AND ($3 OR user_id = $4)
+        RETURNING user_id, name, key_prefix
+        "#,
+        key_id,
+        id,
+        role >= OrganizationRole::Admin,
+        user_id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(key)) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: user_id,
+                target_user_id: Some(key.user_id),
+                action: "organization.api_key_revoked",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id, "key_id": key_id })),
+                before: Some(serde_json::json!({ "name": key.name, "key_prefix": key.key_prefix })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    // Number of past days to aggregate, 30 by default
+    pub days: Option<u16>,
+}
+
+pub async fn get_organization_usage(
+    db_pool: web::Data<PgPool>,
+    redis_client: web::Data<RedisPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<UsageQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = require_role(&db_pool, id, current_user_id(&req), OrganizationRole::Member).await {
+        return response;
+    }
+
+    let days = query.days.unwrap_or(30).clamp(1, 366) as i32;
+
+    let usage = sqlx::query!(
+        r#"
+        SELECT
+            DATE(request_time) AS "day!",
+            api_key_id,
+            COUNT(*) AS "requests!",
+            COALESCE(SUM(cost), 0)::BIGINT AS "units!",
+            COUNT(*) FILTER (WHERE status_code >= 400) AS "errors!"
+        FROM api_usage
+        WHERE organization_id = $1 AND request_time >= CURRENT_DATE - make_interval(days => $2)
+        GROUP BY 1, 2
+        ORDER BY 1 DESC, 2
+        "#,
+        id,
+        days
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    let organization = sqlx::query!("SELECT monthly_request_quota FROM organizations WHERE id = $1", id)
+        .fetch_one(&**db_pool)
+        .await;
+
+    let used_this_month = match quota::current_usage(&redis_client, &db_pool, &quota::organization_subject(id), &[QuotaPeriod::Month]).await {
+        Ok(usage) => usage.first().map(|usage| usage.used).unwrap_or(0),
+        Err(response) => return response,
+    };
+
+    match (usage, organization) {
+        (Ok(usage), Ok(organization)) => HttpResponse::Ok().json(serde_json::json!({
+            "quota": {
+                "month": QuotaPeriod::Month.current(),
+                "monthly_request_quota": organization.monthly_request_quota,
+                "used": used_this_month,
+            },
+            "usage": usage
+                .into_iter()
+                .map(|row| OrganizationUsageResponse {
+                    day: row.day.to_string(),
+                    api_key_id: row.api_key_id,
+                    requests: row.requests,
+                    units: row.units,
+                    errors: row.errors,
+                })
+                .collect::<Vec<_>>(),
+        })),
+        (Err(e), _) => {
+            eprintln!("Error fetching organization usage: {:?}", e);
+            HttpResponse::InternalServerError().body("Error fetching organization usage")
+        }
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn list_all_organizations(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<TenantQuery>,
+) -> impl Responder {
+    let tenant_id = match admin_tenant_scope(&db_pool, &req, query.tenant.as_deref()).await {
+        Ok(tenant_id) => tenant_id,
+        Err(response) => return response,
+    };
+
+    let result = sqlx::query!(
+        r#"
+        SELECT id, name, monthly_request_quota, created_at
+        FROM organizations
+        WHERE $1::INTEGER IS NULL OR tenant_id = $1
+        ORDER BY name
+        "#,
+        tenant_id
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(organizations) => HttpResponse::Ok().json(
+            organizations
+                .into_iter()
+                .map(|organization| OrganizationResponse {
+                    id: organization.id,
+                    name: organization.name,
+                    role: None,
+                    monthly_request_quota: organization.monthly_request_quota,
+                    created_at: organization.created_at.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OrganizationQuota {
+    // Unlimited when null
+    pub monthly_request_quota: Option<i64>,
+}
+
+pub async fn set_organization_quota(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    quota: web::Json<OrganizationQuota>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_organization_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+
+    if quota.monthly_request_quota.is_some_and(|quota| quota < 0) {
+        return HttpResponse::BadRequest().body("Quota cannot be negative.");
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_organization AS (
+            SELECT id, monthly_request_quota FROM organizations WHERE id = $2 FOR UPDATE
+        )
+        UPDATE organizations SET monthly_request_quota = $1
+        FROM old_organization
+        WHERE organizations.id = old_organization.id
+        RETURNING old_organization.monthly_request_quota AS old_quota
+        "#,
+        quota.monthly_request_quota,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(organization)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "organization.quota_changed",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id })),
+                before: Some(serde_json::json!({ "monthly_request_quota": organization.old_quota })),
+                after: Some(serde_json::json!({ "monthly_request_quota": quota.monthly_request_quota })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
@@ -0,0 +1,515 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::web::ServiceConfig;
+use serde::Deserialize;
+use sqlx::PgPool;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::permissions::Permission;
+use crate::utils::plans::{Plan, PlanRegistry};
+use crate::utils::quota::QuotaAction;
+use crate::utils::rate_limit::Algorithm;
+use crate::utils::tenants::{ensure_organization_in_scope, ensure_user_in_scope};
+use super::tenants::is_valid_route_pattern;
+
+pub fn configure_plan_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("plans")
+            .route("/{id}", web::put().to(update_plan).wrap(RequirePermission::new(Permission::PlansManage)))
+            .route("/{id}", web::delete().to(delete_plan).wrap(RequirePermission::new(Permission::PlansManage)))
+            .route("", web::post().to(create_plan).wrap(RequirePermission::new(Permission::PlansManage)))
+            .route("", web::get().to(list_plans).wrap(RequirePermission::new(Permission::PlansManage)))
+    );
+}
+
+pub async fn list_plans(db_pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as!(
+        Plan,
+        r#"
+        SELECT id, name, rate_limit_max_requests, rate_limit_window_secs, rate_limit_algorithm,
+            daily_request_quota, monthly_request_quota, quota_action, degraded_max_requests, degraded_window_secs,
+            priority, allowed_routes, cache_max_age_secs, is_default, currency, base_price_cents, included_units,
+            overage_price_per_1000_units_cents, price_per_gb_cents
+        FROM plans
+        ORDER BY id
+        "#
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(plans) => HttpResponse::Ok().json(plans),
+        Err(e) => {
+            eprintln!("Error fetching plans: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlanSettings {
+    pub name: String,
+    pub rate_limit_max_requests: i32,
+    pub rate_limit_window_secs: i32,
+    // RATE_LIMIT_ALGORITHM when not set
+    pub rate_limit_algorithm: Option<String>,
+    pub daily_request_quota: Option<i64>,
+    pub monthly_request_quota: Option<i64>,
+    // `block` when not set
+    pub quota_action: Option<String>,
+    pub degraded_max_requests: Option<i32>,
+    pub degraded_window_secs: Option<i32>,
+    #[serde(default)]
+    pub priority: i16,
+    // `<METHOD> <path glob>` patterns such as `GET /api/v1/*`, every route is allowed when empty
+    #[serde(default)]
+    pub allowed_routes: Vec<String>,
+    pub cache_max_age_secs: Option<i32>,
+    #[serde(default)]
+    pub is_default: bool,
+    // ISO 4217 code, `USD` when not set
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub base_price_cents: i64,
+    pub included_units: Option<i64>,
+    #[serde(default)]
+    pub overage_price_per_1000_units_cents: i64,
+    #[serde(default)]
+    pub price_per_gb_cents: i64,
+}
+
+impl PlanSettings {
+    fn algorithm(&self) -> Result<Option<&'static str>, String> {
+        self.rate_limit_algorithm
+            .as_deref()
+            .map(|algorithm| algorithm.parse::<Algorithm>().map(|algorithm| algorithm.as_str()))
+            .transpose()
+    }
+
+    fn quota_action(&self) -> Result<QuotaAction, String> {
+        self.quota_action.as_deref().map(str::parse).unwrap_or(Ok(QuotaAction::Block))
+    }
+
+    fn currency(&self) -> String {
+        self.currency.as_deref().map(|currency| currency.trim().to_uppercase()).unwrap_or_else(|| "USD".to_string())
+    }
+
+    fn allowed_routes(&self) -> Vec<String> {
+        self.allowed_routes.iter().map(|route| route.trim().to_string()).collect()
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name.trim(),
+            "rate_limit_max_requests": self.rate_limit_max_requests,
+            "rate_limit_window_secs": self.rate_limit_window_secs,
+            "rate_limit_algorithm": self.algorithm().ok().flatten(),
+            "daily_request_quota": self.daily_request_quota,
+            "monthly_request_quota": self.monthly_request_quota,
+            "quota_action": self.quota_action().ok().map(|action| action.as_str()),
+            "degraded_max_requests": self.degraded_max_requests,
+            "degraded_window_secs": self.degraded_window_secs,
+            "priority": self.priority,
+            "allowed_routes": self.allowed_routes(),
+            "cache_max_age_secs": self.cache_max_age_secs,
+            "is_default": self.is_default,
+            "currency": self.currency(),
+            "base_price_cents": self.base_price_cents,
+            "included_units": self.included_units,
+            "overage_price_per_1000_units_cents": self.overage_price_per_1000_units_cents,
+            "price_per_gb_cents": self.price_per_gb_cents,
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.name.trim().chars().count() > 64 {
+            return Err("Plan name must be between 1 and 64 characters.".to_string());
+        }
+
+        if self.rate_limit_max_requests < 0 || self.rate_limit_window_secs <= 0 {
+            return Err("Rate limit values must be positive.".to_string());
+        }
+        self.algorithm()?;
+
+        if self.daily_request_quota.is_some_and(|quota| quota < 0) || self.monthly_request_quota.is_some_and(|quota| quota < 0) {
+            return Err("Quotas cannot be negative.".to_string());
+        }
+
+        match (self.degraded_max_requests, self.degraded_window_secs) {
+            (Some(max_requests), Some(window_secs)) if max_requests < 0 || window_secs <= 0 => {
+                return Err("Degraded rate limit values must be positive.".to_string());
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                return Err("degraded_max_requests and degraded_window_secs must be set together.".to_string());
+            }
+            (None, None) if self.quota_action()? == QuotaAction::Degrade => {
+                return Err("The degrade quota action requires a degraded rate limit.".to_string());
+            }
+            _ => {}
+        }
+
+        if !(0..=10).contains(&self.priority) {
+            return Err("Priority must be between 0 and 10.".to_string());
+        }
+
+        if let Some(route) = self.allowed_routes().iter().find(|route| !is_valid_route_pattern(route)) {
+            return Err(format!("Invalid route pattern '{}', expected '<METHOD> <path>'", route));
+        }
+
+        if self.cache_max_age_secs.is_some_and(|max_age| max_age < 0) {
+            return Err("Cache max age cannot be negative.".to_string());
+        }
+
+        let currency = self.currency();
+        if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err("Currency must be a three-letter ISO 4217 code.".to_string());
+        }
+        let prices = [self.base_price_cents, self.overage_price_per_1000_units_cents, self.price_per_gb_cents];
+        if prices.iter().any(|price| *price < 0) || self.included_units.is_some_and(|units| units < 0) {
+            return Err("Prices and included units cannot be negative.".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn create_plan(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<PlanRegistry>,
+    req: HttpRequest,
+    settings: web::Json<PlanSettings>,
+) -> impl Responder {
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let created = async {
+        let mut tx = db_pool.begin().await?;
+
+        // Only one plan can be the default
+        if settings.is_default {
+            sqlx::query!("UPDATE plans SET is_default = false WHERE is_default")
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let record = sqlx::query!(
+            r#"
+            INSERT INTO plans (name, rate_limit_max_requests, rate_limit_window_secs, rate_limit_algorithm,
+                daily_request_quota, monthly_request_quota, quota_action, degraded_max_requests, degraded_window_secs,
+                priority, allowed_routes, cache_max_age_secs, is_default, currency, base_price_cents, included_units,
+                overage_price_per_1000_units_cents, price_per_gb_cents)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+            RETURNING id
+            "#,
+            settings.name.trim(),
+            settings.rate_limit_max_requests,
+            settings.rate_limit_window_secs,
+            settings.algorithm().ok().flatten(),
+            settings.daily_request_quota,
+            settings.monthly_request_quota,
+            settings.quota_action().unwrap_or(QuotaAction::Block).as_str(),
+            settings.degraded_max_requests,
+            settings.degraded_window_secs,
+            settings.priority,
+            &settings.allowed_routes()[..],
+            settings.cache_max_age_secs,
+            settings.is_default,
+            settings.currency(),
+            settings.base_price_cents,
+            settings.included_units,
+            settings.overage_price_per_1000_units_cents,
+            settings.price_per_gb_cents
+        )
+            .fetch_one(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(record.id)
+    }.await;
+
+    match created {
+        Ok(id) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "plan.created",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "plan_id": id })),
+                before: None,
+                after: Some(settings.snapshot()),
+            }).await;
+
+            HttpResponse::Created().json(serde_json::json!({ "id": id }))
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("A plan with this name already exists.")
+        }
+        Err(e) => {
+            eprintln!("Error creating plan: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+pub async fn update_plan(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<PlanRegistry>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    settings: web::Json<PlanSettings>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let updated = async {
+        let mut tx = db_pool.begin().await?;
+
+        let old_plan = sqlx::query_as!(
+            Plan,
+            r#"
+            SELECT id, name, rate_limit_max_requests, rate_limit_window_secs, rate_limit_algorithm,
+                daily_request_quota, monthly_request_quota, quota_action, degraded_max_requests, degraded_window_secs,
+                priority, allowed_routes, cache_max_age_secs, is_default, currency, base_price_cents, included_units,
+                overage_price_per_1000_units_cents, price_per_gb_cents
+            FROM plans
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            id
+        )
+            .fetch_optional(&mut *tx)
+            .await?;
+        let old_plan = match old_plan {
+            Some(old_plan) => old_plan,
+            None => return Ok(None),
+        };
+
+        if settings.is_default {
+            sqlx::query!("UPDATE plans SET is_default = false WHERE is_default AND id <> $1", id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE plans
+            SET name = $1, rate_limit_max_requests = $2, rate_limit_window_secs = $3, rate_limit_algorithm = $4,
+                daily_request_quota = $5, monthly_request_quota = $6, quota_action = $7, degraded_max_requests = $8,
+                degraded_window_secs = $9, priority = $10, allowed_routes = $11, cache_max_age_secs = $12, is_default = $13,
+                currency = $14, base_price_cents = $15, included_units = $16, overage_price_per_1000_units_cents = $17,
+                price_per_gb_cents = $18
+            WHERE id = $19
+            "#,
+            settings.name.trim(),
+            settings.rate_limit_max_requests,
+            settings.rate_limit_window_secs,
+            settings.algorithm().ok().flatten(),
+            settings.daily_request_quota,
+            settings.monthly_request_quota,
+            settings.quota_action().unwrap_or(QuotaAction::Block).as_str(),
+            settings.degraded_max_requests,
+            settings.degraded_window_secs,
+            settings.priority,
+            &settings.allowed_routes()[..],
+            settings.cache_max_age_secs,
+            settings.is_default,
+            settings.currency(),
+            settings.base_price_cents,
+            settings.included_units,
+            settings.overage_price_per_1000_units_cents,
+            settings.price_per_gb_cents,
+            id
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(old_plan))
+    }.await;
+
+    match updated {
+        Ok(Some(old_plan)) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "plan.updated",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "plan_id": id })),
+                before: serde_json::to_value(&old_plan).ok(),
+                after: Some(settings.snapshot()),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("A plan with this name already exists.")
+        }
+        Err(e) => {
+            eprintln!("Error updating plan: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+// Users and organizations on the deleted plan fall back to the default plan
+pub async fn delete_plan(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<PlanRegistry>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM plans
+        WHERE id = $1 AND NOT is_default
+        RETURNING name
+        "#,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(plan)) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "plan.deleted",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "plan_id": id })),
+                before: Some(serde_json::json!({ "name": plan.name })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => {
+            let is_default = sqlx::query_scalar!("SELECT is_default FROM plans WHERE id = $1", id)
+                .fetch_optional(&**db_pool)
+                .await;
+            match is_default {
+                Ok(Some(_)) => HttpResponse::Conflict().body("The default plan cannot be deleted, make another plan the default first."),
+                Ok(None) => HttpResponse::NotFound().finish(),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PlanAssignment {
+    // `null` puts the user or organization back on the default plan
+    pub plan_id: Option<i32>,
+}
+
+pub async fn set_user_plan(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    assignment: web::Json<PlanAssignment>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_user AS (
+            SELECT id, plan_id FROM users WHERE id = $2 FOR UPDATE
+        )
+        UPDATE users SET plan_id = $1
+        FROM old_user
+        WHERE users.id = old_user.id
+        RETURNING old_user.plan_id AS old_plan_id
+        "#,
+        assignment.plan_id,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(user)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(id),
+                action: "user.plan_changed",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({ "plan_id": user.old_plan_id })),
+                after: Some(serde_json::json!({ "plan_id": assignment.plan_id })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => HttpResponse::BadRequest().body("Unknown plan."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Keys of the organization are limited by its plan rather than by the plan of their creator
+pub async fn set_organization_plan(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    assignment: web::Json<PlanAssignment>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_organization_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_organization AS (
+            SELECT id, plan_id FROM organizations WHERE id = $2 FOR UPDATE
+        )
+        UPDATE organizations SET plan_id = $1
+        FROM old_organization
+        WHERE organizations.id = old_organization.id
+        RETURNING old_organization.plan_id AS old_plan_id
+        "#,
+        assignment.plan_id,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(organization)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "organization.plan_changed",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "organization_id": id })),
+                before: Some(serde_json::json!({ "plan_id": organization.old_plan_id })),
+                after: Some(serde_json::json!({ "plan_id": assignment.plan_id })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => HttpResponse::BadRequest().body("Unknown plan."),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
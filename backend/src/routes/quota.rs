@@ -0,0 +1,135 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
+use sqlx::PgPool;
+use crate::config::redis::RedisPool;
+use crate::models::quota::{QuotaPeriodResponse, QuotaStatusResponse};
+use crate::utils::plans::PlanRegistry;
+use crate::utils::quota::{self, QuotaAction};
+
+// A subject requests are counted for, as `(subject, name, plan_id, monthly_quota_override)`
+type QuotaSubject = (String, String, Option<i32>, Option<i64>);
+
+/// Quotas of the personal keys, organizations and client certificates of the current user,
+/// with their usage in the current day and month.
+pub async fn get_quota_status(
+    db_pool: web::Data<PgPool>,
+    redis_client: web::Data<RedisPool>,
+    plans: web::Data<PlanRegistry>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user_id = match req.extensions().get::<String>().and_then(|id| id.parse::<i32>().ok()) {
+        Some(user_id) => user_id,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    let subjects = match quota_subjects(&db_pool, user_id).await {
+        Ok(subjects) => subjects,
+        Err(e) => {
+            eprintln!("Error fetching quota subjects: {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut statuses = Vec::with_capacity(subjects.len());
+    for (subject, name, plan_id, monthly_override) in subjects {
+        let plan = match plans.resolve(plan_id).await {
+            Ok(plan) => plan,
+            Err(_) => return HttpResponse::InternalServerError().finish(),
+        };
+        let limits = quota::limits(plan.as_ref(), monthly_override);
+        let periods = limits.iter().map(|limit| limit.period).collect::<Vec<_>>();
+
+        let usage = if periods.is_empty() {
+            Vec::new()
+        } else {
+            match quota::current_usage(&redis_client, &db_pool, &subject, &periods).await {
+                Ok(usage) => usage,
+                Err(response) => return response,
+            }
+        };
+
+        let periods = limits
+            .iter()
+            .zip(usage)
+            .map(|(limit, usage)| QuotaPeriodResponse {
+                period: limit.period.as_str(),
+                current: limit.period.current(),
+                quota: limit.quota,
+                used: usage.used,
+                remaining: (limit.quota - usage.used).max(0),
+                overage: usage.overage,
+                resets_at: limit.period.resets_at().unix_timestamp(),
+            })
+            .collect::<Vec<_>>();
+
+        statuses.push(QuotaStatusResponse {
+            subject,
+            name,
+            action: plan.as_ref().map(|plan| plan.quota_action()).unwrap_or(QuotaAction::Block).as_str(),
+            plan: plan.map(|plan| plan.name),
+            exhausted: periods.iter().any(|period| period.remaining == 0),
+            periods,
+        });
+    }
+
+    HttpResponse::Ok().json(statuses)
+}
+
+async fn quota_subjects(db_pool: &PgPool, user_id: i32) -> Result<Vec<QuotaSubject>, sqlx::Error> {
+    let keys = sqlx::query!(
+        r#"
+        SELECT api_keys.id, api_keys.name, users.plan_id
+        FROM api_keys
+        JOIN users ON users.id = api_keys.user_id
+        WHERE api_keys.user_id = $1 AND api_keys.organization_id IS NULL AND api_keys.revoked_at IS NULL
+            AND (api_keys.expires_at IS NULL OR api_keys.expires_at > now())
+        ORDER BY api_keys.created_at
+        "#,
+        user_id
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    let organizations = sqlx::query!(
+        r#"
+        SELECT organizations.id, organizations.name, organizations.plan_id, organizations.monthly_request_quota
+        FROM organizations
+        JOIN organization_members ON organization_members.organization_id = organizations.id
+        WHERE organization_members.user_id = $1
+        ORDER BY organizations.name
+        "#,
+        user_id
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    // Requests authenticated with a client certificate are counted for the user
+    let certificates = sqlx::query!(
+        r#"
+        SELECT users.plan_id
+        FROM users
+        WHERE users.id = $1
+            AND EXISTS (SELECT 1 FROM client_certificates WHERE user_id = users.id AND revoked_at IS NULL)
+        "#,
+        user_id
+    )
+        .fetch_optional(db_pool)
+        .await?;
+
+    let mut subjects = keys
+        .into_iter()
+        .map(|key| (quota::key_subject(key.id), key.name, key.plan_id, None))
+        .collect::<Vec<_>>();
+    subjects.extend(organizations.into_iter().map(|organization| {
+        (
+            quota::organization_subject(organization.id),
+            organization.name,
+            organization.plan_id,
+            organization.monthly_request_quota,
+        )
+    }));
+    if let Some(certificates) = certificates {
+        subjects.push((quota::user_subject(user_id), "Client certificates".to_string(), certificates.plan_id, None));
+    }
+
+    Ok(subjects)
+}
@@ -0,0 +1,49 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web::web::ServiceConfig;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::utils::permissions::Permission;
+
+pub fn configure_role_routes(cfg: &mut ServiceConfig) {
+    cfg.route("/roles", web::get().to(list_roles).wrap(RequirePermission::new(Permission::UsersRead)));
+}
+
+#[derive(Serialize)]
+pub struct Role {
+    pub name: String,
+    pub rank: i16,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+pub async fn list_roles(db_pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query_as!(
+        Role,
+        r#"
+        SELECT
+            roles.name,
+            roles.rank,
+            roles.description,
+            COALESCE(
+                ARRAY_AGG(role_permissions.permission ORDER BY role_permissions.permission)
+                    FILTER (WHERE role_permissions.permission IS NOT NULL),
+                '{}'
+            ) AS "permissions!"
+        FROM roles
+        LEFT JOIN role_permissions ON role_permissions.role = roles.name
+        GROUP BY roles.name
+        ORDER BY roles.rank
+        "#
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(roles) => HttpResponse::Ok().json(roles),
+        Err(e) => {
+            eprintln!("Error fetching roles: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
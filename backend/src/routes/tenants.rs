@@ -0,0 +1,371 @@
+use actix_web::{web, HttpResponse, Responder, HttpRequest};
+use actix_web::web::ServiceConfig;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::utils::audit::{self, actor, AuditEntry};
+use crate::utils::permissions::Permission;
+use crate::utils::tenants::{admin_tenant_scope, TenantRegistry};
+use super::user::TenantQuery;
+
+pub fn configure_tenant_routes(cfg: &mut ServiceConfig) {
+    cfg.service(
+        web::scope("tenants")
+            .route("/{id}/routes", web::put().to(set_tenant_routes).wrap(RequirePermission::new(Permission::TenantsManage)))
+            .route("/{id}", web::put().to(update_tenant).wrap(RequirePermission::new(Permission::TenantsManage)))
+            .route("", web::post().to(create_tenant).wrap(RequirePermission::new(Permission::TenantsManage)))
+            .route("", web::get().to(list_tenants).wrap(RequirePermission::new(Permission::TenantsManage)))
+    )
+        .route("/usage", web::get().to(get_tenant_usage).wrap(RequirePermission::new(Permission::UsersRead)));
+}
+
+#[derive(Serialize)]
+pub struct TenantResponse {
+    pub id: i32,
+    pub slug: String,
+    pub name: String,
+    pub hostname: Option<String>,
+    pub rate_limit_max_requests: Option<i32>,
+    pub rate_limit_window_secs: Option<i32>,
+    pub allowed_routes: Vec<String>,
+    pub created_at: String,
+}
+
+pub async fn list_tenants(db_pool: web::Data<PgPool>) -> impl Responder {
+    let result = sqlx::query!(
+        r#"
+        SELECT tenants.id, tenants.slug, tenants.name, tenants.hostname, tenants.rate_limit_max_requests,
+            tenants.rate_limit_window_secs, tenants.created_at,
+            COALESCE(ARRAY_AGG(tenant_routes.route_pattern ORDER BY tenant_routes.route_pattern)
+                FILTER (WHERE tenant_routes.route_pattern IS NOT NULL), '{}') AS "allowed_routes!"
+        FROM tenants
+        LEFT JOIN tenant_routes ON tenant_routes.tenant_id = tenants.id
+        GROUP BY tenants.id
+        ORDER BY tenants.id
+        "#
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(tenants) => HttpResponse::Ok().json(
+            tenants
+                .into_iter()
+                .map(|tenant| TenantResponse {
+                    id: tenant.id,
+                    slug: tenant.slug,
+                    name: tenant.name,
+                    hostname: tenant.hostname,
+                    rate_limit_max_requests: tenant.rate_limit_max_requests,
+                    rate_limit_window_secs: tenant.rate_limit_window_secs,
+                    allowed_routes: tenant.allowed_routes,
+                    created_at: tenant.created_at.to_string(),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TenantSettings {
+    pub slug: String,
+    pub name: String,
+    pub hostname: Option<String>,
+    pub rate_limit_max_requests: Option<i32>,
+    pub rate_limit_window_secs: Option<i32>,
+}
+
+impl TenantSettings {
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "slug": self.slug,
+            "name": self.name.trim(),
+            "hostname": self.hostname(),
+            "rate_limit_max_requests": self.rate_limit_max_requests,
+            "rate_limit_window_secs": self.rate_limit_window_secs,
+        })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid_slug = !self.slug.is_empty()
+            && self.slug.len() <= 64
+            && self.slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+        if !valid_slug {
+            return Err("Slug must be 1 to 64 lowercase letters, digits or dashes.".to_string());
+        }
+
+        if self.name.trim().is_empty() || self.name.chars().count() > 100 {
+            return Err("Tenant name must be between 1 and 100 characters.".to_string());
+        }
+
+        match (self.rate_limit_max_requests, self.rate_limit_window_secs) {
+            (Some(max_requests), Some(window_secs)) if max_requests < 0 || window_secs <= 0 => {
+                Err("Rate limit values must be positive.".to_string())
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                Err("rate_limit_max_requests and rate_limit_window_secs must be set together.".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn hostname(&self) -> Option<String> {
+        self.hostname
+            .as_deref()
+            .map(|hostname| hostname.trim().to_lowercase())
+            .filter(|hostname| !hostname.is_empty())
+    }
+}
+
+pub async fn create_tenant(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<TenantRegistry>,
+    req: HttpRequest,
+    settings: web::Json<TenantSettings>,
+) -> impl Responder {
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO tenants (slug, name, hostname, rate_limit_max_requests, rate_limit_window_secs)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        settings.slug,
+        settings.name.trim(),
+        settings.hostname(),
+        settings.rate_limit_max_requests,
+        settings.rate_limit_window_secs
+    )
+        .fetch_one(&**db_pool)
+        .await;
+
+    match result {
+        Ok(record) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "tenant.created",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "tenant_id": record.id })),
+                before: None,
+                after: Some(settings.snapshot()),
+            }).await;
+
+            HttpResponse::Created().json(serde_json::json!({ "id": record.id }))
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("A tenant with this slug or hostname already exists.")
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+pub async fn update_tenant(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<TenantRegistry>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    settings: web::Json<TenantSettings>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(e) = settings.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        WITH old_tenant AS (
+            SELECT id, slug, name, hostname, rate_limit_max_requests, rate_limit_window_secs
+            FROM tenants
+            WHERE id = $6
+            FOR UPDATE
+        )
+        UPDATE tenants
+        SET slug = $1, name = $2, hostname = $3, rate_limit_max_requests = $4, rate_limit_window_secs = $5
+        FROM old_tenant
+        WHERE tenants.id = old_tenant.id
+        RETURNING old_tenant.slug AS "old_slug!", old_tenant.name AS "old_name!", old_tenant.hostname AS old_hostname,
+            old_tenant.rate_limit_max_requests AS old_rate_limit_max_requests,
+            old_tenant.rate_limit_window_secs AS old_rate_limit_window_secs
+        "#,
+        settings.slug,
+        settings.name.trim(),
+        settings.hostname(),
+        settings.rate_limit_max_requests,
+        settings.rate_limit_window_secs,
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    match result {
+        Ok(Some(old_tenant)) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "tenant.updated",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "tenant_id": id })),
+                before: Some(serde_json::json!({
+                    "slug": old_tenant.old_slug,
+                    "name": old_tenant.old_name,
+                    "hostname": old_tenant.old_hostname,
+                    "rate_limit_max_requests": old_tenant.old_rate_limit_max_requests,
+                    "rate_limit_window_secs": old_tenant.old_rate_limit_window_secs,
+                })),
+                after: Some(settings.snapshot()),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            HttpResponse::Conflict().body("A tenant with this slug or hostname already exists.")
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TenantRoutes {
+    // `<METHOD> <path glob>` patterns such as `GET /api/v1/*`, every route is allowed when empty
+    pub routes: Vec<String>,
+}
+
+pub(crate) fn is_valid_route_pattern(route: &str) -> bool {
+    match route.split_once(' ') {
+        Some((method, path)) => !method.is_empty() && path.starts_with('/'),
+        None => false,
+    }
+}
+
+pub async fn set_tenant_routes(
+    db_pool: web::Data<PgPool>,
+    registry: web::Data<TenantRegistry>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: web::Json<TenantRoutes>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    let routes: Vec<String> = body.routes.iter().map(|route| route.trim().to_string()).collect();
+    if let Some(route) = routes.iter().find(|route| !is_valid_route_pattern(route)) {
+        return HttpResponse::BadRequest().body(format!("Invalid route pattern '{}', expected '<METHOD> <path>'", route));
+    }
+
+    let updated = async {
+        let mut tx = db_pool.begin().await?;
+
+        let tenant = sqlx::query!("SELECT id FROM tenants WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if tenant.is_none() {
+            return Ok(None);
+        }
+
+        let old_routes = sqlx::query_scalar!("DELETE FROM tenant_routes WHERE tenant_id = $1 RETURNING route_pattern", id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            "INSERT INTO tenant_routes (tenant_id, route_pattern) SELECT $1, UNNEST($2::TEXT[]) ON CONFLICT DO NOTHING",
+            id,
+            &routes[..]
+        )
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(old_routes))
+    }.await;
+
+    match updated {
+        Ok(Some(old_routes)) => {
+            registry.invalidate();
+
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: None,
+                action: "tenant.routes_changed",
+                ip: actor_ip,
+                details: Some(serde_json::json!({ "tenant_id": id })),
+                before: Some(serde_json::json!({ "routes": old_routes })),
+                after: Some(serde_json::json!({ "routes": routes })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TenantUsageQuery {
+    // Number of past days to aggregate, 30 by default
+    pub days: Option<u16>,
+}
+
+// Daily API usage of the current tenant, or of any tenant for superadmins
+pub async fn get_tenant_usage(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    tenant: web::Query<TenantQuery>,
+    query: web::Query<TenantUsageQuery>,
+) -> impl Responder {
+    let tenant_id = match admin_tenant_scope(&db_pool, &req, tenant.tenant.as_deref()).await {
+        Ok(tenant_id) => tenant_id,
+        Err(response) => return response,
+    };
+    let days = query.days.unwrap_or(30).clamp(1, 366) as i32;
+
+    let result = sqlx::query!(
+        r#"
+        SELECT
+            tenant_id,
+            DATE(request_time) AS "day!",
+            COUNT(*) AS "requests!",
+            COALESCE(SUM(cost), 0)::BIGINT AS "units!",
+            COUNT(*) FILTER (WHERE status_code >= 400) AS "errors!",
+            COUNT(DISTINCT user_id) AS "users!"
+        FROM api_usage
+        WHERE ($1::INTEGER IS NULL OR tenant_id = $1) AND request_time >= CURRENT_DATE - make_interval(days => $2)
+        GROUP BY 1, 2
+        ORDER BY 2 DESC, 1
+        "#,
+        tenant_id,
+        days
+    )
+        .fetch_all(&**db_pool)
+        .await;
+
+    match result {
+        Ok(rows) => HttpResponse::Ok().json(
+            rows.into_iter()
+                .map(|row| serde_json::json!({
+                    "tenant_id": row.tenant_id,
+                    "day": row.day.to_string(),
+                    "requests": row.requests,
+                    "units": row.units,
+                    "errors": row.errors,
+                    "users": row.users,
+                }))
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            eprintln!("Error fetching tenant usage: {:?}", e);
+            HttpResponse::InternalServerError().body("Error fetching tenant usage")
+        }
+    }
+}
@@ -1,19 +1,35 @@
 use actix_web::{web, HttpResponse, Responder, HttpRequest, HttpMessage};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
+use crate::config::redis::RedisPool;
 use crate::models::api_usage::{ApiUsage, ApiUsageResponse};
 use actix_web::web::ServiceConfig;
+use super::impersonation::start_impersonation;
+use super::client_certificates::{add_client_certificate, list_client_certificates, revoke_client_certificate};
+use super::api_keys::{default_scopes, insert_api_key, key_snapshot, parse_scopes, replace_api_key, DEFAULT_KEY_NAME};
+use crate::utils::scopes::validate_scopes;
+use crate::utils::audit::{self, actor, actor_ip, AuditEntry};
+use crate::utils::login_guard;
+use crate::utils::permissions::{ensure_outranks, Permission};
+use crate::middlewares::permission_guard::RequirePermission;
+use crate::utils::tenants::{admin_tenant_scope, ensure_user_in_scope, request_tenant_id};
 
 pub fn configure_user_routes(cfg: &mut ServiceConfig) {
     cfg.service(
         web::scope("users")
-            .route("/{id}/revoke", web::post().to(revoke))
-            .route("/{id}/create_api_key", web::post().to(create_api_key))
-            .route("/{id}/{permission}", web::post().to(change_permission))
-            .route("/{id}", web::delete().to(delete_user))
-            .route("/{id}", web::get().to(get_user_by_id))
-            .route("", web::post().to(add_user))
-            .route("", web::get().to(get_users))
+            .route("/{id}/revoke", web::post().to(revoke).wrap(RequirePermission::new(Permission::ApiKeysRevoke)))
+            .route("/{id}/create_api_key", web::post().to(create_api_key).wrap(RequirePermission::new(Permission::ApiKeysCreate)))
+            .route("/{id}/impersonate", web::post().to(start_impersonation).wrap(RequirePermission::new(Permission::UsersImpersonate)))
+            .route("/{id}/unlock", web::post().to(unlock).wrap(RequirePermission::new(Permission::LoginsUnlock)))
+            .route("/{id}/certificates/{certificate_id}", web::delete().to(revoke_client_certificate).wrap(RequirePermission::new(Permission::CertificatesManage)))
+            .route("/{id}/certificates", web::get().to(list_client_certificates).wrap(RequirePermission::new(Permission::CertificatesManage)))
+            .route("/{id}/certificates", web::post().to(add_client_certificate).wrap(RequirePermission::new(Permission::CertificatesManage)))
+            .route("/{id}/role", web::put().to(change_role).wrap(RequirePermission::new(Permission::UsersManageRoles)))
+            .route("/{id}/plan", web::put().to(super::plans::set_user_plan).wrap(RequirePermission::new(Permission::PlansManage)))
+            .route("/{id}", web::delete().to(delete_user).wrap(RequirePermission::new(Permission::UsersDelete)))
+            .route("/{id}", web::get().to(get_user_by_id).wrap(RequirePermission::new(Permission::UsersRead)))
+            .route("", web::post().to(add_user).wrap(RequirePermission::new(Permission::UsersCreate)))
+            .route("", web::get().to(get_users).wrap(RequirePermission::new(Permission::UsersRead)))
     );
 }
 
@@ -22,13 +38,38 @@ pub struct User {
     pub id: i32,
     pub name: String,
     pub email: String,
-    pub password_hash: String,
-    pub api_key: Option<String>,
-    pub permission: i16,
+    pub api_key_prefix: Option<String>,
+    pub role: String,
+    pub tenant_id: i32,
 }
 
-pub async fn get_users(db_pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query_as!(User, "SELECT id, name, email, api_key, permission, password_hash FROM users")
+#[derive(Deserialize)]
+pub struct TenantQuery {
+    // Slug of another tenant, or `all`, for superadmins
+    pub tenant: Option<String>,
+}
+
+pub async fn get_users(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<TenantQuery>,
+) -> impl Responder {
+    let tenant_id = match admin_tenant_scope(&db_pool, &req, query.tenant.as_deref()).await {
+        Ok(tenant_id) => tenant_id,
+        Err(response) => return response,
+    };
+
+    let result = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, name, email, role, tenant_id,
+            (SELECT key_prefix FROM api_keys WHERE api_keys.user_id = users.id AND revoked_at IS NULL ORDER BY created_at DESC LIMIT 1) AS api_key_prefix
+        FROM users
+        WHERE $1::INTEGER IS NULL OR tenant_id = $1
+        ORDER BY id
+        "#,
+        tenant_id
+    )
         .fetch_all(&**db_pool)
         .await;
 
@@ -38,10 +79,20 @@ pub async fn get_users(db_pool: web::Data<PgPool>) -> impl Responder {
     }
 }
 
-pub async fn get_user_by_id(db_pool: web::Data<PgPool>, path: web::Path<i32>) -> impl Responder {
+pub async fn get_user_by_id(db_pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> impl Responder {
     let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
 
-    let result = sqlx::query_as!(User, "SELECT id, name, email, api_key, permission, password_hash FROM users WHERE id = $1",
+    let result = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, name, email, role, tenant_id,
+            (SELECT key_prefix FROM api_keys WHERE api_keys.user_id = users.id AND revoked_at IS NULL ORDER BY created_at DESC LIMIT 1) AS api_key_prefix
+        FROM users
+        WHERE id = $1
+        "#,
         id
     )
         .fetch_one(&**db_pool)
@@ -60,126 +111,334 @@ pub struct NewUser {
     pub email: String,
 }
 
-pub async fn generate_api_key() -> String {
-    use rand::distributions::Alphanumeric;
-    use rand::{thread_rng, Rng};
-
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(30)
-        .map(char::from)
-        .collect()
-}
+pub async fn add_user(db_pool: web::Data<PgPool>, req: HttpRequest, new_user: web::Json<NewUser>) -> impl Responder {
+    // Users are created in the tenant the administrator is working in
+    let tenant_id = match request_tenant_id(&req) {
+        Some(tenant_id) => tenant_id,
+        None => return HttpResponse::NotFound().body("Unknown tenant"),
+    };
 
-pub async fn add_user(db_pool: web::Data<PgPool>, new_user: web::Json<NewUser>) -> impl Responder {
-    let api_key = generate_api_key().await;
+    let mut tx = match db_pool.begin().await {
+        Ok(tx) => tx,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
 
-    let result = sqlx::query!("INSERT INTO users (name, email, api_key, permission) VALUES ($1, $2, $3, $4) RETURNING id",
+    let result = sqlx::query!("INSERT INTO users (name, email, tenant_id) VALUES ($1, $2, $3) RETURNING id",
         new_user.name,
         new_user.email,
-        api_key,
-        0
+        tenant_id
     )
-        .fetch_one(&**db_pool)
+        .fetch_one(&mut *tx)
         .await;
 
-    match result {
-        Ok(record) => HttpResponse::Created().json(record.id),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    let id = match result {
+        Ok(record) => record.id,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let created = match insert_api_key(&mut tx, id, None, DEFAULT_KEY_NAME, &default_scopes(), None).await {
+        Ok(created) => created,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if tx.commit().await.is_err() {
+        return HttpResponse::InternalServerError().finish();
     }
+
+    let (actor_user_id, actor_ip) = actor(&req);
+    audit::record(&db_pool, AuditEntry {
+        actor_user_id,
+        target_user_id: Some(id),
+        action: "user.created",
+        ip: actor_ip,
+        details: None,
+        before: None,
+        after: Some(serde_json::json!({
+            "name": new_user.name,
+            "email": new_user.email,
+            "tenant_id": tenant_id,
+            "api_key": key_snapshot(&created),
+        })),
+    }).await;
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    HttpResponse::Created().json(serde_json::json!({ "id": id, "api_key": created.api_key }))
 }
 
-pub async fn delete_user(db_pool: web::Data<PgPool>, path: web::Path<i32>) -> impl Responder {
+pub async fn delete_user(db_pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> impl Responder {
     let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+    if let Err(response) = ensure_outranks(&db_pool, &req, id).await {
+        return response;
+    }
 
-    let result = sqlx::query!("DELETE FROM users WHERE id = $1",
+    let result = sqlx::query!("DELETE FROM users WHERE id = $1 RETURNING name, email, role, tenant_id",
         id
     )
-        .execute(&**db_pool)
+        .fetch_optional(&**db_pool)
         .await;
 
     match result {
-        Ok(_) => HttpResponse::NoContent().finish(),
+        Ok(Some(user)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(id),
+                action: "user.deleted",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({
+                    "name": user.name,
+                    "email": user.email,
+                    "role": user.role,
+                    "tenant_id": user.tenant_id,
+                })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
-pub async fn refresh_api_key(db_pool: web::Data<PgPool>, req: HttpRequest,) -> impl Responder {
-    let user_id = req
+#[derive(Deserialize)]
+pub struct KeyNameQuery {
+    pub name: Option<String>,
+    // Comma separated, the scopes of the replaced key are kept when omitted
+    pub scopes: Option<String>,
+}
+
+impl KeyNameQuery {
+    fn scopes(&self) -> Result<Option<Vec<String>>, String> {
+        match &self.scopes {
+            Some(scopes) => {
+                let scopes = parse_scopes(scopes);
+                validate_scopes(&scopes)?;
+                Ok(Some(scopes))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+pub async fn refresh_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    query: web::Query<KeyNameQuery>,
+) -> impl Responder {
+    let user_id = match req
         .extensions()
         .get::<String>()
         .cloned()
-        .and_then(|id| id.parse::<i32>().ok());
-    let api_key = generate_api_key().await;
-
-    let result = sqlx::query!("UPDATE users SET api_key = $1 WHERE id = $2",
-        api_key,
-        user_id
-    )
-        .execute(&**db_pool)
-        .await;
-
-    match result {
-        Ok(_) => HttpResponse::NoContent().finish(),
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+    let name = query.name.as_deref().unwrap_or(DEFAULT_KEY_NAME);
+    let scopes = match query.scopes() {
+        Ok(scopes) => scopes,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    match replace_api_key(&db_pool, user_id, name, scopes).await {
+        Ok((replaced_prefixes, created)) => {
+            let (_, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(user_id),
+                target_user_id: Some(user_id),
+                action: "api_key.refreshed",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({ "key_prefixes": replaced_prefixes })),
+                after: Some(key_snapshot(&created)),
+            }).await;
+
+            HttpResponse::Ok().json(created)
+        }
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
-pub async fn change_permission(db_pool: web::Data<PgPool>, path: web::Path<(i32, i32)>) -> impl Responder {
-    let (id, permission) = path.into_inner();
+#[derive(Deserialize)]
+pub struct RoleChange {
+    pub role: String,
+}
 
-    if permission < 0 || permission > 1 {
-        return HttpResponse::BadRequest().finish();
+pub async fn change_role(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    role_change: web::Json<RoleChange>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+    let actor_user_id = match req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(actor_user_id) => actor_user_id,
+        None => return HttpResponse::BadRequest().body("Invalid user ID"),
+    };
+
+    if actor_user_id == id {
+        return HttpResponse::BadRequest().body("You cannot change your own role.");
     }
 
-    let result = sqlx::query!("UPDATE users SET permission = $1 WHERE id = $2",
-        permission as i16,
+    // Nobody can change the role of someone ranked at or above them
+    let actor_rank = match ensure_outranks(&db_pool, &req, id).await {
+        Ok(actor_rank) => actor_rank,
+        Err(response) => return response,
+    };
+
+    let ranks = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT rank FROM roles WHERE name = $1) AS new_rank,
+            target.role AS target_role
+        FROM users AS target
+        WHERE target.id = $2
+        "#,
+        role_change.role,
         id
     )
-        .execute(&**db_pool)
+        .fetch_optional(&**db_pool)
         .await;
 
-    match result {
-        Ok(_) => HttpResponse::NoContent().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    let ranks = match ranks {
+        Ok(Some(ranks)) => ranks,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let new_rank = match ranks.new_rank {
+        Some(new_rank) => new_rank,
+        None => return HttpResponse::BadRequest().body("Unknown role."),
+    };
+
+    // Nor grant a role above their own
+    if new_rank > actor_rank {
+        return HttpResponse::Forbidden().body("You cannot manage roles above your own.");
     }
-}
 
-pub async fn revoke(db_pool: web::Data<PgPool>, path: web::Path<i32>) -> impl Responder {
-    let id = path.into_inner();
-
-    let result = sqlx::query!("UPDATE users SET api_key = NULL WHERE id = $1",
+    let result = sqlx::query!("UPDATE users SET role = $1 WHERE id = $2",
+        role_change.role,
         id
     )
         .execute(&**db_pool)
         .await;
 
     match result {
-        Ok(_) => HttpResponse::NoContent().finish(),
+        Ok(_) => {
+            let actor_ip = actor_ip(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id: Some(actor_user_id),
+                target_user_id: Some(id),
+                action: "user.role_changed",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({ "role": ranks.target_role })),
+                after: Some(serde_json::json!({ "role": role_change.role })),
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
-pub async fn create_api_key(db_pool: web::Data<PgPool>, path: web::Path<i32>) -> impl Responder {
+pub async fn revoke(db_pool: web::Data<PgPool>, req: HttpRequest, path: web::Path<i32>) -> impl Responder {
     let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+    if let Err(response) = ensure_outranks(&db_pool, &req, id).await {
+        return response;
+    }
 
-    let result = sqlx::query!("UPDATE users SET api_key = $1 WHERE id = $2",
-        generate_api_key().await,
+    let result = sqlx::query!("UPDATE api_keys SET revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL RETURNING key_prefix",
         id
     )
-        .execute(&**db_pool)
+        .fetch_all(&**db_pool)
         .await;
 
     match result {
-        Ok(_) => HttpResponse::NoContent().finish(),
+        Ok(revoked) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(id),
+                action: "api_key.revoked_by_admin",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({
+                    "key_prefixes": revoked.into_iter().map(|key| key.key_prefix).collect::<Vec<_>>(),
+                })),
+                after: None,
+            }).await;
+
+            HttpResponse::NoContent().finish()
+        }
         Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
+pub async fn create_api_key(
+    db_pool: web::Data<PgPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<KeyNameQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+    if let Err(response) = ensure_outranks(&db_pool, &req, id).await {
+        return response;
+    }
+    let name = query.name.as_deref().unwrap_or(DEFAULT_KEY_NAME);
+    let scopes = match query.scopes() {
+        Ok(scopes) => scopes,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    // The full key is only returned here, it cannot be retrieved afterwards
+    match replace_api_key(&db_pool, id, name, scopes).await {
+        Ok((replaced_prefixes, created)) => {
+            let (actor_user_id, actor_ip) = actor(&req);
+            audit::record(&db_pool, AuditEntry {
+                actor_user_id,
+                target_user_id: Some(id),
+                action: "api_key.created_by_admin",
+                ip: actor_ip,
+                details: None,
+                before: Some(serde_json::json!({ "key_prefixes": replaced_prefixes })),
+                after: Some(key_snapshot(&created)),
+            }).await;
+
+            HttpResponse::Ok().json(created)
+        }
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApiKeyUsageQuery {
+    pub key_id: Option<i32>,
+}
+
 pub async fn get_api_key_usage(
     db_pool: web::Data<PgPool>,
     req: HttpRequest,
     path: web::Path<i32>,
+    query: web::Query<ApiKeyUsageQuery>,
 ) -> impl Responder {
     let size = path.into_inner(); // Maximum number of requests to fetch
 
@@ -193,15 +452,16 @@ pub async fn get_api_key_usage(
             ApiUsage,
             r#"
             SELECT
-                id, user_id, api_key, request_path, request_method,
-                request_time, request_ip, status_code
+                id, user_id, api_key_id, api_key_prefix, client_certificate_id, request_path, request_method,
+                request_time, request_ip, status_code, cost
             FROM api_usage
-            WHERE user_id = $1
+            WHERE user_id = $1 AND ($3::INTEGER IS NULL OR api_key_id = $3)
             ORDER BY request_time DESC
             LIMIT $2
             "#,
             user_id,
-            size as i64
+            size as i64,
+            query.key_id
         )
             .fetch_all(&**db_pool)
             .await;
@@ -213,12 +473,15 @@ pub async fn get_api_key_usage(
                     .map(|usage| ApiUsageResponse {
                         id: usage.id,
                         user_id: usage.user_id,
-                        api_key: usage.api_key,
+                        api_key_id: usage.api_key_id,
+                        api_key_prefix: usage.api_key_prefix,
+                        client_certificate_id: usage.client_certificate_id,
                         request_path: usage.request_path,
                         request_method: usage.request_method,
                         request_time: usage.request_time.to_string(),
                         request_ip: usage.request_ip,
                         status_code: usage.status_code,
+                        cost: usage.cost,
                     })
                     .collect();
 
@@ -232,4 +495,60 @@ pub async fn get_api_key_usage(
     } else {
         HttpResponse::BadRequest().body("Invalid user ID")
     }
+}
+
+#[derive(Deserialize)]
+pub struct UnlockQuery {
+    pub ip: Option<String>,
+}
+
+pub async fn unlock(
+    db_pool: web::Data<PgPool>,
+    redis_client: web::Data<RedisPool>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    query: web::Query<UnlockQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = ensure_user_in_scope(&db_pool, &req, id).await {
+        return response;
+    }
+    if let Err(response) = ensure_outranks(&db_pool, &req, id).await {
+        return response;
+    }
+
+    let user = sqlx::query!("SELECT email, tenant_id FROM users WHERE id = $1",
+        id
+    )
+        .fetch_optional(&**db_pool)
+        .await;
+
+    let user = match user {
+        Ok(Some(user)) => user,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    if login_guard::clear_account(&redis_client, Some(user.tenant_id), &user.email).await.is_err() {
+        return HttpResponse::InternalServerError().body("Failed to connect to Redis");
+    }
+
+    if let Some(ip) = &query.ip {
+        if login_guard::clear_ip(&redis_client, ip).await.is_err() {
+            return HttpResponse::InternalServerError().body("Failed to connect to Redis");
+        }
+    }
+
+    let (actor_user_id, actor_ip) = actor(&req);
+    audit::record(&db_pool, AuditEntry {
+        actor_user_id,
+        target_user_id: Some(id),
+        action: "login.unlocked",
+        ip: actor_ip,
+        details: Some(serde_json::json!({ "ip": query.ip })),
+        before: None,
+        after: None,
+    }).await;
+
+    HttpResponse::NoContent().finish()
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::sync::OnceLock;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Number of leading characters of a key kept in clear to find it and show it to its owner
+pub const PREFIX_LENGTH: usize = 8;
+
+static PEPPER: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Sets the secret used to hash API keys. Must be called once at startup.
+pub fn set_pepper(pepper: String) {
+    PEPPER.set(pepper.into_bytes()).expect("API key pepper already set");
+}
+
+fn pepper() -> &'static [u8] {
+    PEPPER.get().expect("API key pepper not set")
+}
+
+pub struct GeneratedApiKey {
+    // The full key, only ever shown to the user once
+    pub key: String,
+    pub prefix: String,
+    pub hash: String,
+}
+
+pub fn generate_api_key() -> GeneratedApiKey {
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+
+    let key: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(30)
+        .map(char::from)
+        .collect();
+
+    GeneratedApiKey {
+        prefix: key_prefix(&key),
+        hash: hash_api_key(&key),
+        key,
+    }
+}
+
+pub fn key_prefix(api_key: &str) -> String {
+    api_key.chars().take(PREFIX_LENGTH).collect()
+}
+
+pub fn hash_api_key(api_key: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(pepper()).expect("HMAC accepts keys of any size");
+    mac.update(api_key.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// Compares in constant time so the stored hash cannot be guessed from response times
+pub fn verify_api_key(api_key: &str, hash: &str) -> bool {
+    let expected = match hex::decode(hash) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+
+    let mut mac = HmacSha256::new_from_slice(pepper()).expect("HMAC accepts keys of any size");
+    mac.update(api_key.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Derives the secret used to sign requests made with a key.
+/// It is never stored and changes whenever the key itself changes.
+pub fn signing_secret(key_hash: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(pepper()).expect("HMAC accepts keys of any size");
+    mac.update(b"request-signing:");
+    mac.update(key_hash.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
@@ -0,0 +1,53 @@
+use actix_web::{HttpMessage, HttpRequest};
+use sqlx::PgPool;
+use crate::utils::client_ip::request_client_ip;
+
+pub struct AuditEntry<'a> {
+    pub actor_user_id: Option<i32>,
+    pub target_user_id: Option<i32>,
+    pub action: &'a str,
+    pub ip: Option<String>,
+    pub details: Option<serde_json::Value>,
+    // State of the changed record before and after the action, when there is one
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+// The user behind a dashboard request and the address it came from
+pub fn actor(req: &HttpRequest) -> (Option<i32>, Option<String>) {
+    let actor_user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok());
+    (actor_user_id, actor_ip(req))
+}
+
+// Forwarding headers are only trusted from known proxies, entries cannot be recorded with a forged address
+pub fn actor_ip(req: &HttpRequest) -> Option<String> {
+    request_client_ip(req).map(|ip| ip.to_string())
+}
+
+// Failing to write an audit entry must never fail the request that triggered it
+pub async fn record(db_pool: &PgPool, entry: AuditEntry<'_>) {
+    // Entries belong to the tenant of the affected user, or of the actor when nobody else is affected
+    let result = sqlx::query!(
+        "INSERT INTO audit_log (actor_user_id, target_user_id, action, ip, details, before, after, tenant_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE(
+             (SELECT tenant_id FROM users WHERE id = $2),
+             (SELECT tenant_id FROM users WHERE id = $1)
+         ))",
+        entry.actor_user_id,
+        entry.target_user_id,
+        entry.action,
+        entry.ip,
+        entry.details,
+        entry.before,
+        entry.after
+    )
+        .execute(db_pool)
+        .await;
+
+    if let Err(e) = result {
+        eprintln!("Error writing audit log entry '{}': {}", entry.action, e);
+    }
+}
@@ -0,0 +1,313 @@
+use std::str::FromStr;
+use sqlx::PgPool;
+use time::{Date, Month, OffsetDateTime};
+use crate::models::billing::Invoice;
+
+const BYTES_PER_GB: i128 = 1_000_000_000;
+
+/// A calendar month (UTC), the period invoices are issued for.
+#[derive(Clone, Copy)]
+pub struct BillingPeriod {
+    pub start: Date,
+}
+
+impl BillingPeriod {
+    pub fn current() -> Self {
+        Self::containing(OffsetDateTime::now_utc().date())
+    }
+
+    pub fn containing(date: Date) -> Self {
+        Self { start: Date::from_calendar_date(date.year(), date.month(), 1).unwrap_or(date) }
+    }
+
+    pub fn previous(&self) -> Self {
+        Self::containing(self.start.previous_day().unwrap_or(Date::MIN))
+    }
+
+    // First day of the next month, excluded from the period
+    pub fn end(&self) -> Date {
+        let year = if self.start.month() == Month::December { self.start.year() + 1 } else { self.start.year() };
+        Date::from_calendar_date(year, self.start.month().next(), 1).unwrap_or(Date::MAX)
+    }
+
+    // Only periods that are over can be invoiced, a partial month would be billed as final
+    pub fn has_ended(&self) -> bool {
+        self.end() <= OffsetDateTime::now_utc().date()
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}-{:02}", self.start.year(), u8::from(self.start.month()))
+    }
+}
+
+// `2026-01`
+impl FromStr for BillingPeriod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid billing period '{}', expected 'YYYY-MM'", value);
+        let (year, month) = value.trim().split_once('-').ok_or_else(invalid)?;
+        let year = year.parse::<i32>().map_err(|_| invalid())?;
+        let month = month.parse::<u8>().ok().and_then(|month| Month::try_from(month).ok()).ok_or_else(invalid)?;
+        let start = Date::from_calendar_date(year, month, 1).map_err(|_| invalid())?;
+        Ok(Self { start })
+    }
+}
+
+fn ceil_div(value: i128, divisor: i128) -> i64 {
+    ((value + divisor - 1) / divisor).clamp(0, i64::MAX as i128) as i64
+}
+
+/// Aggregates the API usage of the period per organization, for organization keys, and per user otherwise,
+/// and prices it with the current plan of each of them. Subscribers of a plan are invoiced its base price
+/// even without usage, callers without any plan are not invoiced.
+pub async fn compute_invoices(db_pool: &PgPool, period: BillingPeriod) -> Result<Vec<Invoice>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH usage AS (
+            SELECT
+                organization_id,
+                CASE WHEN organization_id IS NULL THEN user_id END AS user_id,
+                COUNT(*) AS requests,
+                SUM(cost) AS units,
+                SUM(cost) FILTER (WHERE overage) AS overage_units,
+                SUM(request_bytes) AS request_bytes,
+                SUM(response_bytes) AS response_bytes
+            FROM api_usage
+            WHERE request_time >= $1 AND request_time < $2
+            GROUP BY 1, 2
+        ),
+        subjects AS (
+            SELECT organization_id, user_id FROM usage
+            UNION
+            SELECT id, NULL FROM organizations WHERE plan_id IS NOT NULL
+            UNION
+            SELECT NULL, id FROM users WHERE plan_id IS NOT NULL
+        )
+        SELECT
+            subjects.organization_id,
+            subjects.user_id,
+            COALESCE(usage.requests, 0) AS "requests!",
+            COALESCE(usage.units, 0)::BIGINT AS "units!",
+            COALESCE(usage.overage_units, 0)::BIGINT AS "flagged_overage_units!",
+            COALESCE(usage.request_bytes, 0)::BIGINT AS "request_bytes!",
+            COALESCE(usage.response_bytes, 0)::BIGINT AS "response_bytes!",
+            plans.id AS plan_id,
+            plans.name AS plan_name,
+            plans.currency,
+            plans.base_price_cents,
+            plans.included_units,
+            plans.overage_price_per_1000_units_cents,
+            plans.price_per_gb_cents
+        FROM subjects
+        LEFT JOIN usage ON usage.organization_id IS NOT DISTINCT FROM subjects.organization_id
+            AND usage.user_id IS NOT DISTINCT FROM subjects.user_id
+        LEFT JOIN organizations ON organizations.id = subjects.organization_id
+        LEFT JOIN users ON users.id = subjects.user_id
+        JOIN plans ON plans.id = COALESCE(organizations.plan_id, users.plan_id, (SELECT id FROM plans WHERE is_default))
+        ORDER BY subjects.organization_id NULLS LAST, subjects.user_id
+        "#,
+        period.start.midnight(),
+        period.end().midnight()
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            // Units beyond those included, or only the ones accepted past a quota when the plan includes no units
+            let overage_units = match row.included_units {
+                Some(included_units) => (row.units - included_units).max(0),
+                None => row.flagged_overage_units,
+            };
+            let overage_cents = ceil_div(overage_units as i128 * row.overage_price_per_1000_units_cents as i128, 1000);
+            let transfer_bytes = row.request_bytes as i128 + row.response_bytes as i128;
+            let transfer_cents = ceil_div(transfer_bytes * row.price_per_gb_cents as i128, BYTES_PER_GB);
+
+            Invoice {
+                id: None,
+                subject: match row.organization_id {
+                    Some(organization_id) => format!("org:{}", organization_id),
+                    None => format!("user:{}", row.user_id.unwrap_or_default()),
+                },
+                organization_id: row.organization_id,
+                user_id: row.user_id,
+                period: period.label(),
+                plan_id: Some(row.plan_id),
+                plan: row.plan_name,
+                currency: row.currency,
+                requests: row.requests,
+                units: row.units,
+                overage_units,
+                request_bytes: row.request_bytes,
+                response_bytes: row.response_bytes,
+                base_cents: row.base_price_cents,
+                overage_cents,
+                transfer_cents,
+                total_cents: row.base_price_cents.saturating_add(overage_cents).saturating_add(transfer_cents),
+                finalized_at: None,
+            }
+        })
+        .collect())
+}
+
+/// Replaces the draft invoices of the period, so it can be generated again after late usage or a pricing fix.
+/// Finalized invoices are kept as they were issued. Returns the number of drafts saved.
+pub async fn save_invoices(db_pool: &PgPool, period: BillingPeriod, invoices: &[Invoice]) -> Result<u64, sqlx::Error> {
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!("DELETE FROM invoices WHERE period_start = $1 AND finalized_at IS NULL", period.start)
+        .execute(&mut *tx)
+        .await?;
+
+    let mut saved = 0;
+    for invoice in invoices {
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO invoices (subject, organization_id, user_id, period_start, period_end, plan_id, plan_name, currency,
+                requests, units, overage_units, request_bytes, response_bytes, base_cents, overage_cents, transfer_cents, total_cents)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
+            ON CONFLICT (subject, period_start) DO NOTHING
+            "#,
+            invoice.subject,
+            invoice.organization_id,
+            invoice.user_id,
+            period.start,
+            period.end(),
+            invoice.plan_id,
+            invoice.plan,
+            invoice.currency,
+            invoice.requests,
+            invoice.units,
+            invoice.overage_units,
+            invoice.request_bytes,
+            invoice.response_bytes,
+            invoice.base_cents,
+            invoice.overage_cents,
+            invoice.transfer_cents,
+            invoice.total_cents
+        )
+            .execute(&mut *tx)
+            .await?;
+        saved += result.rows_affected();
+    }
+
+    tx.commit().await?;
+    Ok(saved)
+}
+
+/// Issues the draft invoices of the period. Returns the number of invoices finalized.
+pub async fn finalize_invoices(db_pool: &PgPool, period: BillingPeriod) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        "UPDATE invoices SET finalized_at = now() WHERE period_start = $1 AND finalized_at IS NULL",
+        period.start
+    )
+        .execute(db_pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Generated invoices, of one period or all of them, optionally of one organization or user.
+pub async fn list_invoices(
+    db_pool: &PgPool,
+    period: Option<BillingPeriod>,
+    organization_id: Option<i32>,
+    user_id: Option<i32>,
+) -> Result<Vec<Invoice>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, subject, organization_id, user_id, period_start, plan_id, plan_name, currency, requests, units,
+            overage_units, request_bytes, response_bytes, base_cents, overage_cents, transfer_cents, total_cents, finalized_at
+        FROM invoices
+        WHERE ($1::DATE IS NULL OR period_start = $1)
+            AND ($2::INTEGER IS NULL OR organization_id = $2)
+            AND ($3::INTEGER IS NULL OR user_id = $3)
+        ORDER BY period_start DESC, id
+        "#,
+        period.map(|period| period.start),
+        organization_id,
+        user_id
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Invoice {
+            id: Some(row.id),
+            subject: row.subject,
+            organization_id: row.organization_id,
+            user_id: row.user_id,
+            period: BillingPeriod::containing(row.period_start).label(),
+            plan_id: row.plan_id,
+            plan: row.plan_name,
+            currency: row.currency,
+            requests: row.requests,
+            units: row.units,
+            overage_units: row.overage_units,
+            request_bytes: row.request_bytes,
+            response_bytes: row.response_bytes,
+            base_cents: row.base_cents,
+            overage_cents: row.overage_cents,
+            transfer_cents: row.transfer_cents,
+            total_cents: row.total_cents,
+            finalized_at: row.finalized_at.map(|t| t.to_string()),
+        })
+        .collect())
+}
+
+pub async fn has_invoices(db_pool: &PgPool, period: BillingPeriod) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM invoices WHERE period_start = $1) AS "exists!""#,
+        period.start
+    )
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(record.exists)
+}
+
+// Quotes fields containing a separator, a quote or a line break (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(invoices: &[Invoice]) -> String {
+    let optional = |value: Option<i32>| value.map(|value| value.to_string()).unwrap_or_default();
+
+    let mut csv = String::from(
+        "id,subject,organization_id,user_id,period,plan_id,plan,currency,requests,units,overage_units,\
+        request_bytes,response_bytes,base_cents,overage_cents,transfer_cents,total_cents,finalized_at\r\n",
+    );
+    for invoice in invoices {
+        let fields = [
+            optional(invoice.id),
+            csv_field(&invoice.subject),
+            optional(invoice.organization_id),
+            optional(invoice.user_id),
+            invoice.period.clone(),
+            optional(invoice.plan_id),
+            csv_field(&invoice.plan),
+            csv_field(&invoice.currency),
+            invoice.requests.to_string(),
+            invoice.units.to_string(),
+            invoice.overage_units.to_string(),
+            invoice.request_bytes.to_string(),
+            invoice.response_bytes.to_string(),
+            invoice.base_cents.to_string(),
+            invoice.overage_cents.to_string(),
+            invoice.transfer_cents.to_string(),
+            invoice.total_cents.to_string(),
+            invoice.finalized_at.clone().unwrap_or_default(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
@@ -0,0 +1,42 @@
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+// Changes made by another instance are picked up after this delay
+const TTL: Duration = Duration::from_secs(30);
+
+type Entry<T> = (Instant, Arc<T>);
+
+/// In-memory copy of a table shared by every worker, loaded again once it is older than `TTL`
+/// or after `invalidate`, so hot paths such as request middlewares do not hit the database.
+pub struct Cached<T> {
+    entry: RwLock<Option<Entry<T>>>,
+}
+
+impl<T> Cached<T> {
+    pub fn new() -> Self {
+        Self { entry: RwLock::new(None) }
+    }
+
+    // Called after the table is changed so this instance sees it immediately
+    pub fn invalidate(&self) {
+        *self.entry.write().unwrap() = None;
+    }
+
+    /// The cached value while it is fresh, otherwise the one returned by `load`, which is then cached.
+    pub async fn get_or_load<F, Fut, E>(&self, load: F) -> Result<Arc<T>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some((loaded_at, value)) = self.entry.read().unwrap().as_ref() {
+            if loaded_at.elapsed() < TTL {
+                return Ok(Arc::clone(value));
+            }
+        }
+
+        let value = Arc::new(load().await?);
+        *self.entry.write().unwrap() = Some((Instant::now(), Arc::clone(&value)));
+        Ok(value)
+    }
+}
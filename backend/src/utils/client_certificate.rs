@@ -0,0 +1,37 @@
+use std::any::Any;
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use sha2::{Digest, Sha256};
+
+// Certificate presented by the client during the TLS handshake, already verified against the client CA
+#[derive(Clone)]
+pub struct ClientCertificate {
+    // Hex encoded SHA-256 of the DER certificate
+    pub fingerprint: String,
+    pub subject: Option<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Self {
+        let subject = x509_parser::parse_x509_certificate(der)
+            .ok()
+            .map(|(_, certificate)| certificate.subject().to_string());
+
+        Self {
+            fingerprint: hex::encode(Sha256::digest(der)),
+            subject,
+        }
+    }
+}
+
+/// Connection callback attaching the client certificate, if any, to the connection data.
+pub fn extract_client_certificate(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(tls_stream) = connection.downcast_ref::<TlsStream<TcpStream>>() {
+        let (_, session) = tls_stream.get_ref();
+
+        if let Some(certificate) = session.peer_certificates().and_then(|certificates| certificates.first()) {
+            data.insert(ClientCertificate::from_der(certificate.as_ref()));
+        }
+    }
+}
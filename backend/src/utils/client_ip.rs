@@ -0,0 +1,182 @@
+use std::net::IpAddr;
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::HOST;
+use actix_web::{web, HttpRequest};
+use ipnet::IpNet;
+use crate::config::trusted_proxies::TrustedProxies;
+
+// Attached to the request extensions by `ApiKeyValidator`
+#[derive(Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Parses a CIDR range, a bare address being treated as a single host range.
+pub fn parse_cidr(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("Invalid IP address or CIDR range '{}'", value))
+}
+
+/// Finds the address of the client, only trusting `X-Forwarded-For` when it was set by a trusted proxy.
+///
+/// The header is read from right to left, each entry having been appended by the previous hop, and the
+/// first address that is not a trusted proxy is the client.
+pub fn resolve_client_ip(req: &HttpRequest, trusted_proxies: &TrustedProxies) -> Option<IpAddr> {
+    let mut client = req.peer_addr()?.ip();
+
+    if !trusted_proxies.contains(&client) {
+        return Some(client);
+    }
+
+    let forwarded_for = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|hop| hop.trim())
+        .collect::<Vec<_>>();
+
+    for hop in forwarded_for.into_iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            // A malformed entry cannot be trusted, nor anything to its left
+            Err(_) => break,
+        }
+    }
+
+    Some(client)
+}
+
+/// Address of the client of a dashboard request, resolved with the trusted proxies of the app.
+/// Without them, forwarding headers are ignored and the peer address is used.
+pub fn request_client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    match req.app_data::<web::Data<TrustedProxies>>() {
+        Some(trusted_proxies) => resolve_client_ip(req, trusted_proxies),
+        None => resolve_client_ip(req, &TrustedProxies::default()),
+    }
+}
+
+/// Host the request was sent to. `X-Forwarded-Host` and `Forwarded: host=` are only honoured when set by a
+/// trusted proxy, otherwise clients could pick any tenant by hostname.
+pub fn request_host(req: &ServiceRequest, trusted_proxies: &TrustedProxies) -> String {
+    let from_proxy = req.peer_addr().is_some_and(|peer| trusted_proxies.contains(&peer.ip()));
+    if from_proxy {
+        return req.connection_info().host().to_string();
+    }
+
+    // HTTP/2 requests carry the host in the URI rather than in a header
+    req.headers()
+        .get(HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(|host| host.to_string())
+        .or_else(|| req.uri().authority().map(|authority| authority.to_string()))
+        .unwrap_or_default()
+}
+
+/// Returns the origin (`scheme://host[:port]`) of the page that sent the request, from `Origin` or `Referer`.
+pub fn request_origin(req: &ServiceRequest) -> Option<String> {
+    if let Some(origin) = req.headers().get("origin").and_then(|v| v.to_str().ok()) {
+        return Some(origin.to_string());
+    }
+
+    let referer = req.headers().get("referer").and_then(|v| v.to_str().ok())?;
+    let (scheme, rest) = referer.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    Some(format!("{}://{}", scheme, host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn proxies(cidrs: &[&str]) -> TrustedProxies {
+        TrustedProxies(cidrs.iter().map(|cidr| parse_cidr(cidr).unwrap()).collect())
+    }
+
+    fn request(peer: &str, forwarded_for: &[&str]) -> HttpRequest {
+        let mut req = TestRequest::default().peer_addr(format!("{}:443", peer).parse().unwrap());
+        for header in forwarded_for {
+            req = req.append_header(("x-forwarded-for", *header));
+        }
+        req.to_http_request()
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let req = request("203.0.113.7", &["198.51.100.1"]);
+        assert_eq!(resolve_client_ip(&req, &proxies(&["10.0.0.0/8"])), ip("203.0.113.7"));
+        assert_eq!(resolve_client_ip(&req, &TrustedProxies::default()), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_is_the_first_untrusted_hop_from_the_right() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+
+        let req = request("10.0.0.1", &["198.51.100.1"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("198.51.100.1"));
+
+        let req = request("10.0.0.1", &["198.51.100.1, 10.0.0.2, 10.0.0.3"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("198.51.100.1"));
+
+        // Several headers are read as one list, in order
+        let req = request("10.0.0.1", &["198.51.100.1", "10.0.0.2"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn spoofed_entries_left_of_the_client_are_ignored() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+
+        // The client sent its own header, which the proxy appended to
+        let req = request("10.0.0.1", &["1.2.3.4, 198.51.100.1"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("198.51.100.1"));
+
+        // Claiming to be a trusted proxy does not help either
+        let req = request("10.0.0.1", &["1.2.3.4, 10.9.9.9, 198.51.100.1, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn garbage_entries_stop_the_walk() {
+        let trusted = proxies(&["10.0.0.0/8"]);
+
+        // Nothing left of a malformed entry can be trusted, the last trusted hop is used
+        let req = request("10.0.0.1", &["1.2.3.4, not-an-ip, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("10.0.0.2"));
+
+        let req = request("10.0.0.1", &["1.2.3.4, 198.51.100.1:8080"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("10.0.0.1"));
+
+        let req = request("10.0.0.1", &[""]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn requests_only_through_trusted_proxies_resolve_to_the_leftmost_hop() {
+        let trusted = proxies(&["10.0.0.0/8", "2001:db8::/32"]);
+
+        let req = request("10.0.0.1", &["2001:db8::1, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("2001:db8::1"));
+
+        let req = request("10.0.0.1", &[]);
+        assert_eq!(resolve_client_ip(&req, &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn bare_addresses_are_single_host_ranges() {
+        assert_eq!(parse_cidr("192.0.2.1").unwrap(), "192.0.2.1/32".parse::<IpNet>().unwrap());
+        assert_eq!(parse_cidr("2001:db8::1").unwrap(), "2001:db8::1/128".parse::<IpNet>().unwrap());
+        assert!(parse_cidr("192.0.2.0/33").is_err());
+        assert!(parse_cidr("example.com").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+use crate::config::redis::RedisPool;
+
+// KEYS the sorted sets of slot holders, then the sorted sets of waiters, in the same order.
+// ARGV request id, lease_ms, queue_size, queue_timeout_ms, then the limit of each semaphore.
+// Holders are scored by the expiry of their lease, waiters by the time they joined the queue.
+// Returns `{status, blocking}`, status being 1 when every slot was acquired, 0 when rejected and -1 when queued,
+// and blocking the 1-based index of the first semaphore without a free slot, or 0.
+static ACQUIRE: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local semaphores = #KEYS / 2
+local id = ARGV[1]
+local lease = tonumber(ARGV[2])
+local queue_size = tonumber(ARGV[3])
+local queue_timeout = tonumber(ARGV[4])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local blocking = 0
+for i = 1, semaphores do
+    local holders = KEYS[i]
+    local queue = KEYS[semaphores + i]
+    -- Slots of requests whose instance died, and waiters that gave up without leaving the queue
+    redis.call('ZREMRANGEBYSCORE', holders, '-inf', now)
+    redis.call('ZREMRANGEBYSCORE', queue, '-inf', now - queue_timeout * 2)
+
+    local free = tonumber(ARGV[4 + i]) - redis.call('ZCARD', holders)
+    -- Waiters ahead in the queue are served first
+    local ahead = redis.call('ZRANK', queue, id) or redis.call('ZCARD', queue)
+    if blocking == 0 and ahead >= free then
+        blocking = i
+    end
+end
+
+if blocking == 0 then
+    for i = 1, semaphores do
+        redis.call('ZADD', KEYS[i], now + lease, id)
+        redis.call('PEXPIRE', KEYS[i], lease)
+        redis.call('ZREM', KEYS[semaphores + i], id)
+    end
+    return {1, 0}
+end
+
+-- A request joins every queue at once, or none of them when one is full
+for i = 1, semaphores do
+    local queue = KEYS[semaphores + i]
+    if not redis.call('ZSCORE', queue, id) and redis.call('ZCARD', queue) >= queue_size then
+        return {0, blocking}
+    end
+end
+for i = 1, semaphores do
+    redis.call('ZADD', KEYS[semaphores + i], 'NX', now, id)
+    redis.call('PEXPIRE', KEYS[semaphores + i], queue_timeout * 2)
+end
+return {-1, blocking}
+"#));
+
+pub struct Semaphore {
+    // `key:<id>`, `certificate:<id>` or `upstream:<name>`
+    pub name: String,
+    pub limit: u32,
+}
+
+pub enum Acquisition {
+    Acquired,
+    // Waiting for a slot of the semaphore at this index
+    Queued(usize),
+    // No slot nor room in the queue of the semaphore at this index
+    Rejected(usize),
+}
+
+fn holders_key(name: &str) -> String {
+    format!("concurrency:{}", name)
+}
+
+fn queue_key(name: &str) -> String {
+    format!("concurrency:{}:queue", name)
+}
+
+/// Takes a slot of every semaphore at once, or joins their queues when `queue_size` allows it.
+/// A queued request must call this again to take its turn, or `release` to give up.
+pub async fn try_acquire(
+    redis_client: &RedisPool,
+    semaphores: &[Semaphore],
+    request_id: &str,
+    lease: Duration,
+    queue_size: u32,
+    queue_timeout: Duration,
+) -> redis::RedisResult<Acquisition> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    let mut invocation = ACQUIRE.prepare_invoke();
+    for semaphore in semaphores {
+        invocation.key(holders_key(&semaphore.name));
+    }
+    for semaphore in semaphores {
+        invocation.key(queue_key(&semaphore.name));
+    }
+    invocation
+        .arg(request_id)
+        .arg(lease.as_millis() as u64)
+        .arg(queue_size)
+        .arg(queue_timeout.as_millis() as u64);
+    for semaphore in semaphores {
+        invocation.arg(semaphore.limit);
+    }
+
+    let (status, blocking): (i64, i64) = invocation.invoke_async(&mut redis_conn).await?;
+    let blocking = usize::try_from(blocking - 1).unwrap_or(0);
+
+    Ok(match status {
+        1 => Acquisition::Acquired,
+        -1 => Acquisition::Queued(blocking),
+        _ => Acquisition::Rejected(blocking),
+    })
+}
+
+/// Frees the slots of the request, or removes it from the queues it is waiting in.
+pub async fn release(redis_client: &RedisPool, names: &[String], request_id: &str) -> redis::RedisResult<()> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    let mut pipe = redis::pipe();
+    for name in names {
+        pipe.zrem(holders_key(name), request_id).ignore();
+        pipe.zrem(queue_key(name), request_id).ignore();
+    }
+    pipe.query_async(&mut redis_conn).await
+}
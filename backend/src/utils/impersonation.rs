@@ -0,0 +1,39 @@
+use actix_web::{HttpMessage, HttpRequest};
+use sqlx::PgPool;
+
+// Attached to the request by `JwtValidator` when the dashboard is used through an impersonation token
+#[derive(Clone, Copy)]
+pub struct Impersonation {
+    pub admin_user_id: i32,
+    pub session_id: i64,
+}
+
+pub fn request_impersonation(req: &HttpRequest) -> Option<Impersonation> {
+    req.extensions().get::<Impersonation>().copied()
+}
+
+// A session stops being usable once it was ended explicitly, even if its token has not expired yet
+pub async fn is_session_active(
+    db_pool: &PgPool,
+    session_id: i64,
+    admin_user_id: i32,
+    user_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM impersonation_sessions
+            WHERE id = $1 AND admin_user_id = $2 AND target_user_id = $3
+                AND ended_at IS NULL AND expires_at > now()
+        ) AS "active!"
+        "#,
+        session_id,
+        admin_user_id,
+        user_id
+    )
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(record.active)
+}
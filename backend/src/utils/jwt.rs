@@ -3,14 +3,21 @@ use serde::{Serialize, Deserialize};
 use chrono::{Utc, Duration};
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+pub struct Claims {
+    pub sub: String,
+    // Tenant the user belongs to, the token is only accepted on this tenant
+    pub tid: i32,
+    pub exp: usize,
+    // Set on impersonation tokens only: the administrator acting as `sub` and their impersonation session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i64>,
 }
 
 const SECRET_KEY: &[u8] = b"secret";
 
-pub fn create_jwt(user_id: &str) -> String {
+pub fn create_jwt(user_id: &str, tenant_id: i32) -> String {
     let expiration = Utc::now()
         .checked_add_signed(Duration::seconds(3600))
         .expect("valid timestamp")
@@ -18,20 +25,35 @@ pub fn create_jwt(user_id: &str) -> String {
 
     let claims = Claims {
         sub: user_id.to_owned(),
+        tid: tenant_id,
         exp: expiration as usize,
+        act: None,
+        sid: None,
     };
 
     encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET_KEY)).unwrap()
 }
 
-pub fn validate_jwt(token: &str) -> Option<String> {
+pub fn create_impersonation_jwt(user_id: i32, tenant_id: i32, admin_user_id: i32, session_id: i64, expires_at: i64) -> String {
+    let claims = Claims {
+        sub: user_id.to_string(),
+        tid: tenant_id,
+        exp: expires_at as usize,
+        act: Some(admin_user_id),
+        sid: Some(session_id),
+    };
+
+    encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET_KEY)).unwrap()
+}
+
+pub fn validate_jwt(token: &str) -> Option<Claims> {
     let validation = Validation::default();
     match decode::<Claims>(
         token,
         &DecodingKey::from_secret(SECRET_KEY),
         &validation,
     ) {
-        Ok(token_data) => Some(token_data.claims.sub), // Return the user ID
+        Ok(token_data) => Some(token_data.claims),
         Err(_) => None, // Return None if validation fails
     }
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::load_shedding::LoadSheddingConfig;
+
+// Plan priorities go from 0 to this
+pub const MAX_PRIORITY: i16 = 10;
+
+struct AdaptiveLimit {
+    limit: f64,
+    in_flight: u32,
+    last_decrease: Option<Instant>,
+}
+
+/// Limits of in-flight requests per upstream, adapted to the latency and errors of its responses:
+/// the limit grows by one per limit's worth of fast responses and shrinks by a factor on congestion (AIMD).
+/// Every instance adapts its own limits.
+pub struct LoadShedding {
+    config: LoadSheddingConfig,
+    upstreams: Mutex<HashMap<&'static str, AdaptiveLimit>>,
+}
+
+pub struct UpstreamLoad {
+    pub upstream: &'static str,
+    pub limit: u32,
+    pub in_flight: u32,
+}
+
+impl LoadShedding {
+    pub fn new(config: LoadSheddingConfig) -> Self {
+        Self { config, upstreams: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Admits a request of the given plan priority, counting it as in flight, or tells it to be shed.
+    pub fn try_acquire(&self, upstream: &'static str, priority: i16) -> bool {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let state = upstreams.entry(upstream).or_insert_with(|| AdaptiveLimit {
+            limit: self.config.initial_limit as f64,
+            in_flight: 0,
+            last_decrease: None,
+        });
+
+        // Lower priorities only get part of the limit, so they are shed before the limit is reached
+        let priority = priority.clamp(0, MAX_PRIORITY) as f64 / MAX_PRIORITY as f64;
+        let share = 1.0 - self.config.reserved_fraction.clamp(0.0, 1.0) * (1.0 - priority);
+        let admitted = (state.limit * share).floor().max(1.0);
+
+        if state.in_flight as f64 >= admitted {
+            return false;
+        }
+        state.in_flight += 1;
+        true
+    }
+
+    /// Ends a request admitted by `try_acquire`. `outcome` is its latency and whether it failed,
+    /// or `None` when it was cancelled and says nothing about the upstream.
+    pub fn release(&self, upstream: &'static str, outcome: Option<(Duration, bool)>) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let state = match upstreams.get_mut(upstream) {
+            Some(state) => state,
+            None => return,
+        };
+
+        if let Some((latency, failed)) = outcome {
+            let target = Duration::from_millis(self.config.target_latency_ms);
+            if failed || latency > target {
+                // Requests in flight during one congestion all see it, the limit only shrinks once for them
+                if state.last_decrease.is_none_or(|at| at.elapsed() >= target) {
+                    state.limit = (state.limit * self.config.decrease_factor).max(self.config.min_limit as f64);
+                    state.last_decrease = Some(Instant::now());
+                }
+            } else if state.in_flight as f64 >= state.limit / 2.0 {
+                // Only grows while the limit is actually used
+                state.limit = (state.limit + 1.0 / state.limit).min(self.config.max_limit as f64);
+            }
+        }
+
+        state.in_flight = state.in_flight.saturating_sub(1);
+    }
+
+    pub fn snapshot(&self) -> Vec<UpstreamLoad> {
+        let upstreams = self.upstreams.lock().unwrap();
+        let mut loads = upstreams
+            .iter()
+            .map(|(upstream, state)| UpstreamLoad {
+                upstream,
+                limit: state.limit as u32,
+                in_flight: state.in_flight,
+            })
+            .collect::<Vec<_>>();
+        loads.sort_by_key(|load| load.upstream);
+        loads
+    }
+}
@@ -0,0 +1,115 @@
+use redis::AsyncCommands;
+use crate::config::redis::{RedisConnection, RedisPool};
+use crate::config::login_guard::LoginGuardConfig;
+
+pub enum LoginBlock {
+    Locked { retry_after: u64 },
+    Delayed { retry_after: u64 },
+}
+
+pub enum FailureOutcome {
+    Counted,
+    AccountLocked,
+    IpLocked,
+}
+
+// Emails are only unique within a tenant, so are accounts
+fn account_key(kind: &str, tenant_id: Option<i32>, email: &str) -> String {
+    let tenant = tenant_id.map(|tenant_id| tenant_id.to_string()).unwrap_or_default();
+    format!("login_guard:{}:account:{}:{}", kind, tenant, email.to_lowercase())
+}
+
+fn ip_key(kind: &str, ip: &str) -> String {
+    format!("login_guard:{}:ip:{}", kind, ip)
+}
+
+// Returns the remaining time to live of a key, or `None` if the key does not exist
+async fn remaining_ttl(redis_conn: &mut RedisConnection, key: &str) -> redis::RedisResult<Option<u64>> {
+    let ttl: i64 = redis_conn.ttl(key).await?;
+    Ok(if ttl > 0 { Some(ttl as u64) } else { None })
+}
+
+/// Checks whether a login attempt for this account and IP must be rejected before verifying the password.
+pub async fn check(redis_client: &RedisPool, tenant_id: Option<i32>, email: &str, ip: &str) -> redis::RedisResult<Option<LoginBlock>> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    for key in [account_key("lock", tenant_id, email), ip_key("lock", ip)] {
+        if let Some(retry_after) = remaining_ttl(&mut redis_conn, &key).await? {
+            return Ok(Some(LoginBlock::Locked { retry_after }));
+        }
+    }
+
+    if let Some(retry_after) = remaining_ttl(&mut redis_conn, &account_key("delay", tenant_id, email)).await? {
+        return Ok(Some(LoginBlock::Delayed { retry_after }));
+    }
+
+    Ok(None)
+}
+
+fn progressive_delay(config: &LoginGuardConfig, failures: u32) -> Option<u64> {
+    if failures < config.delay_after_failures {
+        return None;
+    }
+
+    let exponent = failures - config.delay_after_failures;
+    let factor = 1u64.checked_shl(exponent).unwrap_or(u64::MAX);
+    Some(config.base_delay_secs.saturating_mul(factor).min(config.max_delay_secs))
+}
+
+/// Counts a failed attempt for the account and the IP, applying delays and lockouts when thresholds are reached.
+pub async fn record_failure(
+    redis_client: &RedisPool,
+    config: &LoginGuardConfig,
+    tenant_id: Option<i32>,
+    email: &str,
+    ip: &str,
+) -> redis::RedisResult<FailureOutcome> {
+    let mut redis_conn = redis_client.connection().await?;
+    let window = config.failure_window_secs as i64;
+
+    let (account_failures, _, ip_failures, _): (u32, (), u32, ()) = redis::pipe()
+        .atomic()
+        .incr(account_key("failures", tenant_id, email), 1)
+        .expire(account_key("failures", tenant_id, email), window)
+        .incr(ip_key("failures", ip), 1)
+        .expire(ip_key("failures", ip), window)
+        .query_async(&mut redis_conn)
+        .await?;
+
+    if account_failures >= config.account_lockout_threshold {
+        let _: () = redis_conn.set_ex(account_key("lock", tenant_id, email), 1, config.lockout_duration_secs).await?;
+        let _: () = redis_conn.del(account_key("failures", tenant_id, email)).await?;
+        return Ok(FailureOutcome::AccountLocked);
+    }
+
+    if ip_failures >= config.ip_lockout_threshold {
+        let _: () = redis_conn.set_ex(ip_key("lock", ip), 1, config.lockout_duration_secs).await?;
+        let _: () = redis_conn.del(ip_key("failures", ip)).await?;
+        return Ok(FailureOutcome::IpLocked);
+    }
+
+    if let Some(delay) = progressive_delay(config, account_failures) {
+        let _: () = redis_conn.set_ex(account_key("delay", tenant_id, email), 1, delay).await?;
+    }
+
+    Ok(FailureOutcome::Counted)
+}
+
+/// Removes the failure counters, delays and lockout of an account.
+pub async fn clear_account(redis_client: &RedisPool, tenant_id: Option<i32>, email: &str) -> redis::RedisResult<()> {
+    let mut redis_conn = redis_client.connection().await?;
+    redis_conn.del(&[
+        account_key("failures", tenant_id, email),
+        account_key("delay", tenant_id, email),
+        account_key("lock", tenant_id, email),
+    ]).await
+}
+
+/// Removes the failure counters, delays and lockout of an IP address.
+pub async fn clear_ip(redis_client: &RedisPool, ip: &str) -> redis::RedisResult<()> {
+    let mut redis_conn = redis_client.connection().await?;
+    redis_conn.del(&[
+        ip_key("failures", ip),
+        ip_key("lock", ip),
+    ]).await
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use futures::future::BoxFuture;
+use lettre::{
+    message::Mailbox,
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tokio::io::AsyncWriteExt;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Every transport used by the application implements this trait so handlers
+// only depend on `web::Data<dyn Mailer>` and never on a concrete transport.
+pub trait Mailer: Send + Sync {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<(), String>>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, port: u16, username: String, password: String, from: &str) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(|e| e.to_string())?
+            .port(port)
+            .credentials(Credentials::new(username, password))
+            .build();
+        let from = from.parse::<Mailbox>().map_err(|e| e.to_string())?;
+
+        Ok(Self { transport, from })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let to = email.to.parse::<Mailbox>().map_err(|e| e.to_string())?;
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(email.subject.clone())
+                .body(email.body.clone())
+                .map_err(|e| e.to_string())?;
+
+            self.transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Appends every email to a local file, useful for development and offline testing.
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let entry = format!(
+                "To: {}\nSubject: {}\n\n{}\n----------------------------------------\n",
+                email.to, email.subject, email.body
+            );
+
+            file.write_all(entry.as_bytes()).await.map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// Prints every email to stdout instead of delivering it.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            println!("Email to {} - {}\n{}", email.to, email.subject, email.body);
+            Ok(())
+        })
+    }
+}
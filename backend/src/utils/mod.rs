@@ -1 +1,25 @@
-pub(crate) mod jwt;
\ No newline at end of file
+pub(crate) mod jwt;
+pub(crate) mod mailer;
+pub(crate) mod tokens;
+pub(crate) mod audit;
+pub(crate) mod login_guard;
+pub(crate) mod password_policy;
+pub(crate) mod api_key;
+pub(crate) mod wildcard;
+pub(crate) mod scopes;
+pub(crate) mod client_ip;
+pub(crate) mod request_signing;
+pub(crate) mod client_certificate;
+pub(crate) mod permissions;
+pub(crate) mod tenants;
+pub(crate) mod impersonation;
+pub(crate) mod plans;
+pub(crate) mod rate_limit_key;
+pub(crate) mod rate_limit;
+pub(crate) mod quota;
+pub(crate) mod concurrency;
+pub(crate) mod load_shedding;
+pub(crate) mod billing;
+pub(crate) mod route_costs;
+pub(crate) mod request_cost;
+pub(crate) mod cached;
\ No newline at end of file
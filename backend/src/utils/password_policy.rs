@@ -0,0 +1,94 @@
+use std::path::Path;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use crate::config::password_policy::PasswordPolicy;
+
+#[derive(Serialize)]
+pub struct PasswordViolation {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl PasswordViolation {
+    fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// Returns every rule of the policy the password breaks, or an empty list if it is acceptable.
+pub async fn validate_password(policy: &PasswordPolicy, password: &str, name: &str, email: &str) -> Vec<PasswordViolation> {
+    let mut violations = Vec::new();
+    let length = password.chars().count();
+
+    if length < policy.min_length {
+        violations.push(PasswordViolation::new("too_short", format!("Password must be at least {} characters long.", policy.min_length)));
+    }
+    if length > policy.max_length {
+        violations.push(PasswordViolation::new("too_long", format!("Password must be at most {} characters long.", policy.max_length)));
+    }
+    if policy.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+        violations.push(PasswordViolation::new("missing_lowercase", "Password must contain a lowercase letter."));
+    }
+    if policy.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+        violations.push(PasswordViolation::new("missing_uppercase", "Password must contain an uppercase letter."));
+    }
+    if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+        violations.push(PasswordViolation::new("missing_digit", "Password must contain a digit."));
+    }
+    if policy.require_symbol && !password.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+        violations.push(PasswordViolation::new("missing_symbol", "Password must contain a symbol."));
+    }
+    if policy.disallow_personal_info && contains_personal_info(password, name, email) {
+        violations.push(PasswordViolation::new("contains_personal_info", "Password must not contain your name or email address."));
+    }
+
+    if let Some(dir) = &policy.breached_passwords_dir {
+        match is_breached(dir, password).await {
+            Ok(true) => violations.push(PasswordViolation::new("breached", "This password has appeared in a data breach and cannot be used.")),
+            Ok(false) => {}
+            Err(e) => eprintln!("Error reading breached password list: {}", e),
+        }
+    }
+
+    violations
+}
+
+fn contains_personal_info(password: &str, name: &str, email: &str) -> bool {
+    let password = password.to_lowercase();
+    let email = email.to_lowercase();
+    let local_part = email.split('@').next().unwrap_or_default().to_string();
+
+    // Very short fragments would reject too many legitimate passwords
+    name.to_lowercase()
+        .split_whitespace()
+        .map(|part| part.to_string())
+        .chain([email.clone(), local_part])
+        .filter(|fragment| fragment.chars().count() >= 3)
+        .any(|fragment| password.contains(&fragment))
+}
+
+// The list is split by the first 5 hex characters of the SHA-1 hash, like the k-anonymity range API
+// of Have I Been Pwned: `<dir>/<PREFIX>.txt` contains one `SUFFIX:COUNT` line per breached password.
+async fn is_breached(dir: &Path, password: &str) -> std::io::Result<bool> {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    let content = match tokio::fs::read_to_string(dir.join(format!("{}.txt", prefix))).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .any(|candidate| candidate.trim().eq_ignore_ascii_case(suffix)))
+}
+
+pub fn violations_response(violations: Vec<PasswordViolation>) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "password_policy_violation",
+        "violations": violations,
+    }))
+}
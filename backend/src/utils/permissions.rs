@@ -0,0 +1,114 @@
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+
+// Must match the rows of the `permissions` table, roles are mapped to permissions in `role_permissions`
+#[derive(Clone, Copy)]
+pub enum Permission {
+    AdminAccess,
+    UsersRead,
+    UsersCreate,
+    UsersDelete,
+    UsersManageRoles,
+    UsersImpersonate,
+    ApiKeysManageOwn,
+    ApiKeysCreate,
+    ApiKeysRevoke,
+    LoginsUnlock,
+    CertificatesManage,
+    OrganizationsManage,
+    TenantsManage,
+    AuditLogRead,
+    PlansManage,
+    BillingManage,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::AdminAccess => "admin:access",
+            Permission::UsersRead => "users:read",
+            Permission::UsersCreate => "users:create",
+            Permission::UsersDelete => "users:delete",
+            Permission::UsersManageRoles => "users:manage_roles",
+            Permission::UsersImpersonate => "users:impersonate",
+            Permission::ApiKeysManageOwn => "api_keys:manage_own",
+            Permission::ApiKeysCreate => "api_keys:create",
+            Permission::ApiKeysRevoke => "api_keys:revoke",
+            Permission::LoginsUnlock => "logins:unlock",
+            Permission::CertificatesManage => "certificates:manage",
+            Permission::OrganizationsManage => "organizations:manage",
+            Permission::TenantsManage => "tenants:manage",
+            Permission::AuditLogRead => "audit_log:read",
+            Permission::PlansManage => "plans:manage",
+            Permission::BillingManage => "billing:manage",
+        }
+    }
+}
+
+pub async fn has_permission(db_pool: &PgPool, user_id: i32, permission: Permission) -> Result<bool, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1
+            FROM users
+            JOIN role_permissions ON role_permissions.role = users.role
+            WHERE users.id = $1 AND role_permissions.permission = $2
+        ) AS "granted!"
+        "#,
+        user_id,
+        permission.as_str()
+    )
+        .fetch_one(db_pool)
+        .await?;
+
+    Ok(record.granted)
+}
+
+pub async fn user_permissions(db_pool: &PgPool, user_id: i32) -> Result<Vec<String>, sqlx::Error> {
+    let records = sqlx::query!(
+        r#"
+        SELECT role_permissions.permission
+        FROM users
+        JOIN role_permissions ON role_permissions.role = users.role
+        WHERE users.id = $1
+        ORDER BY role_permissions.permission
+        "#,
+        user_id
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(records.into_iter().map(|record| record.permission).collect())
+}
+
+/// Rank of the acting user, failing with a 403 unless it is above the rank of the target user.
+/// Managing a peer or someone ranked above you would hand out their access.
+pub async fn ensure_outranks(db_pool: &PgPool, req: &HttpRequest, target_user_id: i32) -> Result<i16, HttpResponse> {
+    let actor_user_id = req
+        .extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+        .ok_or_else(|| HttpResponse::Unauthorized().finish())?;
+
+    let ranks = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT rank FROM roles WHERE name = target.role) AS "target_rank!",
+            (SELECT roles.rank FROM users JOIN roles ON roles.name = users.role WHERE users.id = $2) AS actor_rank
+        FROM users AS target
+        WHERE target.id = $1
+        "#,
+        target_user_id,
+        actor_user_id
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?
+        .ok_or_else(|| HttpResponse::NotFound().finish())?;
+
+    match ranks.actor_rank {
+        Some(actor_rank) if ranks.target_rank < actor_rank => Ok(actor_rank),
+        Some(_) => Err(HttpResponse::Forbidden().body("You cannot manage a user ranked at or above you.")),
+        None => Err(HttpResponse::Unauthorized().finish()),
+    }
+}
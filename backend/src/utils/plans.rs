@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::http::header::HeaderValue;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::utils::cached::Cached;
+use crate::utils::quota::QuotaAction;
+use crate::utils::rate_limit::Algorithm;
+use crate::utils::wildcard;
+
+#[derive(Clone, Serialize)]
+pub struct Plan {
+    pub id: i32,
+    pub name: String,
+    pub rate_limit_max_requests: i32,
+    pub rate_limit_window_secs: i32,
+    pub rate_limit_algorithm: Option<String>,
+    pub daily_request_quota: Option<i64>,
+    pub monthly_request_quota: Option<i64>,
+    pub quota_action: String,
+    // Rate limit applied instead of the regular one once a quota is exhausted, with the `degrade` action
+    pub degraded_max_requests: Option<i32>,
+    pub degraded_window_secs: Option<i32>,
+    // From 0 to 10, requests of lower priority plans are shed first under load
+    pub priority: i16,
+    // `<METHOD> <path glob>` patterns, every route is allowed when empty
+    pub allowed_routes: Vec<String>,
+    pub cache_max_age_secs: Option<i32>,
+    pub is_default: bool,
+    // Monthly prices, see `utils::billing`
+    pub currency: String,
+    pub base_price_cents: i64,
+    pub included_units: Option<i64>,
+    pub overage_price_per_1000_units_cents: i64,
+    pub price_per_gb_cents: i64,
+}
+
+impl Plan {
+    /// Rate limit of the plan, as `(max_requests, window)`.
+    pub fn rate_limit(&self) -> (u32, Duration) {
+        (
+            self.rate_limit_max_requests.max(0) as u32,
+            Duration::from_secs(self.rate_limit_window_secs.max(1) as u64),
+        )
+    }
+
+    pub fn algorithm(&self) -> Option<Algorithm> {
+        self.rate_limit_algorithm.as_deref().and_then(|algorithm| algorithm.parse().ok())
+    }
+
+    pub fn quota_action(&self) -> QuotaAction {
+        self.quota_action.parse().unwrap_or(QuotaAction::Block)
+    }
+
+    pub fn degraded_rate_limit(&self) -> Option<(u32, Duration)> {
+        Some((
+            self.degraded_max_requests?.max(0) as u32,
+            Duration::from_secs(self.degraded_window_secs?.max(1) as u64),
+        ))
+    }
+
+    pub fn allows_route(&self, method: &str, path: &str) -> bool {
+        let route = format!("{} {}", method, path);
+        self.allowed_routes.is_empty() || self.allowed_routes.iter().any(|pattern| wildcard::matches(pattern, &route))
+    }
+
+    /// `Cache-Control` of successful GET responses that do not set their own, `None` to leave them untouched.
+    pub fn cache_control(&self) -> Option<HeaderValue> {
+        match self.cache_max_age_secs? {
+            0 => Some(HeaderValue::from_static("no-store")),
+            max_age => HeaderValue::from_str(&format!("private, max-age={}", max_age)).ok(),
+        }
+    }
+}
+
+/// In-memory copy of the plans table, so rate limiting a request does not hit the database.
+pub struct PlanRegistry {
+    db_pool: PgPool,
+    cache: Cached<HashMap<i32, Plan>>,
+}
+
+impl PlanRegistry {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool, cache: Cached::new() }
+    }
+
+    pub fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    async fn plans(&self) -> Result<Arc<HashMap<i32, Plan>>, sqlx::Error> {
+        self.cache.get_or_load(|| self.load()).await
+    }
+
+    async fn load(&self) -> Result<HashMap<i32, Plan>, sqlx::Error> {
+        let records = sqlx::query_as!(
+            Plan,
+            r#"
+            SELECT id, name, rate_limit_max_requests, rate_limit_window_secs, rate_limit_algorithm,
+                daily_request_quota, monthly_request_quota, quota_action, degraded_max_requests, degraded_window_secs,
+                priority, allowed_routes, cache_max_age_secs, is_default, currency, base_price_cents, included_units,
+                overage_price_per_1000_units_cents, price_per_gb_cents
+            FROM plans
+            "#
+        )
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        Ok(records.into_iter().map(|plan| (plan.id, plan)).collect())
+    }
+
+    /// The plan of the caller, or the default plan when it has none.
+    pub async fn resolve(&self, id: Option<i32>) -> Result<Option<Plan>, sqlx::Error> {
+        let plans = self.plans().await?;
+        Ok(id
+            .and_then(|id| plans.get(&id))
+            .or_else(|| plans.values().find(|plan| plan.is_default))
+            .cloned())
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use actix_web::HttpResponse;
+use sqlx::PgPool;
+use time::{Date, Month, OffsetDateTime};
+use crate::config::redis::RedisPool;
+use crate::models::api_key::ApiIdentity;
+use crate::utils::plans::Plan;
+
+// Keys of the counters changed since the last checkpoint
+const DIRTY_COUNTERS_KEY: &str = "quota:dirty";
+
+// Counters outlive their period by a day so the last checkpoint still sees their final value
+const COUNTER_GRACE_SECS: i64 = 24 * 60 * 60;
+
+// KEYS one hash of `used` and `overage` per period, then the set of dirty counters.
+// ARGV cost, action, then for each period: quota, ttl_secs, seeded used, seeded overage.
+// Returns `{status, exceeded}`, status being 1 when accepted, 0 when rejected, -1 when a counter must be seeded,
+// and exceeded the 1-based index of the first period whose quota is exhausted, or 0.
+static CONSUME: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local cost = tonumber(ARGV[1])
+local action = ARGV[2]
+local periods = #KEYS - 1
+local used = {}
+local exceeded = 0
+
+for i = 1, periods do
+    local base = 2 + (i - 1) * 4
+    if redis.call('EXISTS', KEYS[i]) == 0 then
+        -- Lost with a restart of Redis, or a new period: the caller retries with the last checkpoint
+        if ARGV[base + 3] == '' then
+            return {-1, 0}
+        end
+        redis.call('HSET', KEYS[i], 'used', ARGV[base + 3], 'overage', ARGV[base + 4])
+        redis.call('EXPIRE', KEYS[i], ARGV[base + 2])
+    end
+    used[i] = tonumber(redis.call('HGET', KEYS[i], 'used') or '0')
+    local quota = tonumber(ARGV[base + 1])
+    if exceeded == 0 and used[i] + cost > quota then
+        exceeded = i
+    end
+end
+
+if exceeded > 0 and action == 'block' then
+    return {0, exceeded}
+end
+
+for i = 1, periods do
+    local quota = tonumber(ARGV[2 + (i - 1) * 4 + 1])
+    redis.call('HINCRBY', KEYS[i], 'used', cost)
+    if action == 'overage' and used[i] + cost > quota then
+        redis.call('HINCRBY', KEYS[i], 'overage', math.min(cost, used[i] + cost - quota))
+    end
+    redis.call('SADD', KEYS[periods + 1], KEYS[i])
+end
+return {1, exceeded}
+"#));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuotaPeriod {
+    Day,
+    Month,
+}
+
+impl QuotaPeriod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Day => "daily",
+            QuotaPeriod::Month => "monthly",
+        }
+    }
+
+    // Periods are calendar days and months, UTC
+    pub fn current(&self) -> String {
+        let today = OffsetDateTime::now_utc().date();
+        match self {
+            QuotaPeriod::Day => today.to_string(),
+            QuotaPeriod::Month => format!("{}-{:02}", today.year(), u8::from(today.month())),
+        }
+    }
+
+    pub fn resets_at(&self) -> OffsetDateTime {
+        let today = OffsetDateTime::now_utc().date();
+        let next = match self {
+            QuotaPeriod::Day => today.next_day(),
+            QuotaPeriod::Month => {
+                let year = if today.month() == Month::December { today.year() + 1 } else { today.year() };
+                Date::from_calendar_date(year, today.month().next(), 1).ok()
+            }
+        };
+        next.unwrap_or(Date::MAX).midnight().assume_utc()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuotaAction {
+    // Requests beyond the quota are rejected until the period ends
+    Block,
+    // Requests beyond the quota are accepted and flagged for billing
+    Overage,
+    // Requests beyond the quota are accepted under the degraded rate limit of the plan
+    Degrade,
+}
+
+impl QuotaAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaAction::Block => "block",
+            QuotaAction::Overage => "overage",
+            QuotaAction::Degrade => "degrade",
+        }
+    }
+}
+
+impl FromStr for QuotaAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "block" => Ok(QuotaAction::Block),
+            "overage" => Ok(QuotaAction::Overage),
+            "degrade" => Ok(QuotaAction::Degrade),
+            value => Err(format!("Unknown quota action '{}'", value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct QuotaLimit {
+    pub period: QuotaPeriod,
+    pub quota: i64,
+}
+
+/// Quotas of a plan, the quota set on an organization replacing the monthly quota of its plan.
+pub fn limits(plan: Option<&Plan>, monthly_override: Option<i64>) -> Vec<QuotaLimit> {
+    let daily = plan.and_then(|plan| plan.daily_request_quota);
+    let monthly = monthly_override.or(plan.and_then(|plan| plan.monthly_request_quota));
+
+    [(QuotaPeriod::Day, daily), (QuotaPeriod::Month, monthly)]
+        .into_iter()
+        .filter_map(|(period, quota)| quota.map(|quota| QuotaLimit { period, quota }))
+        .collect()
+}
+
+// Attached to the request extensions by `ApiKeyValidator` when the plan of the caller has quotas,
+// the request is charged by `QuotaGuard` once it passed the other limits
+#[derive(Clone)]
+pub struct QuotaCharge {
+    pub subject: String,
+    pub limits: Vec<QuotaLimit>,
+    pub action: QuotaAction,
+}
+
+// Attached to the request extensions by `QuotaGuard` when a quota is exhausted but the request accepted
+#[derive(Clone, Copy)]
+pub struct QuotaExceeded {
+    pub action: QuotaAction,
+}
+
+pub fn organization_subject(organization_id: i32) -> String {
+    format!("org:{}", organization_id)
+}
+
+pub fn key_subject(key_id: i32) -> String {
+    format!("key:{}", key_id)
+}
+
+pub fn user_subject(user_id: i32) -> String {
+    format!("user:{}", user_id)
+}
+
+/// Who a request is counted for: the organization owning the key, else the key itself,
+/// else the user of the client certificate.
+pub fn subject(identity: &ApiIdentity) -> String {
+    match (identity.organization_id, identity.key_id) {
+        (Some(organization_id), _) => organization_subject(organization_id),
+        (None, Some(key_id)) => key_subject(key_id),
+        (None, None) => user_subject(identity.user_id),
+    }
+}
+
+fn counter_key(subject: &str, period: &str) -> String {
+    format!("quota:{}:{}", subject, period)
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct QuotaUsage {
+    pub used: i64,
+    pub overage: i64,
+}
+
+pub struct QuotaDecision {
+    pub allowed: bool,
+    // First quota exhausted by the request, if any
+    pub exceeded: Option<QuotaLimit>,
+}
+
+/// Counts a request of the given cost against every quota of the subject, unless the action is to block
+/// and one of them is exhausted. Returns `None` when a counter is missing from Redis: the call must then
+/// be repeated with the last checkpoint of the subject as `seed`.
+pub async fn consume(
+    redis_client: &RedisPool,
+    subject: &str,
+    limits: &[QuotaLimit],
+    action: QuotaAction,
+    cost: i64,
+    seed: Option<&HashMap<String, QuotaUsage>>,
+) -> redis::RedisResult<Option<QuotaDecision>> {
+    let mut redis_conn = redis_client.connection().await?;
+    let now = OffsetDateTime::now_utc();
+
+    let mut invocation = CONSUME.prepare_invoke();
+    invocation.arg(cost).arg(action.as_str());
+    for limit in limits {
+        let period = limit.period.current();
+        let ttl_secs = (limit.period.resets_at() - now).whole_seconds() + COUNTER_GRACE_SECS;
+        let seeded = seed.map(|seed| seed.get(&period).copied().unwrap_or_default());
+
+        invocation
+            .key(counter_key(subject, &period))
+            .arg(limit.quota)
+            .arg(ttl_secs)
+            .arg(seeded.map(|usage| usage.used.to_string()).unwrap_or_default())
+            .arg(seeded.map(|usage| usage.overage.to_string()).unwrap_or_default());
+    }
+    invocation.key(DIRTY_COUNTERS_KEY);
+
+    let (status, exceeded): (i64, i64) = invocation.invoke_async(&mut redis_conn).await?;
+    if status < 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(QuotaDecision {
+        allowed: status == 1,
+        exceeded: usize::try_from(exceeded - 1).ok().and_then(|index| limits.get(index).copied()),
+    }))
+}
+
+/// Usage of the subject saved by the last checkpoints, per period.
+pub async fn load_checkpoint(
+    db_pool: &PgPool,
+    subject: &str,
+    periods: &[String],
+) -> Result<HashMap<String, QuotaUsage>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT period, used, overage FROM quota_usage WHERE subject = $1 AND period = ANY($2)",
+        subject,
+        periods
+    )
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.period, QuotaUsage { used: row.used, overage: row.overage }))
+        .collect())
+}
+
+/// Usage of the subject in the current period of each of `periods`, read from Redis,
+/// or from the last checkpoint when Redis no longer has it.
+pub async fn current_usage(
+    redis_client: &RedisPool,
+    db_pool: &PgPool,
+    subject: &str,
+    periods: &[QuotaPeriod],
+) -> Result<Vec<QuotaUsage>, HttpResponse> {
+    let periods = periods.iter().map(|period| period.current()).collect::<Vec<_>>();
+
+    let mut pipe = redis::pipe();
+    for period in &periods {
+        pipe.cmd("HMGET").arg(counter_key(subject, period)).arg("used").arg("overage");
+    }
+    let counters: Vec<(Option<i64>, Option<i64>)> = match redis_client.connection().await {
+        Ok(mut redis_conn) => pipe
+            .query_async(&mut redis_conn)
+            .await
+            .map_err(|_| HttpResponse::InternalServerError().body("Failed to connect to Redis"))?,
+        Err(_) => return Err(HttpResponse::InternalServerError().body("Failed to connect to Redis")),
+    };
+
+    let checkpoint = if counters.iter().any(|(used, _)| used.is_none()) {
+        load_checkpoint(db_pool, subject, &periods).await.map_err(|e| {
+            eprintln!("Error loading quota checkpoint: {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        })?
+    } else {
+        HashMap::new()
+    };
+
+    Ok(periods
+        .iter()
+        .zip(counters)
+        .map(|(period, counter)| match counter {
+            (Some(used), overage) => QuotaUsage { used, overage: overage.unwrap_or(0) },
+            (None, _) => checkpoint.get(period).copied().unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// First quota a request of the given cost would exhaust, without counting it.
+pub async fn peek_exceeded(
+    redis_client: &RedisPool,
+    db_pool: &PgPool,
+    subject: &str,
+    limits: &[QuotaLimit],
+    cost: i64,
+) -> Result<Option<QuotaLimit>, HttpResponse> {
+    let periods = limits.iter().map(|limit| limit.period).collect::<Vec<_>>();
+    let usage = current_usage(redis_client, db_pool, subject, &periods).await?;
+
+    Ok(limits
+        .iter()
+        .zip(usage)
+        .find(|(limit, usage)| usage.used + cost > limit.quota)
+        .map(|(limit, _)| *limit))
+}
+
+pub struct Checkpoint {
+    pub subject: String,
+    pub period: String,
+    pub usage: QuotaUsage,
+}
+
+/// Takes up to `count` counters changed since they were last checkpointed.
+pub async fn take_dirty(redis_client: &RedisPool, count: usize) -> redis::RedisResult<Vec<Checkpoint>> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    let keys: Vec<String> = redis::cmd("SPOP")
+        .arg(DIRTY_COUNTERS_KEY)
+        .arg(count)
+        .query_async(&mut redis_conn)
+        .await?;
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut pipe = redis::pipe();
+    for key in &keys {
+        pipe.cmd("HMGET").arg(key).arg("used").arg("overage");
+    }
+    let counters: Vec<(Option<i64>, Option<i64>)> = pipe.query_async(&mut redis_conn).await?;
+
+    // Counters that expired in the meantime were checkpointed before
+    Ok(keys
+        .iter()
+        .zip(counters)
+        .filter_map(|(key, (used, overage))| {
+            let (subject, period) = key.strip_prefix("quota:")?.rsplit_once(':')?;
+            Some(Checkpoint {
+                subject: subject.to_string(),
+                period: period.to_string(),
+                usage: QuotaUsage { used: used?, overage: overage.unwrap_or(0) },
+            })
+        })
+        .collect())
+}
+
+/// Puts counters back in the set of dirty counters, after their checkpoint failed.
+pub async fn mark_dirty(redis_client: &RedisPool, checkpoints: &[Checkpoint]) -> redis::RedisResult<()> {
+    let mut redis_conn = redis_client.connection().await?;
+    let keys = checkpoints
+        .iter()
+        .map(|checkpoint| counter_key(&checkpoint.subject, &checkpoint.period))
+        .collect::<Vec<_>>();
+
+    redis::cmd("SADD").arg(DIRTY_COUNTERS_KEY).arg(keys).query_async(&mut redis_conn).await
+}
+
+pub async fn save_checkpoints(db_pool: &PgPool, checkpoints: &[Checkpoint]) -> Result<(), sqlx::Error> {
+    let subjects = checkpoints.iter().map(|checkpoint| checkpoint.subject.clone()).collect::<Vec<_>>();
+    let periods = checkpoints.iter().map(|checkpoint| checkpoint.period.clone()).collect::<Vec<_>>();
+    let used = checkpoints.iter().map(|checkpoint| checkpoint.usage.used).collect::<Vec<_>>();
+    let overage = checkpoints.iter().map(|checkpoint| checkpoint.usage.overage).collect::<Vec<_>>();
+
+    // Counters only grow within a period, an older checkpoint never overwrites a newer one
+    sqlx::query!(
+        r#"
+        INSERT INTO quota_usage (subject, period, used, overage)
+        SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::BIGINT[], $4::BIGINT[])
+        ON CONFLICT (subject, period) DO UPDATE
+        SET used = GREATEST(quota_usage.used, EXCLUDED.used),
+            overage = GREATEST(quota_usage.overage, EXCLUDED.overage),
+            updated_at = now()
+        "#,
+        &subjects[..],
+        &periods[..],
+        &used[..],
+        &overage[..]
+    )
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::HttpResponse;
+use crate::config::redis::RedisPool;
+
+// Every script reads the clock of Redis rather than the one of the gateway instance, so instances
+// with drifting clocks still agree, and runs atomically so concurrent requests cannot over-admit.
+// They all return `{allowed, remaining, reset_after_ms, retry_after_ms}`.
+
+// KEYS[1] counter, ARGV limit, window_ms, cost
+static FIXED_WINDOW: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then
+    ttl = window
+end
+
+if count + cost > limit then
+    return {0, math.max(limit - count, 0), ttl, ttl}
+end
+
+redis.call('INCRBY', KEYS[1], cost)
+-- The window starts with its first request and is not extended by the following ones
+if count == 0 then
+    redis.call('PEXPIRE', KEYS[1], window)
+end
+return {1, limit - count - cost, ttl, 0}
+"#));
+
+// KEYS[1] sorted set of request timestamps in microseconds, ARGV limit, window_ms, cost, unique request id
+static SLIDING_WINDOW_LOG: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2]) * 1000
+local cost = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+
+if count + cost > limit then
+    local retry_after = window
+    if cost <= limit then
+        -- Enough of the oldest requests must leave the window to make room for this one
+        local index = count + cost - limit - 1
+        local oldest = redis.call('ZRANGE', KEYS[1], index, index, 'WITHSCORES')
+        retry_after = tonumber(oldest[2]) + window - now
+    end
+    return {0, math.max(limit - count, 0), math.ceil(window / 1000), math.ceil(retry_after / 1000)}
+end
+
+for i = 1, cost do
+    redis.call('ZADD', KEYS[1], now, ARGV[4] .. ':' .. i)
+end
+redis.call('PEXPIRE', KEYS[1], math.ceil(window / 1000))
+return {1, limit - count - cost, math.ceil(window / 1000), 0}
+"#));
+
+// KEYS[1] hash of counters per window start, ARGV limit, window_ms, cost
+static SLIDING_WINDOW_COUNTER: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local current_start = now - (now % window)
+local previous_start = current_start - window
+local elapsed = now - current_start
+
+-- Counters older than the previous window no longer weigh on anything
+for _, start in ipairs(redis.call('HKEYS', KEYS[1])) do
+    if tonumber(start) < previous_start then
+        redis.call('HDEL', KEYS[1], start)
+    end
+end
+
+local previous = tonumber(redis.call('HGET', KEYS[1], tostring(previous_start)) or '0')
+local current = tonumber(redis.call('HGET', KEYS[1], tostring(current_start)) or '0')
+-- The previous window counts for the part of it still covered by a window ending now
+local estimated = previous * (window - elapsed) / window + current
+
+if estimated + cost > limit then
+    local retry_after = window - elapsed
+    local room = limit - current - cost
+    if room >= 0 and previous > 0 then
+        retry_after = math.ceil(window * (1 - room / previous)) - elapsed
+    end
+    return {0, math.max(math.floor(limit - estimated), 0), window - elapsed, math.max(retry_after, 1)}
+end
+
+redis.call('HINCRBY', KEYS[1], tostring(current_start), cost)
+redis.call('PEXPIRE', KEYS[1], window * 2)
+return {1, math.max(math.floor(limit - estimated - cost), 0), window - elapsed, 0}
+"#));
+
+// KEYS[1] theoretical arrival time in milliseconds, ARGV limit, window_ms, cost
+static TOKEN_BUCKET: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(r#"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local cost = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+if limit == 0 then
+    return {0, 0, window, window}
+end
+
+-- A token is added every `interval`, up to `limit` tokens
+local interval = window / limit
+local tat = tonumber(redis.call('GET', KEYS[1]) or '0')
+if tat < now then
+    tat = now
+end
+
+local new_tat = tat + interval * cost
+local allow_at = new_tat - window
+
+if now < allow_at then
+    return {0, math.max(math.floor((window - (tat - now)) / interval), 0), math.ceil(tat - now), math.ceil(allow_at - now)}
+end
+
+-- A free request leaves `new_tat` at `now`, and Redis rejects an expiration of 0
+redis.call('SET', KEYS[1], tostring(new_tat), 'PX', math.max(1, math.ceil(new_tat - now)))
+return {1, math.floor((window - (new_tat - now)) / interval), math.ceil(new_tat - now), 0}
+"#));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    // Counts requests in consecutive windows, allows bursts of twice the limit around a window boundary
+    FixedWindow,
+    // Remembers every request of the window, exact but uses memory in proportion to the limit
+    SlidingWindowLog,
+    // Weighs the previous window by its overlap with the sliding window, approximate but cheap
+    SlidingWindowCounter,
+    // GCRA, refills evenly over the window and allows bursts up to the limit
+    TokenBucket,
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::FixedWindow => "fixed_window",
+            Algorithm::SlidingWindowLog => "sliding_window_log",
+            Algorithm::SlidingWindowCounter => "sliding_window_counter",
+            Algorithm::TokenBucket => "token_bucket",
+        }
+    }
+
+    fn script(&self) -> &'static redis::Script {
+        match self {
+            Algorithm::FixedWindow => &FIXED_WINDOW,
+            Algorithm::SlidingWindowLog => &SLIDING_WINDOW_LOG,
+            Algorithm::SlidingWindowCounter => &SLIDING_WINDOW_COUNTER,
+            Algorithm::TokenBucket => &TOKEN_BUCKET,
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "fixed_window" => Ok(Algorithm::FixedWindow),
+            "sliding_window_log" => Ok(Algorithm::SlidingWindowLog),
+            "sliding_window_counter" => Ok(Algorithm::SlidingWindowCounter),
+            "token_bucket" | "gcra" => Ok(Algorithm::TokenBucket),
+            value => Err(format!("Unknown rate limit algorithm '{}'", value)),
+        }
+    }
+}
+
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // Until the bucket is back to its full capacity, or the current window ends
+    pub reset_after: Duration,
+    // Set when the request was rejected
+    pub retry_after: Option<Duration>,
+}
+
+// Rounded up so clients never retry too early
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(1000) as u64
+}
+
+impl RateLimitDecision {
+    /// Adds the `RateLimit-*` headers of the IETF draft, along with the legacy `X-RateLimit-*` ones
+    /// where the reset is a Unix timestamp, and `Retry-After` when the request was rejected.
+    pub fn write_headers(&self, headers: &mut HeaderMap, window: Duration) {
+        let reset_secs = whole_seconds(self.reset_after);
+        let reset_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs() + reset_secs)
+            .unwrap_or_default();
+
+        let values = [
+            ("ratelimit-limit", self.limit.to_string()),
+            ("ratelimit-remaining", self.remaining.to_string()),
+            ("ratelimit-reset", reset_secs.to_string()),
+            ("ratelimit-policy", format!("{};w={}", self.limit, window.as_secs())),
+            ("x-ratelimit-limit", self.limit.to_string()),
+            ("x-ratelimit-remaining", self.remaining.to_string()),
+            ("x-ratelimit-reset", reset_at.to_string()),
+        ];
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        }
+
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(RETRY_AFTER, HeaderValue::from(whole_seconds(retry_after).max(1)));
+        }
+    }
+
+    /// The 429 response sent for a rejected request.
+    pub fn rejection(&self, window: Duration) -> HttpResponse {
+        let retry_after = self.retry_after.map(|retry_after| whole_seconds(retry_after).max(1));
+        let mut response = HttpResponse::TooManyRequests().json(serde_json::json!({
+            "error": "rate_limited",
+            "message": format!("Rate limit exceeded - {} requests in {} seconds", self.limit, window.as_secs()),
+            "limit": self.limit,
+            "remaining": self.remaining,
+            "retry_after": retry_after,
+        }));
+        self.write_headers(response.headers_mut(), window);
+        response
+    }
+}
+
+/// Counts a request of the given cost against the bucket and tells whether it is allowed.
+/// A rejected request is not counted.
+pub async fn check(
+    redis_client: &RedisPool,
+    algorithm: Algorithm,
+    bucket: &str,
+    limit: u32,
+    window: Duration,
+    cost: u32,
+) -> redis::RedisResult<RateLimitDecision> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    // Each algorithm stores a different Redis type, a bucket changing algorithm starts afresh
+    let key = format!("rate_limiter:{}:{}", algorithm.as_str(), bucket);
+    let window_ms = window.as_millis().max(1) as u64;
+
+    let mut invocation = algorithm.script().key(&key);
+    invocation.arg(limit).arg(window_ms).arg(cost);
+    if algorithm == Algorithm::SlidingWindowLog {
+        // Tells apart requests logged in the same microsecond
+        invocation.arg(format!("{:016x}", rand::random::<u64>()));
+    }
+
+    let (allowed, remaining, reset_after_ms, retry_after_ms): (i64, i64, i64, i64) =
+        invocation.invoke_async(&mut redis_conn).await?;
+
+    Ok(RateLimitDecision {
+        allowed: allowed == 1,
+        limit,
+        remaining: remaining.max(0) as u32,
+        reset_after: Duration::from_millis(reset_after_ms.max(0) as u64),
+        retry_after: (allowed != 1).then(|| Duration::from_millis(retry_after_ms.max(0) as u64)),
+    })
+}
+
+// Past this many buckets, idle ones are forgotten before a new one is added
+const MAX_LOCAL_BUCKETS: usize = 100_000;
+
+struct LocalWindow {
+    window: Duration,
+    start: Instant,
+    current: u32,
+    previous: u32,
+}
+
+/// Sliding window counter kept in memory, used instead of Redis while it is unreachable.
+/// Each instance counts on its own, so a client spread over several instances gets more than the limit.
+#[derive(Default)]
+pub struct LocalRateLimiter {
+    windows: Mutex<HashMap<String, LocalWindow>>,
+}
+
+impl LocalRateLimiter {
+    pub fn check(&self, bucket: &str, limit: u32, window: Duration, cost: u32) -> RateLimitDecision {
+        let window = window.max(Duration::from_millis(1));
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+
+        if windows.len() >= MAX_LOCAL_BUCKETS && !windows.contains_key(bucket) {
+            windows.retain(|_, entry| now.duration_since(entry.start) < entry.window * 2);
+        }
+        let entry = windows.entry(bucket.to_string()).or_insert(LocalWindow { window, start: now, current: 0, previous: 0 });
+
+        // A changed limit starts afresh
+        if entry.window != window || now.duration_since(entry.start) >= window * 2 {
+            *entry = LocalWindow { window, start: now, current: 0, previous: 0 };
+        } else if now.duration_since(entry.start) >= window {
+            entry.previous = entry.current;
+            entry.current = 0;
+            entry.start += window;
+        }
+
+        let elapsed = now.duration_since(entry.start);
+        let estimated = entry.previous as f64 * (1.0 - elapsed.as_secs_f64() / window.as_secs_f64()) + entry.current as f64;
+        let reset_after = window - elapsed;
+
+        if estimated + cost as f64 > limit as f64 {
+            return RateLimitDecision {
+                allowed: false,
+                limit,
+                remaining: (limit as f64 - estimated).max(0.0) as u32,
+                reset_after,
+                retry_after: Some(reset_after),
+            };
+        }
+
+        entry.current += cost;
+        RateLimitDecision {
+            allowed: true,
+            limit,
+            remaining: (limit as f64 - estimated - cost as f64).max(0.0) as u32,
+            reset_after,
+            retry_after: None,
+        }
+    }
+}
+
+/// Sent instead of checking a limit that needs Redis while it is unreachable, with the `closed` failure mode.
+pub fn redis_unavailable() -> actix_web::Error {
+    let response = HttpResponse::ServiceUnavailable()
+        .insert_header((RETRY_AFTER, 1))
+        .json(serde_json::json!({
+            "error": "limits_unavailable",
+            "message": "Request limits cannot be checked at the moment, try again later",
+        }));
+    actix_web::error::InternalError::from_response("Redis is unavailable", response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::redis::{create_redis_pool, RedisConfig};
+
+    const ALGORITHMS: [Algorithm; 4] = [
+        Algorithm::FixedWindow,
+        Algorithm::SlidingWindowLog,
+        Algorithm::SlidingWindowCounter,
+        Algorithm::TokenBucket,
+    ];
+
+    fn redis_pool() -> RedisPool {
+        let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
+        create_redis_pool(&redis_url, RedisConfig::from_env())
+    }
+
+    // Each run gets its own buckets, so runs against the same Redis do not see each other
+    fn test_bucket(name: &str) -> String {
+        format!("test:{}:{:016x}", name, rand::random::<u64>())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn concurrent_checks_never_admit_beyond_the_limit() {
+        const LIMIT: u32 = 10;
+        const REQUESTS: usize = 100;
+        let redis_client = redis_pool();
+
+        for algorithm in ALGORITHMS {
+            let bucket = test_bucket(algorithm.as_str());
+            let checks = (0..REQUESTS).map(|_| check(&redis_client, algorithm, &bucket, LIMIT, Duration::from_secs(60), 1));
+            let decisions = futures::future::join_all(checks).await;
+
+            let admitted = decisions
+                .into_iter()
+                .map(|decision| decision.expect("Redis check failed"))
+                .filter(|decision| decision.allowed)
+                .count();
+            assert!(admitted <= LIMIT as usize, "{} admitted {} requests over a limit of {}", algorithm.as_str(), admitted, LIMIT);
+            assert!(admitted > 0, "{} admitted no request", algorithm.as_str());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn concurrent_weighted_checks_never_admit_beyond_the_limit() {
+        const LIMIT: u32 = 20;
+        const COST: u32 = 3;
+        let redis_client = redis_pool();
+
+        for algorithm in ALGORITHMS {
+            let bucket = test_bucket(algorithm.as_str());
+            let checks = (0..50).map(|_| check(&redis_client, algorithm, &bucket, LIMIT, Duration::from_secs(60), COST));
+            let decisions = futures::future::join_all(checks).await;
+
+            let admitted = decisions
+                .into_iter()
+                .map(|decision| decision.expect("Redis check failed"))
+                .filter(|decision| decision.allowed)
+                .count() as u32;
+            assert!(admitted * COST <= LIMIT, "{} admitted {} units over a limit of {}", algorithm.as_str(), admitted * COST, LIMIT);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server at REDIS_URL"]
+    async fn free_requests_are_always_admitted() {
+        let redis_client = redis_pool();
+
+        for algorithm in ALGORITHMS {
+            let bucket = test_bucket(algorithm.as_str());
+            for _ in 0..3 {
+                let decision = check(&redis_client, algorithm, &bucket, 1, Duration::from_secs(60), 0)
+                    .await
+                    .unwrap_or_else(|e| panic!("{} failed a free request: {}", algorithm.as_str(), e));
+                assert!(decision.allowed, "{} rejected a free request", algorithm.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn local_limiter_rejects_requests_over_the_limit() {
+        let limiter = LocalRateLimiter::default();
+
+        for remaining in (0..3).rev() {
+            let decision = limiter.check("key", 3, Duration::from_secs(60), 1);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            assert!(decision.retry_after.is_none());
+        }
+
+        let decision = limiter.check("key", 3, Duration::from_secs(60), 1);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(decision.retry_after.is_some());
+    }
+
+    #[test]
+    fn local_limiter_counts_the_cost_of_requests() {
+        let limiter = LocalRateLimiter::default();
+
+        assert!(limiter.check("key", 10, Duration::from_secs(60), 4).allowed);
+        assert!(limiter.check("key", 10, Duration::from_secs(60), 4).allowed);
+        // Rejected requests are not counted, a cheaper one still fits
+        assert!(!limiter.check("key", 10, Duration::from_secs(60), 4).allowed);
+        assert!(limiter.check("key", 10, Duration::from_secs(60), 2).allowed);
+        assert!(!limiter.check("key", 10, Duration::from_secs(60), 1).allowed);
+        assert!(limiter.check("key", 10, Duration::from_secs(60), 0).allowed);
+    }
+
+    #[test]
+    fn local_limiter_counts_buckets_separately() {
+        let limiter = LocalRateLimiter::default();
+
+        assert!(limiter.check("first", 1, Duration::from_secs(60), 1).allowed);
+        assert!(!limiter.check("first", 1, Duration::from_secs(60), 1).allowed);
+        assert!(limiter.check("second", 1, Duration::from_secs(60), 1).allowed);
+    }
+
+    #[test]
+    fn local_limiter_starts_afresh_when_the_window_changes() {
+        let limiter = LocalRateLimiter::default();
+
+        assert!(limiter.check("key", 1, Duration::from_secs(60), 1).allowed);
+        assert!(!limiter.check("key", 1, Duration::from_secs(60), 1).allowed);
+        assert!(limiter.check("key", 1, Duration::from_secs(30), 1).allowed);
+    }
+
+    #[test]
+    fn local_limiter_admits_again_once_the_window_has_passed() {
+        let limiter = LocalRateLimiter::default();
+        let window = Duration::from_millis(50);
+
+        assert!(limiter.check("key", 1, window, 1).allowed);
+        assert!(!limiter.check("key", 1, window, 1).allowed);
+        std::thread::sleep(window * 2);
+        assert!(limiter.check("key", 1, window, 1).allowed);
+    }
+
+    #[test]
+    fn local_limiter_never_admits_concurrent_requests_beyond_the_limit() {
+        const LIMIT: u32 = 50;
+        let limiter = std::sync::Arc::new(LocalRateLimiter::default());
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let limiter = std::sync::Arc::clone(&limiter);
+                std::thread::spawn(move || {
+                    (0..100)
+                        .filter(|_| limiter.check("key", LIMIT, Duration::from_secs(60), 1).allowed)
+                        .count()
+                })
+            })
+            .collect();
+        let admitted: usize = threads.into_iter().map(|thread| thread.join().unwrap()).sum();
+
+        assert_eq!(admitted, LIMIT as usize);
+    }
+}
@@ -0,0 +1,87 @@
+use std::str::FromStr;
+use actix_web::dev::ServiceRequest;
+use actix_web::HttpMessage;
+use sha2::{Digest, Sha256};
+use crate::models::api_key::ApiIdentity;
+use crate::utils::client_ip::{request_client_ip, ClientIp};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyPart {
+    // The API key, or the client certificate, the request was authenticated with
+    ApiKey,
+    User,
+    // The organization owning the key, personal keys fall back to their user
+    Organization,
+    Ip,
+    // The value of a request header, requests without it fall back to their IP address
+    Header(String),
+}
+
+/// What requests sharing a rate limit bucket have in common, e.g. `api_key` or `user+ip`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyStrategy(Vec<KeyPart>);
+
+impl Default for KeyStrategy {
+    fn default() -> Self {
+        Self(vec![KeyPart::ApiKey])
+    }
+}
+
+impl FromStr for KeyStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parts = value
+            .split('+')
+            .map(|part| match part.trim() {
+                "api_key" => Ok(KeyPart::ApiKey),
+                "user" => Ok(KeyPart::User),
+                "org" => Ok(KeyPart::Organization),
+                "ip" => Ok(KeyPart::Ip),
+                part => match part.strip_prefix("header:") {
+                    Some(name) if !name.trim().is_empty() => Ok(KeyPart::Header(name.trim().to_lowercase())),
+                    _ => Err(format!("Unknown rate limit key '{}'", part)),
+                },
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self(parts))
+    }
+}
+
+impl KeyStrategy {
+    /// Builds the bucket of a request. Must run after `ApiKeyValidator`, which attaches the identity and client IP.
+    pub fn resolve(&self, req: &ServiceRequest) -> String {
+        let client_ip = req.extensions().get::<ClientIp>().map(|ip| ip.0.to_string());
+        // Without the client IP of `ApiKeyValidator`, forwarding headers are still only trusted from known proxies
+        let ip = client_ip
+            .or_else(|| request_client_ip(req.request()).map(|ip| ip.to_string()))
+            .unwrap_or_default();
+        let extensions = req.extensions();
+        let identity = extensions.get::<ApiIdentity>();
+
+        self.0
+            .iter()
+            .map(|part| match (part, identity) {
+                (KeyPart::ApiKey, Some(identity)) => match (identity.key_id, identity.certificate_id) {
+                    (Some(key_id), _) => format!("key:{}", key_id),
+                    (None, Some(certificate_id)) => format!("cert:{}", certificate_id),
+                    (None, None) => format!("user:{}", identity.user_id),
+                },
+                (KeyPart::User, Some(identity)) => format!("user:{}", identity.user_id),
+                (KeyPart::Organization, Some(identity)) => match identity.organization_id {
+                    Some(organization_id) => format!("org:{}", organization_id),
+                    None => format!("user:{}", identity.user_id),
+                },
+                (KeyPart::Header(name), _) => match req.headers().get(name.as_str()) {
+                    // Hashed so arbitrary client input cannot bloat the Redis keys
+                    Some(value) => format!("header:{}:{}", name, hex::encode(&Sha256::digest(value.as_bytes())[..8])),
+                    None => format!("ip:{}", ip),
+                },
+                // Unauthenticated requests can only be told apart by their address
+                _ => format!("ip:{}", ip),
+            })
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+use actix_web::{dev::{Payload, ServiceRequest}, web, Error};
+use async_graphql::parser::parse_query;
+use async_graphql::parser::types::{ExecutableDocument, Selection, SelectionSet};
+use async_graphql::Name;
+use crate::config::request_cost::RequestCostConfig;
+use crate::utils::route_costs::{RouteCostRegistry, DEFAULT_COST};
+
+// Requests to this route also cost the complexity of their GraphQL query
+const GRAPHQL_ROUTE: (&str, &str) = ("POST", "/api/graphql");
+
+/// Units of a request, attached to the request extensions by `ApiKeyValidator`.
+/// They are deducted from rate limits and quotas, and billed.
+#[derive(Clone, Copy)]
+pub struct RequestCost(pub i32);
+
+// Counts every field once per place it is selected, each fragment being counted once and reused
+struct Complexity<'a> {
+    document: &'a ExecutableDocument,
+    fragments: HashMap<&'a str, u64>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'a> Complexity<'a> {
+    fn selection_set(&mut self, selection_set: &'a SelectionSet) -> u64 {
+        selection_set.items.iter().fold(0u64, |complexity, selection| {
+            let selection = match &selection.node {
+                Selection::Field(field) => self.selection_set(&field.node.selection_set.node).saturating_add(1),
+                Selection::InlineFragment(fragment) => self.selection_set(&fragment.node.selection_set.node),
+                Selection::FragmentSpread(spread) => self.fragment(&spread.node.fragment_name.node),
+            };
+            complexity.saturating_add(selection)
+        })
+    }
+
+    fn fragment(&mut self, name: &'a Name) -> u64 {
+        if let Some(complexity) = self.fragments.get(name.as_str()) {
+            return *complexity;
+        }
+        // Cyclic fragments are rejected by the GraphQL validation anyway
+        if !self.visiting.insert(name.as_str()) {
+            return 0;
+        }
+
+        let document = self.document;
+        let complexity = document
+            .fragments
+            .get(name)
+            .map(|fragment| self.selection_set(&fragment.node.selection_set.node))
+            .unwrap_or(0);
+
+        self.visiting.remove(name.as_str());
+        self.fragments.insert(name.as_str(), complexity);
+        complexity
+    }
+}
+
+/// Number of fields selected by the most complex operation of a GraphQL query, `None` when it does not parse.
+pub fn graphql_complexity(query: &str) -> Option<u64> {
+    let document = parse_query(query).ok()?;
+    let mut complexity = Complexity { document: &document, fragments: HashMap::new(), visiting: HashSet::new() };
+    document
+        .operations
+        .iter()
+        .map(|(_, operation)| complexity.selection_set(&operation.node.selection_set.node))
+        .max()
+}
+
+/// Units of a request: the cost of its route, plus one unit per `graphql_fields_per_unit` fields of a GraphQL query.
+/// The body of a GraphQL request is read to measure its query, then put back for the handler.
+pub async fn resolve(
+    req: &mut ServiceRequest,
+    registry: Option<&RouteCostRegistry>,
+    config: &RequestCostConfig,
+) -> Result<i32, Error> {
+    let cost = match registry {
+        Some(registry) => registry
+            .cost(req.method().as_str(), req.path())
+            .await
+            .map_err(|_| actix_web::error::ErrorInternalServerError("Failed to load route costs"))?,
+        None => DEFAULT_COST,
+    };
+    if (req.method().as_str(), req.path()) != GRAPHQL_ROUTE || config.graphql_fields_per_unit == 0 {
+        return Ok(cost);
+    }
+
+    let body = req.extract::<web::Bytes>().await?;
+    req.set_payload(Payload::from(body.clone()));
+
+    // Malformed requests are rejected by the handler, they only cost the route
+    let complexity = serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body.get("query")?.as_str().and_then(graphql_complexity))
+        .unwrap_or(0);
+    let extra = complexity.div_ceil(config.graphql_fields_per_unit as u64);
+    Ok(cost.saturating_add(i32::try_from(extra).unwrap_or(i32::MAX)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_selected_field_counts_once() {
+        assert_eq!(graphql_complexity("{ a }"), Some(1));
+        assert_eq!(graphql_complexity("{ a b { c d { e } } }"), Some(5));
+        assert_eq!(graphql_complexity("{ first: a second: a }"), Some(2));
+        assert_eq!(graphql_complexity("{ a { ... on T { b c } } }"), Some(3));
+    }
+
+    #[test]
+    fn fragments_count_for_every_spread() {
+        let query = "{ a { ...F } b { ...F } } fragment F on T { x y }";
+        assert_eq!(graphql_complexity(query), Some(6));
+        // Unknown fragments select nothing, the GraphQL validation rejects them anyway
+        assert_eq!(graphql_complexity("{ a { ...Missing } }"), Some(1));
+    }
+
+    #[test]
+    fn cyclic_fragments_terminate() {
+        let query = "{ a { ...A } } fragment A on T { x ...B } fragment B on T { y ...A }";
+        assert_eq!(graphql_complexity(query), Some(3));
+
+        let query = "{ a { ...A } } fragment A on T { x ...A }";
+        assert_eq!(graphql_complexity(query), Some(2));
+    }
+
+    #[test]
+    fn nested_fragment_reuse_saturates_instead_of_exploding() {
+        // Each fragment selects the previous one twice, 2^70 fields once expanded
+        let mut query = String::from("{ ...F70 } fragment F0 on T { a }");
+        for level in 1..=70 {
+            query.push_str(&format!(" fragment F{} on T {{ ...F{} ...F{} }}", level, level - 1, level - 1));
+        }
+        assert_eq!(graphql_complexity(&query), Some(u64::MAX));
+    }
+
+    #[test]
+    fn most_complex_operation_is_counted() {
+        assert_eq!(graphql_complexity("query One { a } query Two { a b c } mutation Three { d }"), Some(3));
+    }
+
+    #[test]
+    fn unparsable_queries_have_no_complexity() {
+        assert_eq!(graphql_complexity("{ a "), None);
+        assert_eq!(graphql_complexity("not graphql"), None);
+        assert_eq!(graphql_complexity(""), None);
+    }
+}
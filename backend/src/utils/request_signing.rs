@@ -0,0 +1,187 @@
+use actix_web::http::header::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use crate::config::redis::RedisPool;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const KEY_ID_HEADER: &str = "x-gk-key-id";
+pub const TIMESTAMP_HEADER: &str = "x-gk-timestamp";
+pub const NONCE_HEADER: &str = "x-gk-nonce";
+pub const SIGNATURE_HEADER: &str = "x-gk-signature";
+
+pub struct SignatureHeaders {
+    pub key_id: i32,
+    pub timestamp: i64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+/// Reads the signature headers, returning `Ok(None)` when the request is not signed.
+pub fn parse_headers(headers: &HeaderMap) -> Result<Option<SignatureHeaders>, &'static str> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string());
+
+    let signature = match header(SIGNATURE_HEADER) {
+        Some(signature) => signature,
+        None => return Ok(None),
+    };
+
+    let key_id = header(KEY_ID_HEADER)
+        .and_then(|v| v.parse::<i32>().ok())
+        .ok_or("Missing or invalid x-gk-key-id header")?;
+    let timestamp = header(TIMESTAMP_HEADER)
+        .and_then(|v| v.parse::<i64>().ok())
+        .ok_or("Missing or invalid x-gk-timestamp header")?;
+    let nonce = header(NONCE_HEADER)
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .ok_or("Missing or invalid x-gk-nonce header")?;
+
+    Ok(Some(SignatureHeaders { key_id, timestamp, nonce, signature }))
+}
+
+/// Builds the string clients sign:
+/// `METHOD\nPATH?QUERY\nTIMESTAMP\nNONCE\nhex(SHA-256(body))`
+pub fn canonical_request(method: &str, path_and_query: &str, timestamp: i64, nonce: &str, body: &[u8]) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method.to_uppercase(),
+        path_and_query,
+        timestamp,
+        nonce,
+        hex::encode(Sha256::digest(body))
+    )
+}
+
+// Compares in constant time so a valid signature cannot be guessed from response times
+pub fn verify_signature(secret: &str, canonical_request: &str, signature: &str) -> bool {
+    let expected = match hex::decode(signature) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(canonical_request.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// Remembers a nonce for the given time, returning `false` if it was already used.
+pub async fn register_nonce(redis_client: &RedisPool, key_id: i32, nonce: &str, ttl_secs: u64) -> redis::RedisResult<bool> {
+    let mut redis_conn = redis_client.connection().await?;
+
+    let stored: Option<String> = redis::cmd("SET")
+        .arg(format!("request_signing:nonce:{}:{}", key_id, nonce))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(ttl_secs)
+        .query_async(&mut redis_conn)
+        .await?;
+
+    Ok(stored.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    const SECRET: &str = "signing-secret";
+
+    fn sign(secret: &str, canonical_request: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(canonical_request.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn canonical_request_follows_the_documented_layout() {
+        let canonical = canonical_request("post", "/api/v1/items?page=2", 1700000000, "abc", b"");
+        assert_eq!(
+            canonical,
+            "POST\n/api/v1/items?page=2\n1700000000\nabc\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        let canonical = canonical_request("POST", "/api/graphql", 1700000000, "nonce", br#"{"query":"{ a }"}"#);
+        assert!(verify_signature(SECRET, &canonical, &sign(SECRET, &canonical)));
+        // Hex digits are case insensitive
+        assert!(verify_signature(SECRET, &canonical, &sign(SECRET, &canonical).to_uppercase()));
+    }
+
+    #[test]
+    fn tampered_requests_are_rejected() {
+        let signed = canonical_request("POST", "/api/v1/items?page=2", 1700000000, "nonce", b"{\"amount\":1}");
+        let signature = sign(SECRET, &signed);
+
+        let tampered = [
+            canonical_request("POST", "/api/v1/items?page=2", 1700000000, "nonce", b"{\"amount\":9}"),
+            canonical_request("POST", "/api/v1/items?page=3", 1700000000, "nonce", b"{\"amount\":1}"),
+            canonical_request("POST", "/api/v1/items", 1700000000, "nonce", b"{\"amount\":1}"),
+            canonical_request("POST", "/api/v1/other?page=2", 1700000000, "nonce", b"{\"amount\":1}"),
+            canonical_request("PUT", "/api/v1/items?page=2", 1700000000, "nonce", b"{\"amount\":1}"),
+            canonical_request("POST", "/api/v1/items?page=2", 1700000001, "nonce", b"{\"amount\":1}"),
+            canonical_request("POST", "/api/v1/items?page=2", 1700000000, "other", b"{\"amount\":1}"),
+        ];
+        for canonical in &tampered {
+            assert!(!verify_signature(SECRET, canonical, &signature), "accepted {:?}", canonical);
+        }
+    }
+
+    #[test]
+    fn wrong_secret_or_malformed_signature_is_rejected() {
+        let canonical = canonical_request("GET", "/api/v1/get_random_number", 1700000000, "nonce", b"");
+        let signature = sign(SECRET, &canonical);
+
+        assert!(!verify_signature("another-secret", &canonical, &signature));
+        assert!(!verify_signature(SECRET, &canonical, ""));
+        assert!(!verify_signature(SECRET, &canonical, "not hex"));
+        assert!(!verify_signature(SECRET, &canonical, &signature[..signature.len() - 2]));
+        assert!(!verify_signature(SECRET, &canonical, &format!("{}00", signature)));
+    }
+
+    #[test]
+    fn unsigned_requests_have_no_signature_headers() {
+        assert!(matches!(parse_headers(&HeaderMap::new()), Ok(None)));
+        assert!(matches!(parse_headers(&headers(&[(KEY_ID_HEADER, "1")])), Ok(None)));
+    }
+
+    #[test]
+    fn signature_headers_must_be_complete_and_valid() {
+        let complete = [
+            (SIGNATURE_HEADER, "abcd"),
+            (KEY_ID_HEADER, "12"),
+            (TIMESTAMP_HEADER, "1700000000"),
+            (NONCE_HEADER, "nonce"),
+        ];
+        let parsed = parse_headers(&headers(&complete)).unwrap().unwrap();
+        assert_eq!((parsed.key_id, parsed.timestamp, parsed.nonce.as_str()), (12, 1700000000, "nonce"));
+
+        let long_nonce = "n".repeat(129);
+        for (name, value) in [
+            (KEY_ID_HEADER, "twelve"),
+            (TIMESTAMP_HEADER, "yesterday"),
+            (NONCE_HEADER, ""),
+            (NONCE_HEADER, long_nonce.as_str()),
+        ] {
+            let mut invalid = headers(&complete);
+            invalid.insert(HeaderName::from_static(name), HeaderValue::from_str(value).unwrap());
+            assert!(parse_headers(&invalid).is_err(), "accepted {} = {:?}", name, value);
+        }
+
+        for missing in [KEY_ID_HEADER, TIMESTAMP_HEADER, NONCE_HEADER] {
+            let mut incomplete = headers(&complete);
+            incomplete.remove(missing);
+            assert!(parse_headers(&incomplete).is_err(), "accepted a request without {}", missing);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use sqlx::PgPool;
+use crate::utils::cached::Cached;
+use crate::utils::wildcard;
+
+// Units of a request to a route without a pattern
+pub const DEFAULT_COST: i32 = 1;
+
+#[derive(Clone)]
+pub struct RouteCost {
+    pub route_pattern: String,
+    pub cost: i32,
+}
+
+/// In-memory copy of the route_costs table, so metering a request does not hit the database.
+pub struct RouteCostRegistry {
+    db_pool: PgPool,
+    cache: Cached<Vec<RouteCost>>,
+}
+
+impl RouteCostRegistry {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool, cache: Cached::new() }
+    }
+
+    pub fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    async fn costs(&self) -> Result<Arc<Vec<RouteCost>>, sqlx::Error> {
+        self.cache
+            .get_or_load(|| sqlx::query_as!(RouteCost, "SELECT route_pattern, cost FROM route_costs").fetch_all(&self.db_pool))
+            .await
+    }
+
+    /// Units of a request, from the longest pattern matching its route as the most specific one.
+    pub async fn cost(&self, method: &str, path: &str) -> Result<i32, sqlx::Error> {
+        let route = format!("{} {}", method, path);
+        Ok(self
+            .costs()
+            .await?
+            .iter()
+            .filter(|cost| wildcard::matches(&cost.route_pattern, &route))
+            .max_by_key(|cost| cost.route_pattern.len())
+            .map(|cost| cost.cost)
+            .unwrap_or(DEFAULT_COST))
+    }
+}
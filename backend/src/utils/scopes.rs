@@ -0,0 +1,61 @@
+// Scopes a key can be given by name. Routes declare which one they require with `RequireScope`.
+pub const KNOWN_SCOPES: &[(&str, &str)] = &[
+    ("random:read", "Call GET /api/v1/get_random_number"),
+    ("graphql:query", "Send queries to POST /api/graphql"),
+];
+
+// Grants every scope
+pub const ALL_SCOPES: &str = "*";
+
+// Prefix of the scopes granting access by route, e.g. `route:GET /api/v1/*`
+const ROUTE_SCOPE_PREFIX: &str = "route:";
+
+/// Checks that a scope requested for a key is either known, a resource wildcard like `random:*`, or a route glob.
+pub fn validate_scope(scope: &str) -> Result<(), String> {
+    if scope == ALL_SCOPES || KNOWN_SCOPES.iter().any(|(name, _)| *name == scope) {
+        return Ok(());
+    }
+
+    if let Some(resource) = scope.strip_suffix(":*") {
+        if KNOWN_SCOPES.iter().any(|(name, _)| name.split(':').next() == Some(resource)) {
+            return Ok(());
+        }
+    }
+
+    if let Some(route) = scope.strip_prefix(ROUTE_SCOPE_PREFIX) {
+        return match route.split_once(' ') {
+            Some((method, path)) if !method.is_empty() && path.starts_with('/') => Ok(()),
+            _ => Err(format!("Invalid route scope '{}', expected 'route:<METHOD> <path>'", scope)),
+        };
+    }
+
+    Err(format!("Unknown scope '{}'", scope))
+}
+
+pub fn validate_scopes(scopes: &[String]) -> Result<(), String> {
+    if scopes.is_empty() {
+        return Err("At least one scope is required".to_string());
+    }
+    scopes.iter().try_for_each(|scope| validate_scope(scope))
+}
+
+/// Tells whether the scopes of a key allow a request that requires the scope `required`.
+pub fn grants(scopes: &[String], required: &str, method: &str, path: &str) -> bool {
+    let resource_wildcard = required
+        .split_once(':')
+        .map(|(resource, _)| format!("{}:*", resource));
+
+    scopes.iter().any(|scope| {
+        if scope == ALL_SCOPES || scope == required || Some(scope) == resource_wildcard.as_ref() {
+            return true;
+        }
+
+        match scope.strip_prefix(ROUTE_SCOPE_PREFIX).and_then(|route| route.split_once(' ')) {
+            Some((scope_method, scope_path)) => {
+                (scope_method == "*" || scope_method.eq_ignore_ascii_case(method))
+                    && super::wildcard::matches(scope_path, path)
+            }
+            None => false,
+        }
+    })
+}
@@ -0,0 +1,200 @@
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use crate::utils::cached::Cached;
+use crate::utils::permissions::{has_permission, Permission};
+use crate::utils::wildcard;
+
+pub const DEFAULT_TENANT_SLUG: &str = "default";
+
+// Requests under `/t/{slug}` are resolved to the tenant and routed without the prefix
+const PATH_PREFIX: &str = "/t/";
+
+// Attached to the request extensions by `TenantResolver`
+#[derive(Clone)]
+pub struct Tenant {
+    pub id: i32,
+    pub slug: String,
+    pub hostname: Option<String>,
+    pub rate_limit_max_requests: Option<i32>,
+    pub rate_limit_window_secs: Option<i32>,
+    pub allowed_routes: Vec<String>,
+}
+
+impl Tenant {
+    pub fn allows_route(&self, method: &str, path: &str) -> bool {
+        let route = format!("{} {}", method, path);
+        self.allowed_routes.is_empty() || self.allowed_routes.iter().any(|pattern| wildcard::matches(pattern, &route))
+    }
+
+    /// Rate limit of the tenant, as `(max_requests, window)`, when it overrides the default one.
+    pub fn rate_limit(&self) -> Option<(u32, Duration)> {
+        match (self.rate_limit_max_requests, self.rate_limit_window_secs) {
+            (Some(max_requests), Some(window_secs)) => {
+                Some((max_requests.max(0) as u32, Duration::from_secs(window_secs.max(1) as u64)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// In-memory copy of the tenants table, so resolving the tenant of a request does not hit the database.
+pub struct TenantRegistry {
+    db_pool: PgPool,
+    cache: Cached<Vec<Tenant>>,
+}
+
+impl TenantRegistry {
+    pub fn new(db_pool: PgPool) -> Self {
+        Self { db_pool, cache: Cached::new() }
+    }
+
+    pub fn invalidate(&self) {
+        self.cache.invalidate();
+    }
+
+    async fn tenants(&self) -> Result<Arc<Vec<Tenant>>, sqlx::Error> {
+        self.cache.get_or_load(|| self.load()).await
+    }
+
+    async fn load(&self) -> Result<Vec<Tenant>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT tenants.id, tenants.slug, tenants.hostname, tenants.rate_limit_max_requests, tenants.rate_limit_window_secs,
+                COALESCE(ARRAY_AGG(tenant_routes.route_pattern) FILTER (WHERE tenant_routes.route_pattern IS NOT NULL), '{}') AS "allowed_routes!"
+            FROM tenants
+            LEFT JOIN tenant_routes ON tenant_routes.tenant_id = tenants.id
+            GROUP BY tenants.id
+            "#
+        )
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| Tenant {
+                id: record.id,
+                slug: record.slug,
+                hostname: record.hostname.map(|hostname| hostname.to_lowercase()),
+                rate_limit_max_requests: record.rate_limit_max_requests,
+                rate_limit_window_secs: record.rate_limit_window_secs,
+                allowed_routes: record.allowed_routes,
+            })
+            .collect())
+    }
+
+    /// Resolves the tenant of a request from its path prefix, then its hostname, falling back to the default tenant.
+    /// Also returns the path without the tenant prefix when there was one. `None` means an unknown tenant prefix.
+    pub async fn resolve(&self, host: &str, path: &str) -> Result<Option<(Tenant, Option<String>)>, sqlx::Error> {
+        let tenants = self.tenants().await?;
+
+        if let Some(rest) = path.strip_prefix(PATH_PREFIX) {
+            let (slug, remaining) = match rest.find('/') {
+                Some(index) => (&rest[..index], &rest[index..]),
+                None => (rest, "/"),
+            };
+
+            return Ok(tenants
+                .iter()
+                .find(|tenant| tenant.slug == slug)
+                .map(|tenant| (tenant.clone(), Some(remaining.to_string()))));
+        }
+
+        let hostname = host.split(':').next().unwrap_or_default().to_lowercase();
+        let tenant = tenants
+            .iter()
+            .find(|tenant| tenant.hostname.as_deref() == Some(hostname.as_str()))
+            .or_else(|| tenants.iter().find(|tenant| tenant.slug == DEFAULT_TENANT_SLUG));
+
+        Ok(tenant.map(|tenant| (tenant.clone(), None)))
+    }
+}
+
+pub fn request_tenant_id(req: &HttpRequest) -> Option<i32> {
+    req.extensions().get::<Tenant>().map(|tenant| tenant.id)
+}
+
+fn actor_user_id(req: &HttpRequest) -> Option<i32> {
+    req.extensions()
+        .get::<String>()
+        .and_then(|id| id.parse::<i32>().ok())
+}
+
+async fn can_cross_tenants(db_pool: &PgPool, req: &HttpRequest) -> Result<bool, HttpResponse> {
+    match actor_user_id(req) {
+        Some(user_id) => has_permission(db_pool, user_id, Permission::TenantsManage)
+            .await
+            .map_err(|_| HttpResponse::InternalServerError().finish()),
+        None => Ok(false),
+    }
+}
+
+/// Returns the tenant admin queries are restricted to, or `None` for the cross-tenant view.
+/// Only users with `tenants:manage` can pick another tenant with `?tenant=<slug>` or every tenant with `?tenant=all`.
+pub async fn admin_tenant_scope(db_pool: &PgPool, req: &HttpRequest, requested: Option<&str>) -> Result<Option<i32>, HttpResponse> {
+    let requested = match requested {
+        Some(requested) => requested,
+        None => return Ok(request_tenant_id(req)),
+    };
+
+    if !can_cross_tenants(db_pool, req).await? {
+        return Err(HttpResponse::Forbidden().body("Only superadmins can view other tenants."));
+    }
+
+    if requested == "all" {
+        return Ok(None);
+    }
+
+    let tenant = sqlx::query!("SELECT id FROM tenants WHERE slug = $1", requested)
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    match tenant {
+        Some(tenant) => Ok(Some(tenant.id)),
+        None => Err(HttpResponse::NotFound().body("Unknown tenant.")),
+    }
+}
+
+/// Fails with a 404 when the organization belongs to another tenant than the request, unless the admin can cross tenants.
+pub async fn ensure_organization_in_scope(db_pool: &PgPool, req: &HttpRequest, organization_id: i32) -> Result<(), HttpResponse> {
+    if can_cross_tenants(db_pool, req).await? {
+        return Ok(());
+    }
+
+    let organization = sqlx::query!(
+        "SELECT 1 AS found FROM organizations WHERE id = $1 AND tenant_id = $2",
+        organization_id,
+        request_tenant_id(req)
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    match organization {
+        Some(_) => Ok(()),
+        None => Err(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Fails with a 404 when the user belongs to another tenant than the request, unless the admin can cross tenants.
+pub async fn ensure_user_in_scope(db_pool: &PgPool, req: &HttpRequest, user_id: i32) -> Result<(), HttpResponse> {
+    if can_cross_tenants(db_pool, req).await? {
+        return Ok(());
+    }
+
+    let user = sqlx::query!(
+        "SELECT 1 AS found FROM users WHERE id = $1 AND tenant_id = $2",
+        user_id,
+        request_tenant_id(req)
+    )
+        .fetch_optional(db_pool)
+        .await
+        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+
+    match user {
+        Some(_) => Ok(()),
+        None => Err(HttpResponse::NotFound().finish()),
+    }
+}
@@ -0,0 +1,89 @@
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+#[derive(Clone, Copy)]
+pub enum TokenPurpose {
+    EmailVerification,
+    PasswordReset,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::EmailVerification => "email_verification",
+            TokenPurpose::PasswordReset => "password_reset",
+        }
+    }
+
+    // Lifetime of a token in seconds
+    pub fn ttl(&self) -> f64 {
+        match self {
+            TokenPurpose::EmailVerification => 60.0 * 60.0 * 24.0,
+            TokenPurpose::PasswordReset => 60.0 * 30.0,
+        }
+    }
+}
+
+pub fn generate_token() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::{thread_rng, Rng};
+
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
+
+// Only the hash of a token is stored, so a database leak does not expose usable tokens
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Creates a new token for the given purpose, invalidating any previous unused one.
+pub async fn issue_token(db_pool: &PgPool, user_id: i32, purpose: TokenPurpose) -> Result<String, sqlx::Error> {
+    let token = generate_token();
+
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE user_tokens SET used_at = now() WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+        user_id,
+        purpose.as_str()
+    )
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO user_tokens (user_id, purpose, token_hash, expires_at)
+         VALUES ($1, $2, $3, now() + make_interval(secs => $4))",
+        user_id,
+        purpose.as_str(),
+        hash_token(&token),
+        purpose.ttl()
+    )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(token)
+}
+
+/// Marks the token as used and returns its owner, or `None` if it is unknown, expired or already used.
+pub async fn consume_token<'c, E>(executor: E, token: &str, purpose: TokenPurpose) -> Result<Option<i32>, sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let record = sqlx::query!(
+        "UPDATE user_tokens SET used_at = now()
+         WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > now()
+         RETURNING user_id",
+        hash_token(token),
+        purpose.as_str()
+    )
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(record.map(|r| r.user_id))
+}
@@ -0,0 +1,78 @@
+/// Matches `text` against a pattern where `*` stands for any sequence of characters, including none.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one element
+    let first = parts.next().unwrap_or_default();
+
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.last() {
+        Some(last) => *last,
+        None => return rest.is_empty(), // No wildcard, the whole text must have matched
+    };
+
+    for part in &parts[..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_without_wildcard_match_exactly() {
+        assert!(matches("GET /api/v1/users", "GET /api/v1/users"));
+        assert!(!matches("GET /api/v1/users", "GET /api/v1/users/1"));
+        assert!(!matches("GET /api/v1/users", "GET /api/v1/user"));
+        assert!(matches("", ""));
+        assert!(!matches("", "GET /"));
+    }
+
+    #[test]
+    fn wildcard_matches_any_sequence_including_none() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything at all"));
+        assert!(matches("GET /api/*", "GET /api/"));
+        assert!(matches("GET /api/*", "GET /api/v1/users/1"));
+        assert!(matches("* /api/v1/users", "DELETE /api/v1/users"));
+        assert!(!matches("GET /api/*", "POST /api/v1/users"));
+    }
+
+    #[test]
+    fn multiple_wildcards_match_parts_in_order() {
+        assert!(matches("* /api/*/users/*", "GET /api/v1/users/1"));
+        assert!(matches("https://*.example.com:*", "https://app.example.com:8443"));
+        assert!(matches("a*b*c", "abc"));
+        assert!(matches("a*b*c", "a-b-b-c"));
+        assert!(!matches("a*b*c", "a-c-b"));
+        assert!(matches("a**b", "ab"));
+        assert!(!matches("* /api/*/users/*", "GET /api/v1/accounts/1"));
+    }
+
+    #[test]
+    fn parts_of_the_pattern_cannot_overlap() {
+        // The prefix and suffix cannot share characters of the text
+        assert!(!matches("ab*ba", "aba"));
+        assert!(matches("ab*ba", "abba"));
+        assert!(!matches("a*a", "a"));
+        assert!(!matches("x*ab*b", "xab"));
+        assert!(matches("x*ab*b", "xabb"));
+    }
+
+    #[test]
+    fn wildcard_does_not_allow_a_different_prefix_or_suffix() {
+        assert!(!matches("https://*.example.com", "https://example.com.attacker.net"));
+        assert!(!matches("https://*.example.com", "http://app.example.com"));
+        assert!(matches("https://*.example.com", "https://evil.com.example.com"));
+    }
+}
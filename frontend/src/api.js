@@ -0,0 +1,2 @@
+// Base URL of the GateKeeper backend, `https://` when it serves TLS
+export const API_URL = process.env.VUE_APP_API_URL || 'http://localhost:8080';
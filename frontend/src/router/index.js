@@ -1,12 +1,18 @@
 import { createRouter, createWebHistory } from 'vue-router';
 import Login from '../components/Login.vue';
 import Register from '../components/Register.vue';
+import VerifyEmail from '../components/VerifyEmail.vue';
+import ResetPassword from '../components/ResetPassword.vue';
 import Home from '../views/Home.vue';
 import Admin from '@/views/Admin.vue';
+import { API_URL } from '../api';
 
 const routes = [
   { path: '/login', component: Login },
   { path: '/register', component: Register },
+  // Opened from the links sent by email
+  { path: '/verify_email', component: VerifyEmail },
+  { path: '/reset_password', component: ResetPassword },
   {
     path: '/',
     component: Home,
@@ -35,7 +41,7 @@ router.beforeEach(async (to, from, next) => {
     }
 
     try {
-      const response = await fetch("http://localhost:8080/dashboard/verify", {
+      const response = await fetch(`${API_URL}/dashboard/verify`, {
         method: "GET",
         headers: {
           Authorization: `${authToken}`,